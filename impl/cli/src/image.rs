@@ -0,0 +1,167 @@
+use clap::ArgMatches;
+use namenode::file::FileType;
+use serde::Serialize;
+use shared::NahFSError;
+
+#[derive(Serialize)]
+struct InodeEntry {
+    inode: u64,
+    path: String,
+    file_type: String,
+    owner: String,
+    group: String,
+    permissions: String,
+    storage_policy: Option<String>,
+    block_ids: Vec<u64>,
+    replication: Option<u32>,
+    block_size: Option<u64>,
+}
+
+pub fn process(matches: &ArgMatches, image_matches: &ArgMatches) {
+    let result = match image_matches.subcommand() {
+        ("dump", Some(dump_matches)) => {
+            dump(&matches, &image_matches, &dump_matches)
+        },
+        (cmd, _) => Err(NahFSError::from(format!("unknown subcommand '{}'", cmd))),
+    };
+
+    if let Err(e) = result {
+        println!("{}", e);
+    }
+}
+
+fn dump(_matches: &ArgMatches, _image_matches: &ArgMatches,
+        dump_matches: &ArgMatches) -> Result<(), NahFSError> {
+    // read image
+    let path = dump_matches.value_of("PATH").unwrap();
+    let entries = read_entries(path)?;
+
+    // print inode entries
+    let format = dump_matches.value_of("format").unwrap();
+    let delimiter = dump_matches.value_of("delimiter").unwrap();
+    println!("{}", format_entries(entries, format, delimiter)?);
+    Ok(())
+}
+
+fn format_entries(entries: Vec<InodeEntry>, format: &str,
+        delimiter: &str) -> Result<String, NahFSError> {
+    match format {
+        "json" => serde_json::to_string_pretty(&entries)
+            .map_err(|e| NahFSError::from(e.to_string())),
+        _ => {
+            let mut lines = vec!(vec!("INODE", "PATH", "TYPE", "OWNER",
+                "GROUP", "PERMISSIONS", "STORAGE_POLICY", "BLOCK_IDS",
+                "REPLICATION", "BLOCK_SIZE").join(delimiter));
+
+            for entry in entries {
+                let block_ids: Vec<String> = entry.block_ids.iter()
+                    .map(|x| x.to_string()).collect();
+
+                lines.push(vec!(entry.inode.to_string(), entry.path,
+                    entry.file_type, entry.owner, entry.group,
+                    entry.permissions,
+                    entry.storage_policy.unwrap_or("".to_string()),
+                    block_ids.join(","),
+                    entry.replication.map(|x| x.to_string())
+                        .unwrap_or("".to_string()),
+                    entry.block_size.map(|x| x.to_string())
+                        .unwrap_or("".to_string())).join(delimiter));
+            }
+
+            Ok(lines.join("\n"))
+        },
+    }
+}
+
+fn read_entries(path: &str) -> Result<Vec<InodeEntry>, NahFSError> {
+    let (file_store, _, _, _) = namenode::image::read_image(path)?;
+
+    // compile inode entries
+    let mut entries = Vec::new();
+    for file in file_store.get_files() {
+        let inode = file.get_inode();
+        let storage_policy = file_store.get_storage_policy_id(&inode)
+            .and_then(|id| file_store.get_storage_policy_str(&id))
+            .map(|x| x.to_string());
+
+        let (file_type, block_ids, replication, block_size) =
+                match file.get_file_type() {
+            FileType::Directory => ("DIRECTORY", Vec::new(), None, None),
+            FileType::Regular {blocks, replication, block_size} =>
+                ("FILE", blocks.clone(), Some(*replication),
+                    Some(*block_size)),
+        };
+
+        entries.push(InodeEntry {
+            inode: inode,
+            path: file_store.compute_path(inode),
+            file_type: file_type.to_string(),
+            owner: file.get_owner().to_string(),
+            group: file.get_group().to_string(),
+            permissions: format!("{:o}", file.get_permissions()),
+            storage_policy: storage_policy,
+            block_ids: block_ids,
+            replication: replication,
+            block_size: block_size,
+        });
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn dump() {
+        use super::{format_entries, read_entries};
+        use namenode::block::BlockStore;
+        use namenode::file::FileStore;
+        use namenode::index::Index;
+
+        let path = std::env::temp_dir().join(format!(
+            "nahfs-cli-image-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a", 448, "bob", "staff", true, 0).unwrap();
+        file_store.create("/a/f", 420, "bob", "staff",
+            2, 1024, "client", 0).unwrap();
+        file_store.add_block("/a/f", 5, 0, 0).unwrap();
+        file_store.add_block("/a/f", 6, 0, 0).unwrap();
+        namenode::image::write_image(&file_store,
+            &BlockStore::new(), &Index::new(), 0, path, 0).unwrap();
+
+        // entries are decoded from the image in path order
+        let entries = read_entries(path).unwrap();
+        let paths: Vec<&str> =
+            entries.iter().map(|x| x.path.as_str()).collect();
+        assert_eq!(paths, vec!("/", "/a", "/a/f"));
+
+        let entry = &entries[2];
+        assert_eq!((entry.file_type.as_str(), entry.owner.as_str(),
+            entry.group.as_str(), entry.permissions.as_str()),
+            ("FILE", "bob", "staff", "644"));
+        assert_eq!(entry.block_ids, vec!(5, 6));
+        assert_eq!((entry.replication, entry.block_size),
+            (Some(2), Some(1024)));
+
+        // delimited format prints a header and a line per inode
+        let output = format_entries(entries, "delimited", "|").unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("INODE|PATH|TYPE|"));
+        assert!(lines[2].ends_with("|/a|DIRECTORY|bob|staff|700||||"));
+        assert!(lines[3].ends_with("|/a/f|FILE|bob|staff|644||5,6|2|1024"));
+
+        // json format serializes each field
+        let entries = read_entries(path).unwrap();
+        let output = format_entries(entries, "json", "|").unwrap();
+        let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(json[2]["path"], "/a/f");
+        assert_eq!(json[2]["block_ids"], serde_json::json!([5, 6]));
+        assert_eq!(json[1]["replication"], serde_json::Value::Null);
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -3,8 +3,11 @@ extern crate clap;
 
 use clap::App;
 
+mod image;
 mod index;
 mod inode;
+mod policy;
+mod trash;
 
 fn main() {
     let yaml = load_yaml!("cli.yaml");
@@ -12,10 +15,16 @@ fn main() {
 
     // parse subcommands
     match matches.subcommand() {
+        ("image", Some(image_matches)) =>
+            image::process(&matches, &image_matches),
         ("index", Some(index_matches)) =>
             index::process(&matches, &index_matches),
         ("inode", Some(inode_matches)) =>
             inode::process(&matches, &inode_matches),
+        ("policy", Some(policy_matches)) =>
+            policy::process(&matches, &policy_matches),
+        ("trash", Some(trash_matches)) =>
+            trash::process(&matches, &trash_matches),
         (cmd, _) => println!("unknown subcommand '{}'", cmd),
     }
 }
@@ -0,0 +1,79 @@
+use clap::ArgMatches;
+use shared::NahFSError;
+
+use hdfs_comm::rpc::Client;
+use prost::Message;
+use shared::protos::{StoragePolicyListResponseProto, StoragePolicyListRequestProto, StoragePolicyUnsetResponseProto, StoragePolicyUnsetRequestProto};
+
+pub fn process(matches: &ArgMatches, policy_matches: &ArgMatches) {
+    let result = match policy_matches.subcommand() {
+        ("list", Some(list_matches)) => {
+            list(&matches, &policy_matches, &list_matches)
+        },
+        ("show", Some(show_matches)) => {
+            show(&matches, &policy_matches, &show_matches)
+        },
+        ("unset", Some(unset_matches)) => {
+            unset(&matches, &policy_matches, &unset_matches)
+        },
+        (cmd, _) => Err(NahFSError::from(format!("unknown subcommand '{}'", cmd))),
+    };
+
+    if let Err(e) = result {
+        println!("{}", e);
+    }
+}
+
+fn list(matches: &ArgMatches, _policy_matches: &ArgMatches,
+        _list_matches: &ArgMatches) -> Result<(), NahFSError> {
+    let req_proto = StoragePolicyListRequestProto::default();
+    print_storage_policies(matches, req_proto)
+}
+
+fn show(matches: &ArgMatches, _policy_matches: &ArgMatches,
+        show_matches: &ArgMatches) -> Result<(), NahFSError> {
+    let mut req_proto = StoragePolicyListRequestProto::default();
+    req_proto.id = Some(show_matches.value_of("ID").unwrap().parse::<u32>()?);
+    print_storage_policies(matches, req_proto)
+}
+
+fn print_storage_policies(matches: &ArgMatches,
+        req_proto: StoragePolicyListRequestProto) -> Result<(), NahFSError> {
+    // send StoragePolicyListRequestProto
+    let ip_address = matches.value_of("ip_address").unwrap();
+    let port = matches.value_of("port").unwrap().parse::<u16>()?;
+    let mut client = Client::new(ip_address, port)?;
+    let (_, resp_buf) = client.write_message("io.blackpine.nahfs.protocol.NahFSProtocol", "storagePolicyList", req_proto)?;
+
+    // read response
+    let resp_proto = StoragePolicyListResponseProto
+        ::decode_length_delimited(resp_buf)?;
+
+    // print storage policies and referencing paths
+    for sp_proto in resp_proto.storage_policies.iter() {
+        println!("POLICY: {} '{}'", sp_proto.id, sp_proto.storage_policy);
+        for path in sp_proto.paths.iter() {
+            println!("\t{}", path);
+        }
+    }
+
+    Ok(())
+}
+
+fn unset(matches: &ArgMatches, _policy_matches: &ArgMatches,
+        unset_matches: &ArgMatches) -> Result<(), NahFSError> {
+    let mut req_proto = StoragePolicyUnsetRequestProto::default();
+    req_proto.path = unset_matches.value_of("PATH").unwrap().to_string();
+
+    // send StoragePolicyUnsetRequestProto
+    let ip_address = matches.value_of("ip_address").unwrap();
+    let port = matches.value_of("port").unwrap().parse::<u16>()?;
+    let mut client = Client::new(ip_address, port)?;
+    let (_, resp_buf) = client.write_message("io.blackpine.nahfs.protocol.NahFSProtocol", "storagePolicyUnset", req_proto)?;
+
+    // read response
+    let _ = StoragePolicyUnsetResponseProto
+        ::decode_length_delimited(resp_buf)?;
+
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use clap::ArgMatches;
+use shared::NahFSError;
+
+use hdfs_comm::rpc::Client;
+use prost::Message;
+use shared::protos::{TrashExpungeResponseProto, TrashExpungeRequestProto};
+
+pub fn process(matches: &ArgMatches, trash_matches: &ArgMatches) {
+    let result = match trash_matches.subcommand() {
+        ("expunge", Some(expunge_matches)) => {
+            expunge(&matches, &trash_matches, &expunge_matches)
+        },
+        (cmd, _) => Err(NahFSError::from(format!("unknown subcommand '{}'", cmd))),
+    };
+
+    if let Err(e) = result {
+        println!("{}", e);
+    }
+}
+
+fn expunge(matches: &ArgMatches, _trash_matches: &ArgMatches,
+        _expunge_matches: &ArgMatches) -> Result<(), NahFSError> {
+    let te_proto = TrashExpungeRequestProto::default();
+
+    // send TrashExpungeRequestProto
+    let ip_address = matches.value_of("ip_address").unwrap();
+    let port = matches.value_of("port").unwrap().parse::<u16>()?;
+    let mut client = Client::new(ip_address, port)?;
+    let (_, resp_buf) = client.write_message("io.blackpine.nahfs.protocol.NahFSProtocol", "trashExpunge", te_proto)?;
+
+    // read response
+    let _ = TrashExpungeResponseProto
+        ::decode_length_delimited(resp_buf)?;
+
+    Ok(())
+}
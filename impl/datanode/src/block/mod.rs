@@ -9,11 +9,27 @@ mod processor;
 pub use processor::BlockProcessor;
 
 use std::fs::File;
-use std::io::{BufWriter, Read, SeekFrom, Write};
+use std::io::{BufWriter, ErrorKind, Read, SeekFrom, Write};
 use std::io::prelude::*;
 use std::net::TcpStream;
 use std::time::SystemTime;
 
+pub fn delete_block(block_id: u64,
+        data_directory: &str) -> Result<(), NahFSError> {
+    // remove block and metadata files - where missing files were
+    //  already deleted so repeated invalidations succeed
+    for path in vec!(format!("{}/blk_{}", data_directory, block_id),
+            format!("{}/blk_{}.meta", data_directory, block_id)) {
+        match std::fs::remove_file(&path) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {},
+            result => result?,
+        }
+    }
+
+    debug!("deleted block {} and metadata", block_id);
+    Ok(())
+}
+
 fn read_block(block_id: u64, offset: u64, data_directory: &str,
         buf: &mut [u8]) -> Result<(), NahFSError> {
     // open file
@@ -27,6 +43,27 @@ fn read_block(block_id: u64, offset: u64, data_directory: &str,
     Ok(())
 }
 
+fn read_block_data(block_id: u64,
+        data_directory: &str) -> Result<Vec<u8>, NahFSError> {
+    let mut data = Vec::new();
+    let mut file = File::open(&format!("{}/blk_{}",
+        data_directory, block_id))?;
+    file.read_to_end(&mut data)?;
+
+    Ok(data)
+}
+
+fn read_block_metadata(block_id: u64,
+        data_directory: &str) -> Result<BlockMetadataProto, NahFSError> {
+    let mut buf = Vec::new();
+    let mut file = File::open(format!("{}/blk_{}.meta",
+        data_directory, block_id))?;
+    file.read_to_end(&mut buf)?;
+
+    let bm_proto = BlockMetadataProto::decode_length_delimited(&buf)?;
+    Ok(bm_proto)
+}
+
 fn read_indexed_block(block_id: u64, geohashes: &Vec<u8>, offset: u64,
         data_directory: &str, buf: &mut [u8]) -> Result<(), NahFSError> {
     // read block metadata
@@ -108,6 +145,54 @@ fn read_indexed_block(block_id: u64, geohashes: &Vec<u8>, offset: u64,
     Ok(())
 }
 
+pub fn replicate_block(block_id: u64, replicas: &Vec<DatanodeIdProto>,
+        data_directory: &str) -> Result<(), NahFSError> {
+    // copy stored block and metadata - indexed blocks are sent as is
+    let bm_proto = read_block_metadata(block_id, data_directory)?;
+    let now = SystemTime::now();
+
+    for di_proto in replicas.iter() {
+        // open socket
+        let stream = match TcpStream::connect(&format!("{}:{}",
+                di_proto.ip_addr, di_proto.xfer_port)) {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("replicate block {} to node {} {}:{}: {}",
+                    block_id, di_proto.datanode_uuid,
+                    di_proto.ip_addr, di_proto.xfer_port, e);
+                continue;
+            },
+        };
+
+        // write version, op, and block metadata
+        let mut buf_writer = BufWriter::new(stream);
+        buf_writer.write_u16::<BigEndian>(28)?;
+        buf_writer.write_u8(82)?;
+
+        let mut buf = Vec::new();
+        bm_proto.encode_length_delimited(&mut buf)?;
+        buf_writer.write_all(&buf)?;
+
+        // stream block from disk rather than buffering it in memory
+        let file = File::open(&format!("{}/blk_{}",
+            data_directory, block_id))?;
+        let length = std::io::copy(&mut file.take(bm_proto.length),
+            &mut buf_writer)?;
+        buf_writer.flush()?;
+
+        if length != bm_proto.length {
+            return Err(NahFSError::from(format!("block {} is truncated \
+                at {} of {} bytes", block_id, length, bm_proto.length)));
+        }
+    }
+
+    let elapsed = now.elapsed().unwrap();
+    debug!("replicated block {} in {}.{}s", block_id,
+        elapsed.as_secs(), elapsed.subsec_millis());
+
+    Ok(())
+}
+
 fn transfer_block(data: &Vec<u8>, replicas: &Vec<DatanodeIdProto>,
         bm_proto: &BlockMetadataProto) -> Result<(), NahFSError> {
     let now = SystemTime::now();
@@ -214,3 +299,30 @@ fn write_block(data: &Vec<u8>, bm_proto: &BlockMetadataProto,
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn delete_block() {
+        use super::delete_block;
+
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let data_directory = std::env::temp_dir().join(format!(
+            "nahfs-delete-block-{}", time.as_nanos()));
+        std::fs::create_dir_all(&data_directory).unwrap();
+        let data_directory = data_directory.to_str().unwrap();
+
+        // missing block or metadata files are already deleted
+        std::fs::write(format!("{}/blk_1", data_directory), b"a").unwrap();
+        std::fs::write(format!("{}/blk_2.meta", data_directory), b"a")
+            .unwrap();
+        for block_id in vec!(1, 2, 2) {
+            delete_block(block_id, data_directory).unwrap();
+        }
+
+        assert_eq!(std::fs::read_dir(data_directory).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(data_directory);
+    }
+}
@@ -11,6 +11,7 @@ use std::thread::JoinHandle;
 static INDEXED_MASK: u64 = 18446744004990074880;
 
 pub enum Operation {
+    APPEND,
     INDEX,
     WRITE,
     TRANSFER,
@@ -66,6 +67,14 @@ impl BlockProcessor {
         }
     }
 
+    pub fn add_append(&self, bm_proto: BlockMetadataProto, data: Vec<u8>,
+            replicas: Vec<DatanodeIdProto>)
+            -> Result<(), SendError<BlockOperation>> {
+        let block_op = BlockOperation::new(Operation::APPEND,
+            bm_proto, data, replicas);
+        self.operation_channel.0.send(block_op)
+    }
+
     pub fn add_index(&self, bm_proto: BlockMetadataProto, data: Vec<u8>,
             replicas: Vec<DatanodeIdProto>)
             -> Result<(), SendError<BlockOperation>> {
@@ -87,6 +96,10 @@ impl BlockProcessor {
         super::read_block(block_id, offset, &self.data_directory, buf)
     }
 
+    pub fn read_data(&self, block_id: u64) -> Result<Vec<u8>, NahFSError> {
+        super::read_block_data(block_id, &self.data_directory)
+    }
+
     pub fn read_indexed(&self, block_id: u64, geohashes: &Vec<u8>,
             offset: u64, buf: &mut [u8]) -> Result<(), NahFSError> {
         super::read_indexed_block(block_id,
@@ -153,6 +166,8 @@ fn process_loop(index_store: Arc<RwLock<IndexStore>>,
                 let mut block_op = result.unwrap();
                 let process_result = match (&block_op.operation,
                         &block_op.bm_proto.index) {
+                    (Operation::APPEND, _) =>
+                        append_block(&mut block_op, &data_directory),
                     (Operation::INDEX, _) =>
                         index_block(&index_store, &mut block_op),
                     (Operation::WRITE, _) => 
@@ -176,7 +191,7 @@ fn process_loop(index_store: Arc<RwLock<IndexStore>>,
 
                 // send block operation to next stage
                 let send_result = match block_op.operation {
-                    Operation::INDEX => {
+                    Operation::APPEND | Operation::INDEX => {
                         block_op.operation = Operation::WRITE;
                         operation_sender.send(block_op)
                     },
@@ -202,6 +217,20 @@ fn process_loop(index_store: Arc<RwLock<IndexStore>>,
     }
 }
 
+fn append_block(block_op: &mut BlockOperation,
+        data_directory: &str) -> Result<(), NahFSError> {
+    // prepend existing block data to appended data
+    let mut data = super::read_block_data(
+        block_op.bm_proto.block_id, data_directory)?;
+    data.append(&mut block_op.data);
+
+    // update BlockOperation
+    block_op.bm_proto.length = data.len() as u64;
+    block_op.data = data;
+
+    Ok(())
+}
+
 fn index_block(index_store: &Arc<RwLock<IndexStore>>,
         block_op: &mut BlockOperation) -> Result<(), NahFSError> {
     // parse storage_policy_id and get Indexer
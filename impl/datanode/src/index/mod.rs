@@ -1,7 +1,7 @@
 use hdfs_comm::rpc::Client;
 use prost::Message;
-use regex::Regex;
 use shared::NahFSError;
+use shared::policy::StoragePolicy;
 use shared::protos::{BlockIndexProto, BlockMetadataProto, GetStoragePolicyResponseProto, GetStoragePolicyRequestProto, SpatialIndexProto, TemporalIndexProto};
 
 mod data_format;
@@ -81,50 +81,27 @@ pub struct Indexer {
 
 impl Indexer {
     pub fn from(string: &String) -> Result<Indexer, NahFSError> {
-        // compile regexes
-        let regex = Regex::new(r"(\w+)\((\w+:\w+)?(,\s*\w+:\w+)*\)")?;
-        let fields_regex = Regex::new(r"(\w+):(\w+)")?;
-
-        // check for match
-        if !regex.is_match(string) {
-            return Err(NahFSError::from(format!(
-                "unable to parse storage policy {}", string)));
-        }
-
-        // parse string
-        let caps = regex.captures(string).unwrap();
-
-        let mut map = HashMap::new();
-        for field in fields_regex.captures_iter(string) {
-            map.insert(field[1].to_string(), field[2].to_string());
-        }
-
-        // initialize Indexer
+        // parse storage policy into indexer formats
         let (data_format, spatial_format, temporal_format) =
-                match &caps[1] {
-            "CsvPoint" => (
+                match StoragePolicy::parse(string)? {
+            StoragePolicy::CsvPoint {latitude_index,
+                    longitude_index, timestamp_index} => (
                 DataFormat::Delimited {delimiter: ',' as u8},
                 SpatialFormat::Point {
-                    latitude_index: get_field("latitude_index", &map)?
-                        .parse::<usize>()?,
-                    longitude_index: get_field("longitude_index", &map)?
-                        .parse::<usize>()?,
+                    latitude_index: latitude_index,
+                    longitude_index: longitude_index,
                 },
                 TemporalFormat::Value {
-                    index: get_field("timestamp_index", &map)?
-                        .parse::<usize>()?,
+                    index: timestamp_index,
                 },
             ),
-            "Wkt" => (
+            StoragePolicy::Wkt {spatial_index} => (
                 DataFormat::Delimited {delimiter: '\t' as u8},
                 SpatialFormat::Wkt {
-                    spatial_index: get_field("spatial_index", &map)?
-                        .parse::<usize>()?,
+                    spatial_index: spatial_index,
                 },
                 TemporalFormat::None,
             ),
-            _ => return Err(NahFSError::from(
-                format!("unsupported indexer type {}", &caps[1]))),
         };
 
         Ok(
@@ -244,8 +221,3 @@ impl Indexer {
         Ok((indexed_data, bi_proto))
     }
 }
-
-fn get_field<'a>(field: &str, map: &'a HashMap<String, String>)
-        -> Result<&'a String, String> {
-    map.get(field).ok_or(format!("field '{}' not found", field))
-}
@@ -1,7 +1,7 @@
 use crossbeam_channel::{self, Receiver, Sender};
 use hdfs_comm::rpc::Client;
-use hdfs_comm::protos::hdfs::StorageReportProto;
-use hdfs_comm::protos::hdfs::datanode::{BlockReportResponseProto, BlockReportRequestProto, HeartbeatResponseProto, HeartbeatRequestProto, RegisterDatanodeRequestProto, RegisterDatanodeResponseProto, StorageBlockReportProto};
+use hdfs_comm::protos::hdfs::{DatanodeIdProto, StorageReportProto};
+use hdfs_comm::protos::hdfs::datanode::{block_command_proto, datanode_command_proto, BlockReportResponseProto, BlockReportRequestProto, HeartbeatResponseProto, HeartbeatRequestProto, RegisterDatanodeRequestProto, RegisterDatanodeResponseProto, StorageBlockReportProto};
 use prost::Message;
 use shared::NahFSError;
 use shared::protos::{BlockMetadataProto, IndexReportResponseProto, IndexReportRequestProto};
@@ -11,9 +11,13 @@ use crate::Config;
 use std::fs::File;
 use std::io::Read;
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread::JoinHandle;
 use std::time::{Duration, SystemTime};
 
+static MAX_TRANSFER_COUNT: usize = 4;
+static TRANSFER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
 pub struct NamenodeProtocol {
     config: Config,
     join_handle: Option<JoinHandle<()>>,
@@ -30,22 +34,8 @@ impl NamenodeProtocol {
     }
 
     pub fn start(&mut self) -> Result<(), NahFSError> {
-        // initialize RegisterDatanodeRequestProto
-        let mut rdr_proto = RegisterDatanodeRequestProto::default();
-        rdr_proto.registration =
-            super::to_datanode_registration_proto(&self.config);
-
-        debug!("writing RegistrationDatanodeRequestProto to {}:{}",
-            self.config.namenode_ip_address, self.config.namenode_port);
-
-        // send RegisterDatanodeRequestProto
-        let mut client = Client::new(&self.config.namenode_ip_address,
-            self.config.namenode_port as u16)?;
-        let (_, resp_buf) = client.write_message("org.apache.hadoop.hdfs.server.protocol.DatanodeProtocol", "registerDatanode", rdr_proto)?;
-
-        // read respnose
-        let _ = RegisterDatanodeResponseProto
-            ::decode_length_delimited(resp_buf)?;
+        // register with namenode
+        register(&self.config)?;
 
         // initialize shutdown and tick channels
         let shutdown_receiver = self.shutdown_channel.1.clone();
@@ -72,8 +62,14 @@ impl NamenodeProtocol {
                         }
                     },
                     recv(heartbeat_tick) -> _ => {
-                        if let Err(e) = heartbeat(&config_clone) {
-                            warn!("heartbeat failed: {}", e);
+                        match heartbeat(&config_clone) {
+                            Ok(true) => {
+                                // reregistered -> send full reports
+                                block_timestamp = 0;
+                                index_timestamp = 0;
+                            },
+                            Ok(false) => {},
+                            Err(e) => warn!("heartbeat failed: {}", e),
                         }
                     },
                     recv(index_tick) -> _ => {
@@ -158,7 +154,7 @@ fn block_report(config: &Config, block_timestamp: u64)
     Ok(max_timestamp)
 }
 
-fn heartbeat(config: &Config) -> std::io::Result<()> {
+fn heartbeat(config: &Config) -> Result<bool, NahFSError> {
     // initialize StorageReportProto
     let mut sr_proto = StorageReportProto::default();
     sr_proto.storage_uuid = config.storage_id.to_string();
@@ -208,11 +204,12 @@ fn heartbeat(config: &Config) -> std::io::Result<()> {
     hr_proto.registration = super::to_datanode_registration_proto(config);
     hr_proto.reports.push(sr_proto);
     hr_proto.xceiver_count = Some(1);
+    hr_proto.xmits_in_progress =
+        Some(TRANSFER_COUNT.load(Ordering::SeqCst) as u32);
     hr_proto.cache_capacity = Some(0);
     hr_proto.cache_used = Some(0);
     // TODO - populate rest of HeartbeatRequestProto
-    /*pub xmits_in_progress: ::std::option::Option<u32>,
-    pub failed_volumes: ::std::option::Option<u32>,
+    /*pub failed_volumes: ::std::option::Option<u32>,
     pub volume_failure_summary: ::std::option::Option<VolumeFailureSummaryProto>,
     pub request_full_block_report_lease: ::std::option::Option<bool>,*/
 
@@ -223,9 +220,70 @@ fn heartbeat(config: &Config) -> std::io::Result<()> {
     let (_, resp_buf) = client.write_message("org.apache.hadoop.hdfs.server.protocol.DatanodeProtocol", "heartbeat", hr_proto)?;
 
     // read response
-    let _ = HeartbeatResponseProto::decode_length_delimited(resp_buf)?;
+    let hr_proto = HeartbeatResponseProto::decode_length_delimited(resp_buf)?;
+
+    // process datanode commands
+    let mut registered = false;
+    for dc_proto in hr_proto.cmds.iter() {
+        if dc_proto.cmd_type
+                == datanode_command_proto::Type::RegisterCommand as i32 {
+            // namenode does not recognize datanode -> reregister
+            info!("reregistering with namenode {}:{}",
+                config.namenode_ip_address, config.namenode_port);
+            register(config)?;
+            registered = true;
+            continue;
+        } else if dc_proto.cmd_type
+                != datanode_command_proto::Type::BlockCommand as i32 {
+            continue;
+        }
 
-    Ok(())
+        if let Some(bc_proto) = &dc_proto.blk_cmd {
+            if bc_proto.action
+                    == block_command_proto::Action::Invalidate as i32 {
+                // delete invalidated blocks
+                for b_proto in bc_proto.blocks.iter() {
+                    if let Err(e) = crate::block::delete_block(
+                            b_proto.block_id, &config.data_directory) {
+                        warn!("delete block {}: {}", b_proto.block_id, e);
+                    }
+                }
+            } else if bc_proto.action
+                    == block_command_proto::Action::Transfer as i32 {
+                // copy under-replicated blocks to target datanodes
+                //  - transfers over the limit are rescheduled by the
+                //  namenode once they time out
+                for (b_proto, dis_proto) in bc_proto.blocks.iter()
+                        .zip(bc_proto.targets.iter()) {
+                    let block_id = b_proto.block_id;
+                    if TRANSFER_COUNT.fetch_add(1, Ordering::SeqCst)
+                            >= MAX_TRANSFER_COUNT {
+                        TRANSFER_COUNT.fetch_sub(1, Ordering::SeqCst);
+                        debug!("skipping transfer of block {} - {} \
+                            transfers in progress", block_id,
+                            MAX_TRANSFER_COUNT);
+                        continue;
+                    }
+
+                    let replicas: Vec<DatanodeIdProto> =
+                        dis_proto.datanodes.iter()
+                            .map(|din_proto| din_proto.id.clone()).collect();
+                    let data_directory = config.data_directory.clone();
+
+                    std::thread::spawn(move || {
+                        if let Err(e) = crate::block::replicate_block(
+                                block_id, &replicas, &data_directory) {
+                            warn!("transfer block {}: {}", block_id, e);
+                        }
+
+                        TRANSFER_COUNT.fetch_sub(1, Ordering::SeqCst);
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(registered)
 }
 
 fn index_report(config: &Config, index_timestamp: u64)
@@ -282,6 +340,26 @@ fn index_report(config: &Config, index_timestamp: u64)
     Ok(max_timestamp)
 }
 
+fn register(config: &Config) -> Result<(), NahFSError> {
+    // initialize RegisterDatanodeRequestProto
+    let mut rdr_proto = RegisterDatanodeRequestProto::default();
+    rdr_proto.registration = super::to_datanode_registration_proto(config);
+
+    debug!("writing RegistrationDatanodeRequestProto to {}:{}",
+        config.namenode_ip_address, config.namenode_port);
+
+    // send RegisterDatanodeRequestProto
+    let mut client = Client::new(&config.namenode_ip_address,
+        config.namenode_port as u16)?;
+    let (_, resp_buf) = client.write_message("org.apache.hadoop.hdfs.server.protocol.DatanodeProtocol", "registerDatanode", rdr_proto)?;
+
+    // read respnose
+    let _ = RegisterDatanodeResponseProto
+        ::decode_length_delimited(resp_buf)?;
+
+    Ok(())
+}
+
 fn get_file_timestamp(file: &File) -> u64 {
     match file.metadata() {
         Ok(metadata) => {
@@ -1,26 +1,31 @@
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use comm::StreamHandler;
 use hdfs_comm::block::{BlockInputStream, BlockOutputStream};
-use hdfs_comm::protos::hdfs::{BlockOpResponseProto, ChecksumProto, OpReadBlockProto, OpWriteBlockProto, ReadOpChecksumInfoProto, Status};
+use hdfs_comm::protos::hdfs::op_write_block_proto::BlockConstructionStage;
+use hdfs_comm::protos::hdfs::{BlockOpResponseProto, ChecksumProto, DatanodeIdProto, OpReadBlockProto, OpTransferBlockProto, OpWriteBlockProto, ReadOpChecksumInfoProto, Status};
 use prost::Message;
 use shared::NahFSError;
 use shared::protos::BlockMetadataProto;
 
 use crate::block::BlockProcessor;
 
+use std::collections::HashMap;
 use std::error::Error;
 use std::io::{Read, Write};
 use std::net::TcpStream;
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
 use std::time::SystemTime;
 
 static PROTOCOL_VERSION: u16 = 28;
 static FIRST_BIT_U64: u64 = 9223372036854775808;
 static FIRST_BIT_U8: u8 = 128;
 static MASK_U8: u8 = 127;
+static REPLICA_CAPACITY_BYTES: usize = 268435456;
+static REPLICA_TIMEOUT_SECS: u64 = 3600;
 
 pub struct TransferStreamHandler {
     processor: RwLock<BlockProcessor>,
+    replicas: Mutex<HashMap<u64, (SystemTime, Vec<u8>)>>,
 }
 
 impl TransferStreamHandler {
@@ -28,8 +33,79 @@ impl TransferStreamHandler {
             -> TransferStreamHandler {
         TransferStreamHandler {
             processor: processor,
+            replicas: Mutex::new(HashMap::new()),
         }
     }
+
+    fn retain_replica(&self, block_id: u64, append: bool,
+            mut data: Vec<u8>) {
+        // appended replicas are retained with their existing data
+        if append {
+            let processor = self.processor.read().unwrap();
+            match processor.read_data(block_id) {
+                Ok(mut existing_data) => {
+                    existing_data.append(&mut data);
+                    data = existing_data;
+                },
+                Err(e) => {
+                    warn!("retain appended block {}: {}", block_id, e);
+                    return;
+                },
+            }
+        }
+
+        if data.len() > REPLICA_CAPACITY_BYTES {
+            warn!("retain block {}: {} bytes exceeds replica capacity",
+                block_id, data.len());
+            return;
+        }
+
+        // drop replicas of pipelines which were never recovered
+        let mut replicas = self.replicas.lock().unwrap();
+        replicas.remove(&block_id);
+        replicas.retain(|_, replica| replica.0.elapsed()
+            .map(|duration| duration.as_secs() < REPLICA_TIMEOUT_SECS)
+            .unwrap_or(false));
+
+        // evict the oldest replicas to bound retained memory
+        let mut size: usize =
+            replicas.values().map(|replica| replica.1.len()).sum();
+        while size + data.len() > REPLICA_CAPACITY_BYTES {
+            let oldest_id = *replicas.iter()
+                .min_by_key(|(_, replica)| replica.0)
+                .map(|(block_id, _)| block_id).unwrap();
+            let (_, oldest_data) = replicas.remove(&oldest_id).unwrap();
+            debug!("evicted retained replica of block {}", oldest_id);
+            size -= oldest_data.len();
+        }
+
+        debug!("retained {} bytes of block {}", data.len(), block_id);
+        replicas.insert(block_id, (SystemTime::now(), data));
+    }
+
+    fn take_replica(&self, block_id: u64,
+            length: u64) -> Result<Vec<u8>, NahFSError> {
+        // prefer replicas retained from failed pipelines over
+        //  stored blocks
+        let replica = self.replicas.lock().unwrap().remove(&block_id);
+        let mut data = match replica {
+            Some((_, data)) => data,
+            None => {
+                let processor = self.processor.read().unwrap();
+                processor.read_data(block_id)?
+            },
+        };
+
+        // truncate to the length acknowledged by the client
+        if (data.len() as u64) < length {
+            return Err(NahFSError::from(format!("replica of block {} \
+                holds {} of {} acknowledged bytes", block_id,
+                data.len(), length)));
+        }
+
+        data.truncate(length as usize);
+        Ok(data)
+    }
 }
 
 impl StreamHandler for TransferStreamHandler {
@@ -45,18 +121,8 @@ impl StreamHandler for TransferStreamHandler {
 
             let op_type = stream.read_u8()?;
 
-            // calculate leb128 encoded op proto length
-            let mut length = 0;
-            for i in 0.. {
-                let byte = stream.read_u8()?;
-                length += ((byte & MASK_U8) as u64) << (i * 7);
-
-                if byte & FIRST_BIT_U8 != FIRST_BIT_U8 {
-                    break;
-                }
-            }
-
             // read op proto into buffer
+            let length = read_length(stream)?;
             let mut buf = vec![0u8; length as usize];
             stream.read_exact(&mut buf)?;
  
@@ -71,30 +137,107 @@ impl StreamHandler for TransferStreamHandler {
                     let owb_proto = OpWriteBlockProto::decode(&buf)?;
                     debug!("WriteBlock: {:?}", owb_proto);
 
-                    // send op response
+                    let block_id = owb_proto.header.base_header.block.block_id;
+                    let stage = owb_proto.stage;
+                    let append = stage ==
+                        BlockConstructionStage::PipelineSetupAppend as i32;
+                    let indexed = block_id & FIRST_BIT_U64 == FIRST_BIT_U64;
+
+                    // recovered pipelines resume the replica retained
+                    //  when the previous pipeline failed
                     let mut bor_proto = BlockOpResponseProto::default();
                     bor_proto.status = Status::Success as i32;
 
+                    let mut buf = Vec::new();
+                    if is_recovery_stage(stage) {
+                        match self.take_replica(block_id,
+                                owb_proto.min_bytes_rcvd) {
+                            Ok(data) => buf = data,
+                            Err(e) => {
+                                bor_proto.status = Status::Error as i32;
+                                bor_proto.message = Some(e.to_string());
+                            },
+                        }
+                    }
+
+                    // mirror block to the next datanode in the pipeline
+                    //  - indexed blocks are replicated once indexed
+                    let mut mirror = None;
+                    if bor_proto.status == Status::Success as i32
+                            && !indexed && owb_proto.targets.len() != 0 {
+                        let mut mirror_proto = owb_proto.clone();
+                        let di_proto = mirror_proto.targets.remove(0).id;
+                        match open_write_block(&di_proto, &mirror_proto) {
+                            Ok(mirror_stream) => mirror = Some(
+                                BlockOutputStream::new(mirror_stream,
+                                    owb_proto.min_bytes_rcvd as i64,
+                                    chunk_size_bytes, chunks_per_packet)),
+                            Err(e) => warn!("mirror block {} to node {} \
+                                {}:{}: {}", block_id, di_proto.datanode_uuid,
+                                di_proto.ip_addr, di_proto.xfer_port, e),
+                        }
+                    }
+
+                    // send op response
                     let mut resp_buf = Vec::new();
                     bor_proto.encode_length_delimited(&mut resp_buf)?;
                     stream.write_all(&resp_buf)?;
 
-                    // recv block
-                    let mut buf = Vec::new();
+                    if let Some(message) = bor_proto.message {
+                        return Err(message.into());
+                    }
+
+                    // recv block - retaining received data if the
+                    //  pipeline fails
                     let mut block_stream = BlockInputStream::new(
                         stream.try_clone()?, chunk_size_bytes,
                         chunks_per_packet);
-                    block_stream.read_to_end(&mut buf)?;
+                    let mut packet_buf = vec![0u8;
+                        chunk_size_bytes as usize * chunks_per_packet as usize];
+                    loop {
+                        let count = match block_stream.read(&mut packet_buf) {
+                            Ok(0) => break,
+                            Ok(count) => count,
+                            Err(e) => {
+                                self.retain_replica(block_id, append, buf);
+                                return Err(e.into());
+                            },
+                        };
+
+                        let packet = &packet_buf[..count];
+                        if let Some(Err(e)) = mirror.as_mut()
+                                .map(|mirror| mirror.write_all(packet)) {
+                            warn!("mirror block {}: {}", block_id, e);
+                            mirror = None;
+                        }
+
+                        buf.extend_from_slice(packet);
+                    }
+
                     block_stream.close();
+                    let mirrored = match mirror {
+                        Some(mut mirror) => {
+                            mirror.close();
+                            true
+                        },
+                        None => false,
+                    };
 
                     debug!("read {} bytes into block", buf.len());
 
-                    // create Block struct
-                    let block_id = owb_proto.header.base_header.block.block_id;
+                    // transfered replicas are retained until the
+                    //  recovered pipeline resumes them
+                    if stage == BlockConstructionStage::TransferRbw as i32 {
+                        self.retain_replica(block_id, false, buf);
+                        continue;
+                    }
 
+                    // replicate to targets unless mirrored
                     let mut replicas = Vec::new();
-                    for di_proto in owb_proto.targets {
-                        replicas.push(di_proto.id);
+                    if !mirrored {
+                        for di_proto in owb_proto.targets {
+                            replicas.push(di_proto.id);
+                        }
                     }
  
                     // process block_id
@@ -103,8 +246,9 @@ impl StreamHandler for TransferStreamHandler {
                     bm_proto.length = buf.len() as u64;
 
                     let processor = self.processor.read().unwrap();
-                    let write_result = if block_id & FIRST_BIT_U64
-                            == FIRST_BIT_U64 {
+                    let write_result = if append {
+                        processor.add_append(bm_proto, buf, replicas)
+                    } else if indexed {
                         processor.add_index(bm_proto, buf, replicas)
                     } else {
                         processor.add_write(bm_proto, buf, replicas)
@@ -220,12 +364,100 @@ impl StreamHandler for TransferStreamHandler {
                     debug!("read block {} with length {} in {:?}",
                         block_id, buf.len(), read_duration);
                 },
+                86 => {
+                    // parse transfer block op
+                    let otb_proto = OpTransferBlockProto::decode(&buf)?;
+                    debug!("TransferBlock: {:?}", otb_proto);
+
+                    // transfer replicas retained from failed pipelines
+                    //  as being written and stored blocks as finalized
+                    let block_id = otb_proto.header.base_header.block.block_id;
+                    let replica = self.replicas.lock().unwrap()
+                        .get(&block_id).map(|replica| replica.1.clone());
+                    let (stage, data_result) = match replica {
+                        Some(data) =>
+                            (BlockConstructionStage::TransferRbw, Ok(data)),
+                        None => {
+                            let processor = self.processor.read().unwrap();
+                            (BlockConstructionStage::TransferFinalized,
+                                processor.read_data(block_id))
+                        },
+                    };
+
+                    let mut owb_proto = OpWriteBlockProto::default();
+                    owb_proto.header = otb_proto.header.clone();
+                    owb_proto.stage = stage as i32;
+                    owb_proto.pipeline_size = 1;
+
+                    // attempt every target - reporting each failure
+                    let mut errors = Vec::new();
+                    match data_result {
+                        Ok(data) => for din_proto in otb_proto.targets.iter() {
+                            let di_proto = &din_proto.id;
+                            if let Err(e) = transfer_block(di_proto,
+                                    &owb_proto, &data, chunk_size_bytes,
+                                    chunks_per_packet) {
+                                errors.push(format!("transfer block {} to \
+                                    node {} {}:{}: {}", block_id,
+                                    di_proto.datanode_uuid, di_proto.ip_addr,
+                                    di_proto.xfer_port, e));
+                            }
+                        },
+                        Err(e) => errors.push(e.to_string()),
+                    }
+
+                    let mut bor_proto = BlockOpResponseProto::default();
+                    bor_proto.status = Status::Success as i32;
+                    if !errors.is_empty() {
+                        warn!("{}", errors.join("; "));
+                        bor_proto.status = Status::Error as i32;
+                        bor_proto.message = Some(errors.join("; "));
+                    }
+
+                    // send op response
+                    let mut resp_buf = Vec::new();
+                    bor_proto.encode_length_delimited(&mut resp_buf)?;
+                    stream.write_all(&resp_buf)?;
+                },
                 _ => unimplemented!(),
             }
         }
     }
 }
 
+fn is_recovery_stage(stage: i32) -> bool {
+    stage == BlockConstructionStage::PipelineSetupAppendRecovery as i32
+        || stage == BlockConstructionStage
+            ::PipelineSetupStreamingRecovery as i32
+        || stage == BlockConstructionStage::PipelineCloseRecovery as i32
+}
+
+fn open_write_block(di_proto: &DatanodeIdProto,
+        owb_proto: &OpWriteBlockProto) -> Result<TcpStream, Box<dyn Error>> {
+    // write version, op, and write block op
+    let mut stream = TcpStream::connect(&format!("{}:{}",
+        di_proto.ip_addr, di_proto.xfer_port))?;
+
+    let mut buf = Vec::new();
+    buf.write_u16::<BigEndian>(PROTOCOL_VERSION)?;
+    buf.write_u8(80)?;
+    owb_proto.encode_length_delimited(&mut buf)?;
+    stream.write_all(&buf)?;
+
+    // read op response
+    let length = read_length(&mut stream)?;
+    let mut buf = vec![0u8; length as usize];
+    stream.read_exact(&mut buf)?;
+
+    let bor_proto = BlockOpResponseProto::decode(&buf)?;
+    if bor_proto.status != Status::Success as i32 {
+        return Err(format!("write block failed with status {}: {}",
+            bor_proto.status, bor_proto.message.unwrap_or_default()).into());
+    }
+
+    Ok(stream)
+}
+
 fn read_block(block_id: u64, offset: u64, buf: &mut Vec<u8>,
         processor: &BlockProcessor) -> Result<(), NahFSError> {
     if block_id & FIRST_BIT_U64 == FIRST_BIT_U64 {
@@ -239,3 +471,245 @@ fn read_block(block_id: u64, offset: u64, buf: &mut Vec<u8>,
         processor.read(block_id, offset, buf)
     }
 }
+
+fn transfer_block(di_proto: &DatanodeIdProto,
+        owb_proto: &OpWriteBlockProto, data: &[u8], chunk_size_bytes: u32,
+        chunks_per_packet: u32) -> Result<(), Box<dyn Error>> {
+    let stream = open_write_block(di_proto, owb_proto)?;
+    let mut block_stream = BlockOutputStream::new(stream, 0,
+        chunk_size_bytes, chunks_per_packet);
+    block_stream.write_all(data)?;
+    block_stream.close();
+    Ok(())
+}
+
+fn read_length(stream: &mut TcpStream) -> std::io::Result<u64> {
+    // calculate leb128 encoded length
+    let mut length = 0;
+    for i in 0.. {
+        let byte = stream.read_u8()?;
+        length += ((byte & MASK_U8) as u64) << (i * 7);
+
+        if byte & FIRST_BIT_U8 != FIRST_BIT_U8 {
+            break;
+        }
+    }
+
+    Ok(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, WriteBytesExt};
+    use comm::StreamHandler;
+    use hdfs_comm::block::BlockOutputStream;
+    use hdfs_comm::protos::hdfs::op_write_block_proto::BlockConstructionStage;
+    use hdfs_comm::protos::hdfs::{BlockOpResponseProto, DatanodeIdProto, DatanodeInfoProto, OpTransferBlockProto, OpWriteBlockProto, Status};
+    use prost::Message;
+
+    use super::TransferStreamHandler;
+    use crate::block::BlockProcessor;
+    use crate::index::IndexStore;
+
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::sync::{Arc, RwLock};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    static PACKET_LENGTH: usize = 64512;
+
+    pub struct TestDatanode {
+        pub data_directory: String,
+        pub di_proto: DatanodeIdProto,
+        pub handler: Arc<TransferStreamHandler>,
+    }
+
+    impl TestDatanode {
+        pub fn info(&self) -> DatanodeInfoProto {
+            let mut din_proto = DatanodeInfoProto::default();
+            din_proto.id = self.di_proto.clone();
+            din_proto
+        }
+
+        pub fn wait_for_block(&self, block_id: u64, data: &[u8]) {
+            // blocks are written asynchronously by the processor
+            let path = format!("{}/blk_{}", self.data_directory, block_id);
+            for _ in 0..100 {
+                if let Ok(block_data) = std::fs::read(&path) {
+                    if block_data.len() == data.len() {
+                        assert!(block_data == data);
+                        return;
+                    }
+                }
+
+                std::thread::sleep(Duration::from_millis(100));
+            }
+
+            panic!("block {} was never written to {}",
+                block_id, self.di_proto.datanode_uuid);
+        }
+
+        pub fn wait_for_replica(&self, block_id: u64, length: usize) {
+            // replicas are retained once the failed stream is detected
+            for _ in 0..100 {
+                if let Some(replica) = self.handler.replicas
+                        .lock().unwrap().get(&block_id) {
+                    if replica.1.len() >= length {
+                        return;
+                    }
+                }
+
+                std::thread::sleep(Duration::from_millis(100));
+            }
+
+            panic!("replica of block {} was never retained by {}",
+                block_id, self.di_proto.datanode_uuid);
+        }
+    }
+
+    pub fn start_datanode(id: &str) -> TestDatanode {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let data_directory = std::env::temp_dir().join(format!(
+            "nahfs-datanode-{}-{}", id, time.as_nanos()));
+        std::fs::create_dir_all(&data_directory).unwrap();
+        let data_directory = data_directory.to_str().unwrap().to_string();
+
+        let index_store = Arc::new(RwLock::new(
+            IndexStore::new("127.0.0.1".to_string(), 0)));
+        let mut processor = BlockProcessor::new(index_store, 2, 8,
+            data_directory.clone(), id.to_string(),
+            "127.0.0.1".to_string(), 0);
+        processor.start().unwrap();
+
+        // handle each connection on a separate thread
+        let handler = Arc::new(
+            TransferStreamHandler::new(RwLock::new(processor)));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut di_proto = DatanodeIdProto::default();
+        di_proto.ip_addr = "127.0.0.1".to_string();
+        di_proto.datanode_uuid = id.to_string();
+        di_proto.xfer_port = listener.local_addr().unwrap().port() as u32;
+
+        let listener_handler = handler.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (handler, mut stream) =
+                    (listener_handler.clone(), stream.unwrap());
+                std::thread::spawn(move || {
+                    let _ = handler.process(&mut stream);
+                });
+            }
+        });
+
+        TestDatanode {
+            data_directory: data_directory,
+            di_proto: di_proto,
+            handler: handler,
+        }
+    }
+
+    pub fn write_op<T: Message>(di_proto: &DatanodeIdProto,
+            op_type: u8, message: &T) -> TcpStream {
+        let mut stream = TcpStream::connect(&format!("{}:{}",
+            di_proto.ip_addr, di_proto.xfer_port)).unwrap();
+
+        let mut buf = Vec::new();
+        buf.write_u16::<BigEndian>(super::PROTOCOL_VERSION).unwrap();
+        buf.write_u8(op_type).unwrap();
+        message.encode_length_delimited(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+        stream
+    }
+
+    #[test]
+    fn pipeline_recovery() {
+        let data: Vec<u8> = (0..PACKET_LENGTH * 3)
+            .map(|i| (i % 251) as u8).collect();
+        let (a, b, c) = (start_datanode("a"),
+            start_datanode("b"), start_datanode("c"));
+
+        let transfer_block = |otb_proto: &OpTransferBlockProto| {
+            let mut stream = write_op(&b.di_proto, 86, otb_proto);
+            let mut buf = Vec::new();
+            stream.shutdown(Shutdown::Write).unwrap();
+            stream.read_to_end(&mut buf).unwrap();
+            BlockOpResponseProto::decode_length_delimited(&buf)
+                .unwrap().status
+        };
+
+        let write_block = |stage: BlockConstructionStage,
+                targets: &[&TestDatanode], min_bytes_rcvd: usize| {
+            let mut owb_proto = OpWriteBlockProto::default();
+            owb_proto.header.base_header.block.block_id = 1;
+            owb_proto.header.client_name = "client".to_string();
+            owb_proto.targets = targets[1..].iter()
+                .map(|datanode| datanode.info()).collect();
+            owb_proto.stage = stage as i32;
+            owb_proto.pipeline_size = targets.len() as u32;
+            owb_proto.min_bytes_rcvd = min_bytes_rcvd as u64;
+
+            let stream = super::open_write_block(
+                &targets[0].di_proto, &owb_proto).unwrap();
+            (stream.try_clone().unwrap(), BlockOutputStream::new(stream,
+                min_bytes_rcvd as i64, 512, 126))
+        };
+
+        // the first datanode fails while the block is being written
+        //  - the stream is mirrored through the pipeline
+        let (stream, mut block_stream) = write_block(
+            BlockConstructionStage::PipelineSetupCreate, &[&a, &b], 0);
+        block_stream.write_all(&data[..PACKET_LENGTH * 2]).unwrap();
+        block_stream.flush().unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+
+        stream.shutdown(Shutdown::Both).unwrap();
+        drop(block_stream);
+
+        // the surviving datanode retains its partial replica
+        b.wait_for_replica(1, PACKET_LENGTH);
+
+        // the client replaces the failed datanode - transferring the
+        //  acknowledged replica to the additional datanode
+        let mut otb_proto = OpTransferBlockProto::default();
+        otb_proto.header.base_header.block.block_id = 1;
+        otb_proto.header.base_header.block.num_bytes =
+            Some(PACKET_LENGTH as u64);
+        otb_proto.header.client_name = "client".to_string();
+        otb_proto.targets.push(c.info());
+        assert_eq!(transfer_block(&otb_proto), Status::Success as i32);
+        c.wait_for_replica(1, PACKET_LENGTH);
+
+        // unreachable targets fail the transfer without skipping others
+        let mut unreachable = c.info();
+        unreachable.id.xfer_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap().local_addr().unwrap().port() as u32;
+        otb_proto.targets.insert(0, unreachable);
+        c.handler.replicas.lock().unwrap().clear();
+        assert_eq!(transfer_block(&otb_proto), Status::Error as i32);
+        c.wait_for_replica(1, PACKET_LENGTH);
+
+
+        // the recovered pipeline resumes from the acknowledged length
+        //  - without losing data written to the failed pipeline
+        let (_, mut block_stream) = write_block(BlockConstructionStage
+            ::PipelineSetupStreamingRecovery, &[&b, &c], PACKET_LENGTH);
+        block_stream.write_all(&data[PACKET_LENGTH..]).unwrap();
+        block_stream.close();
+
+        b.wait_for_block(1, &data);
+        c.wait_for_block(1, &data);
+
+        // recovery fails when no replica holds the acknowledged data
+        let mut owb_proto = OpWriteBlockProto::default();
+        owb_proto.header.base_header.block.block_id = 2;
+        owb_proto.stage =
+            BlockConstructionStage::PipelineCloseRecovery as i32;
+        owb_proto.pipeline_size = 1;
+        owb_proto.min_bytes_rcvd = PACKET_LENGTH as u64;
+        assert!(super::open_write_block(&b.di_proto, &owb_proto).is_err());
+
+        for datanode in vec!(a, b, c) {
+            let _ = std::fs::remove_dir_all(&datanode.data_directory);
+        }
+    }
+}
@@ -1,5 +1,8 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 
+use std::collections::{HashMap, HashSet};
+
+#[derive(Deserialize, Serialize)]
 pub struct Block {
     pub id: u64,
     pub generation_stamp: u64,
@@ -8,14 +11,18 @@ pub struct Block {
     pub storage_ids: Vec<String>,
 }
 
+#[derive(Deserialize, Serialize)]
 pub struct BlockStore {
     map: HashMap<u64, Block>,
+    #[serde(skip)]
+    pipeline_stamps: HashMap<u64, u64>,
 }
 
 impl BlockStore {
     pub fn new() -> BlockStore {
         BlockStore {
             map: HashMap::new(),
+            pipeline_stamps: HashMap::new(),
         }
     }
 
@@ -23,8 +30,99 @@ impl BlockStore {
         self.map.get(id)
     }
 
+    pub fn get_block_ids(&self) -> Vec<u64> {
+        self.map.keys().map(|x| *x).collect()
+    }
+
+    pub fn get_pipeline_stamp(&self, id: &u64) -> Option<u64> {
+        self.pipeline_stamps.get(id).map(|x| *x)
+    }
+
+    pub fn remove(&mut self, id: &u64) -> Option<Block> {
+        self.pipeline_stamps.remove(id);
+        self.map.remove(id)
+    }
+
+    pub fn remove_location(&mut self, id: &u64, datanode_id: &str) {
+        if let Some(block) = self.map.get_mut(id) {
+            if let Some(index) = block.locations.iter()
+                    .position(|value| value == datanode_id) {
+                debug!("removed location {} from block '{}'",
+                    datanode_id, block.id);
+                block.locations.remove(index);
+                block.storage_ids.remove(index);
+            }
+        }
+    }
+
+    pub fn remove_unreported(&mut self, datanode_id: &str,
+            block_ids: &HashSet<u64>) -> Vec<u64> {
+        let mut removed_ids = Vec::new();
+        for block in self.map.values_mut() {
+            if block_ids.contains(&block.id) {
+                continue;
+            }
+
+            // remove datanode from block locations
+            if let Some(index) = block.locations.iter()
+                    .position(|value| value == datanode_id) {
+                debug!("removed unreported location {} from block '{}'",
+                    datanode_id, block.id);
+                block.locations.remove(index);
+                block.storage_ids.remove(index);
+                removed_ids.push(block.id);
+            }
+        }
+
+        removed_ids
+    }
+
+    pub fn set_generation_stamp(&mut self, id: u64,
+            generation_stamp: u64, length: Option<u64>) {
+        // create block if it has not been reported (ex. journal replay)
+        let block = self.map.entry(id).or_insert(
+            Block {
+                id: id,
+                generation_stamp: generation_stamp,
+                length: 0,
+                locations: Vec::new(),
+                storage_ids: Vec::new(),
+            });
+
+        block.generation_stamp = generation_stamp;
+        if let Some(length) = length {
+            block.length = length;
+        }
+    }
+
+    pub fn set_pipeline_stamp(&mut self, id: u64, generation_stamp: u64) {
+        self.pipeline_stamps.insert(id, generation_stamp);
+    }
+
+    pub fn update_pipeline(&mut self, id: u64, generation_stamp: u64,
+            length: u64, datanode_ids: &Vec<String>) -> Vec<String> {
+        // commit the recovered generation stamp and length
+        self.pipeline_stamps.remove(&id);
+        self.set_generation_stamp(id, generation_stamp, Some(length));
+
+        // drop locations of datanodes which left the pipeline
+        let block = self.map.get_mut(&id).unwrap();
+        let mut removed_ids = Vec::new();
+        let mut index = 0;
+        while index < block.locations.len() {
+            if datanode_ids.contains(&block.locations[index]) {
+                index += 1;
+            } else {
+                removed_ids.push(block.locations.remove(index));
+                block.storage_ids.remove(index);
+            }
+        }
+
+        removed_ids
+    }
+
     pub fn update(&mut self, id: u64, generation_stamp: u64,
-            length: u64, datanode_id: &str, storage_id: &str) {
+            length: u64, datanode_id: &str, storage_id: &str) -> bool {
         // get block, creating if it doesn't exist
         let mut block = self.map.entry(id).or_insert(
             Block {
@@ -35,10 +133,11 @@ impl BlockStore {
                 storage_ids: Vec::new(),
             });
  
-        // return if datanode is already registered
+        // if datanode is already registered -> update length
         for value in block.locations.iter() {
             if value == &datanode_id {
-                return;
+                block.length = length;
+                return false;
             }
         }
 
@@ -48,5 +147,6 @@ impl BlockStore {
         block.locations.push(datanode_id.to_owned());
         block.storage_ids.push(storage_id.to_owned());
         block.length = length;
+        true
     }
 }
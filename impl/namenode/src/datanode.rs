@@ -1,4 +1,7 @@
+use hdfs_comm::protos::hdfs::DatanodeInfoProto;
+
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub struct Datanode {
     pub id: String,
@@ -6,6 +9,18 @@ pub struct Datanode {
     pub xfer_port: u32,
     pub storage_ids: Vec<String>,
     pub states: Vec<DatanodeState>,
+    pub invalid_blocks: Vec<u64>,
+    pub transfer_blocks: Vec<(u64, Vec<String>)>,
+    pub full_report_pending: bool,
+    pub registration_timestamp: u64,
+}
+
+impl Datanode {
+    pub fn get_last_seen(&self) -> u64 {
+        // datanodes which never heartbeat were last seen at registration
+        self.states.last().map(|state| state.update_timestamp)
+            .unwrap_or(self.registration_timestamp)
+    }
 }
 
 pub struct DatanodeState {
@@ -19,6 +34,7 @@ pub struct DatanodeState {
 pub struct DatanodeStore {
     state_queue_length: usize,
     map: HashMap<String, Datanode>,
+    infos: Arc<HashMap<String, DatanodeInfoProto>>,
 }
 
 impl DatanodeStore {
@@ -26,6 +42,7 @@ impl DatanodeStore {
         DatanodeStore {
             state_queue_length: state_queue_length,
             map: HashMap::new(),
+            infos: Arc::new(HashMap::new()),
         }
     }
 
@@ -41,10 +58,32 @@ impl DatanodeStore {
         }
     }
 
+    pub fn add_invalid_block(&mut self, id: &str, block_id: u64) {
+        if let Some(datanode) = self.map.get_mut(id) {
+            if !datanode.invalid_blocks.contains(&block_id) {
+                datanode.invalid_blocks.push(block_id);
+            }
+        }
+    }
+
+    pub fn add_transfer_block(&mut self, id: &str,
+            block_id: u64, targets: Vec<String>) {
+        if let Some(datanode) = self.map.get_mut(id) {
+            datanode.transfer_blocks.push((block_id, targets));
+        }
+    }
+
     pub fn register(&mut self, id: String,
-            ip_address: String, xfer_port: u32) {
+            ip_address: String, xfer_port: u32, time: u64) {
         info!("registering datanode '{}' as {}:{}", id, ip_address, xfer_port);
 
+        // reregistering datanodes keep their pending commands
+        let (invalid_blocks, transfer_blocks) = match self.map.remove(&id) {
+            Some(datanode) => (datanode.invalid_blocks,
+                datanode.transfer_blocks),
+            None => (Vec::new(), Vec::new()),
+        };
+
         let id_clone = id.clone();
         let datanode = Datanode {
             id: id,
@@ -52,6 +91,10 @@ impl DatanodeStore {
             xfer_port: xfer_port,
             storage_ids: Vec::new(),
             states: Vec::new(),
+            invalid_blocks: invalid_blocks,
+            transfer_blocks: transfer_blocks,
+            full_report_pending: true,
+            registration_timestamp: time,
         };
         self.map.insert(id_clone, datanode);
     }
@@ -64,6 +107,49 @@ impl DatanodeStore {
         self.map.values().collect()
     }
 
+    pub fn get_infos(&self) -> Arc<HashMap<String, DatanodeInfoProto>> {
+        self.infos.clone()
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<Datanode> {
+        info!("removing datanode '{}'", id);
+        Arc::make_mut(&mut self.infos).remove(id);
+        self.map.remove(id)
+    }
+
+    pub fn set_info(&mut self, id: &str, din_proto: DatanodeInfoProto) {
+        // copy on write - readers keep the snapshot they retrieved
+        if self.map.contains_key(id) {
+            Arc::make_mut(&mut self.infos).insert(id.to_string(), din_proto);
+        }
+    }
+
+    pub fn take_full_report_pending(&mut self, id: &str) -> bool {
+        match self.map.get_mut(id) {
+            Some(datanode) => {
+                let full_report_pending = datanode.full_report_pending;
+                datanode.full_report_pending = false;
+                full_report_pending
+            },
+            None => false,
+        }
+    }
+
+    pub fn take_invalid_blocks(&mut self, id: &str) -> Vec<u64> {
+        match self.map.get_mut(id) {
+            Some(datanode) => datanode.invalid_blocks.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn take_transfer_blocks(&mut self,
+            id: &str) -> Vec<(u64, Vec<String>)> {
+        match self.map.get_mut(id) {
+            Some(datanode) => datanode.transfer_blocks.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
     // TODO - function no longer used
     /*pub fn get_random_ids(&self, count: u32) -> Vec<&String> {
         let mut ids = Vec::new();
@@ -109,3 +195,32 @@ impl DatanodeStore {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn register() {
+        use super::DatanodeStore;
+
+        let mut datanode_store = DatanodeStore::new(5);
+        datanode_store.register("a".to_string(),
+            "127.0.0.1".to_string(), 50010, 0);
+        datanode_store.add_invalid_block("a", 1);
+        datanode_store.add_transfer_block("a", 2, vec!("b".to_string()));
+        datanode_store.update("a", None, None, 10, None, None);
+        assert_eq!(datanode_store.get_datanode("a").unwrap()
+            .get_last_seen(), 10);
+
+        // reregistering keeps pending commands but resets state
+        datanode_store.register("a".to_string(),
+            "127.0.0.2".to_string(), 50010, 20);
+        let datanode = datanode_store.get_datanode("a").unwrap();
+        assert_eq!(datanode.ip_address, "127.0.0.2");
+        assert_eq!(datanode.get_last_seen(), 20);
+        assert!(datanode.full_report_pending);
+
+        assert_eq!(datanode_store.take_invalid_blocks("a"), vec!(1));
+        assert_eq!(datanode_store.take_transfer_blocks("a"),
+            vec!((2, vec!("b".to_string()))));
+    }
+}
@@ -0,0 +1,245 @@
+use serde::{Deserialize, Serialize};
+use shared::NahFSError;
+
+use crate::file::{File, FileType};
+use crate::file::permission::ALL;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum AclScope {
+    Access,
+    Default,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum AclType {
+    User,
+    Group,
+    Mask,
+    Other,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AclEntry {
+    pub scope: AclScope,
+    pub acl_type: AclType,
+    pub name: Option<String>,
+    pub permissions: u32,
+}
+
+impl AclEntry {
+    pub fn new(scope: AclScope, acl_type: AclType,
+            name: Option<String>, permissions: u32) -> AclEntry {
+        AclEntry {
+            scope: scope,
+            acl_type: acl_type,
+            name: name,
+            permissions: permissions,
+        }
+    }
+
+    fn is_base(&self, scope: AclScope, acl_type: AclType) -> bool {
+        self.scope == scope && self.acl_type == acl_type && self.name.is_none()
+    }
+
+    fn is_named(&self) -> bool {
+        self.name.is_some()
+    }
+
+    fn matches(&self, entry: &AclEntry) -> bool {
+        self.scope == entry.scope && self.acl_type == entry.acl_type
+            && self.name == entry.name
+    }
+}
+
+pub fn inherit(parent: &File, permissions: u32,
+        is_directory: bool) -> Option<(u32, Vec<AclEntry>)> {
+    let defaults: Vec<&AclEntry> = parent.get_acl().iter()
+        .filter(|entry| entry.scope == AclScope::Default).collect();
+    if defaults.is_empty() {
+        return None;
+    }
+
+    // access entries are copied from the parent default entries with
+    //  base entries restricted by the requested permissions
+    let has_mask = defaults.iter()
+        .any(|entry| entry.is_base(AclScope::Default, AclType::Mask));
+    let mut entries = Vec::new();
+    for entry in defaults {
+        let mut access_entry = entry.clone();
+        access_entry.scope = AclScope::Access;
+        access_entry.permissions &= match (entry.acl_type, entry.is_named()) {
+            (AclType::User, false) => (permissions >> 6) & ALL,
+            (AclType::Group, false) if !has_mask => (permissions >> 3) & ALL,
+            (AclType::Mask, _) => (permissions >> 3) & ALL,
+            (AclType::Other, _) => permissions & ALL,
+            _ => ALL,
+        };
+
+        entries.push(access_entry);
+
+        // directories inherit the default entries as well
+        if is_directory {
+            entries.push(entry.clone());
+        }
+    }
+
+    compact(permissions, entries, is_directory).ok()
+}
+
+pub fn modify(file: &File,
+        spec: &Vec<AclEntry>) -> Result<(u32, Vec<AclEntry>), NahFSError> {
+    // replace matching entries and add new entries
+    let mut entries = expand(file);
+    for spec_entry in spec.iter() {
+        entries.retain(|entry| !entry.matches(spec_entry));
+        entries.push(spec_entry.clone());
+    }
+
+    remove_modified_masks(&mut entries, spec);
+    compact(file.get_permissions(), entries, is_directory(file))
+}
+
+pub fn remove(file: &File,
+        spec: &Vec<AclEntry>) -> Result<(u32, Vec<AclEntry>), NahFSError> {
+    let mut entries = expand(file);
+    entries.retain(|entry| !spec.iter()
+        .any(|spec_entry| spec_entry.matches(entry)));
+
+    remove_modified_masks(&mut entries, spec);
+    compact(file.get_permissions(), entries, is_directory(file))
+}
+
+pub fn set(file: &File,
+        spec: &Vec<AclEntry>) -> Result<(u32, Vec<AclEntry>), NahFSError> {
+    // entries of scopes missing from the spec are retained
+    let mut entries = spec.clone();
+    for scope in vec!(AclScope::Access, AclScope::Default) {
+        if !spec.iter().any(|entry| entry.scope == scope) {
+            entries.extend(expand(file).into_iter()
+                .filter(|entry| entry.scope == scope));
+        }
+    }
+
+    compact(file.get_permissions(), entries, is_directory(file))
+}
+
+fn compact(permissions: u32, mut entries: Vec<AclEntry>,
+        is_directory: bool) -> Result<(u32, Vec<AclEntry>), NahFSError> {
+    // validate entries
+    for (i, entry) in entries.iter().enumerate() {
+        if entries[i + 1..].iter().any(|x| x.matches(entry)) {
+            return Err(NahFSError::from(format!("Invalid ACL: multiple \
+                entries with same scope, type and name.")));
+        } else if entry.is_named() && (entry.acl_type == AclType::Mask
+                || entry.acl_type == AclType::Other) {
+            return Err(NahFSError::from(format!("Invalid ACL: this \
+                entry type must not have a name: {:?}", entry)));
+        } else if entry.scope == AclScope::Default && !is_directory {
+            return Err(NahFSError::from(format!("Invalid ACL: only \
+                directories may have a default ACL.")));
+        }
+    }
+
+    // retrieve base access entries
+    let get_base = |entries: &Vec<AclEntry>, scope, acl_type| entries.iter()
+        .find(|entry| entry.is_base(scope, acl_type)).map(|x| x.clone());
+    let (user, group, other) =
+            match (get_base(&entries, AclScope::Access, AclType::User),
+                get_base(&entries, AclScope::Access, AclType::Group),
+                get_base(&entries, AclScope::Access, AclType::Other)) {
+        (Some(user), Some(group), Some(other)) => (user, group, other),
+        _ => return Err(NahFSError::from(format!("Invalid ACL: the \
+            user, group and other entries are required."))),
+    };
+
+    // copy missing base default entries from access entries
+    if entries.iter().any(|entry| entry.scope == AclScope::Default) {
+        for base in vec!(&user, &group, &other) {
+            if get_base(&entries, AclScope::Default, base.acl_type).is_none() {
+                let mut entry = base.clone();
+                entry.scope = AclScope::Default;
+                entries.push(entry);
+            }
+        }
+    }
+
+    // compute missing masks as the union of group class entries
+    for scope in vec!(AclScope::Access, AclScope::Default) {
+        let scope_entries: Vec<&AclEntry> = entries.iter()
+            .filter(|entry| entry.scope == scope).collect();
+        if scope_entries.iter().any(|entry| entry.is_named()) &&
+                get_base(&entries, scope, AclType::Mask).is_none() {
+            let mut mask_permissions = 0;
+            for entry in scope_entries {
+                if entry.acl_type == AclType::Group || (entry.is_named()
+                        && entry.acl_type == AclType::User) {
+                    mask_permissions |= entry.permissions;
+                }
+            }
+
+            entries.push(AclEntry::new(scope,
+                AclType::Mask, None, mask_permissions));
+        }
+    }
+
+    // order entries by scope, type, and name
+    entries.sort_by_key(|entry| (entry.scope as u8,
+        entry.acl_type as u8, entry.name.clone()));
+
+    // base entries are stored in the permission bits - where group
+    //  bits hold the mask of extended access acls
+    let mask = get_base(&entries, AclScope::Access, AclType::Mask);
+    let group_permissions = match &mask {
+        Some(mask) => mask.permissions,
+        None => group.permissions,
+    };
+
+    let permissions = (permissions & !0o777) | (user.permissions << 6)
+        | (group_permissions << 3) | other.permissions;
+    entries.retain(|entry| match (entry.scope, entry.acl_type) {
+        (AclScope::Default, _) => true,
+        (AclScope::Access, AclType::Group) => mask.is_some(),
+        (AclScope::Access, AclType::User) => entry.is_named(),
+        _ => false,
+    });
+
+    Ok((permissions, entries))
+}
+
+fn expand(file: &File) -> Vec<AclEntry> {
+    // reconstruct base access entries from the permission bits
+    let permissions = file.get_permissions();
+    let mut entries = vec!(AclEntry::new(AclScope::Access,
+        AclType::User, None, (permissions >> 6) & ALL));
+
+    let extended = file.get_acl().iter()
+        .any(|entry| entry.scope == AclScope::Access);
+    entries.push(AclEntry::new(AclScope::Access, match extended {
+        true => AclType::Mask,
+        false => AclType::Group,
+    }, None, (permissions >> 3) & ALL));
+
+    entries.push(AclEntry::new(AclScope::Access,
+        AclType::Other, None, permissions & ALL));
+    entries.extend(file.get_acl().iter().cloned());
+    entries
+}
+
+fn is_directory(file: &File) -> bool {
+    match file.get_file_type() {
+        FileType::Directory => true,
+        _ => false,
+    }
+}
+
+fn remove_modified_masks(entries: &mut Vec<AclEntry>, spec: &Vec<AclEntry>) {
+    // masks are recomputed for modified scopes unless specified
+    for scope in vec!(AclScope::Access, AclScope::Default) {
+        let modified = spec.iter().any(|entry| entry.scope == scope);
+        let specified = spec.iter()
+            .any(|entry| entry.is_base(scope, AclType::Mask));
+        if modified && !specified {
+            entries.retain(|entry| !entry.is_base(scope, AclType::Mask));
+        }
+    }
+}
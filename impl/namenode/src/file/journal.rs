@@ -0,0 +1,614 @@
+use serde::{Deserialize, Serialize};
+use shared::NahFSError;
+
+use crate::block::BlockStore;
+use crate::file::FileStore;
+use crate::file::acl::AclEntry;
+use crate::file::legacy::OperationV1;
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+
+static MAGIC: u64 = 0x4c4e_4a53_4648_414e; // 'NAHFSJNL'
+
+// version 1: unversioned operations
+// version 2: checksummed operations with appended variants
+static LAYOUT_VERSION: u32 = 2;
+
+#[derive(Deserialize, Serialize)]
+struct JournalHeader {
+    magic: u64,
+    version: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+struct OperationHeader {
+    length: u32,
+    checksum: u32,
+}
+
+// operations are serialized by variant index -> new variants must
+//  be appended to preserve the layout of existing journals
+#[derive(Debug, Deserialize, Serialize)]
+pub enum Operation {
+    AddBlock { path: String, block_id: u64,
+        generation_stamp: u64, time: u64 },
+    Complete { path: String, time: u64 },
+    Create { path: String, permissions: u32, owner: String, group: String,
+        replication: u32, block_size: u64, client_name: String, time: u64 },
+    Delete { path: String, recursive: bool, time: u64 },
+    Mkdirs { path: String, permissions: u32, owner: String,
+        group: String, create_parent: bool, time: u64 },
+    RemoveBlock { path: String, block_id: u64 },
+    Rename { src_path: String, dst_path: String, time: u64 },
+    SetClientName { path: String, client_name: Option<String> },
+    SetOwner { path: String, owner: Option<String>, group: Option<String> },
+    SetPermission { path: String, permissions: u32 },
+    SetStoragePolicy { path: String, storage_policy: String },
+    SetTimes { path: String, modification_time: Option<u64>,
+        access_time: Option<u64> },
+    Rename2 { src_path: String, dst_path: String,
+        overwrite: bool, time: u64 },
+    SetQuota { path: String, namespace_quota: Option<u64>,
+        space_quota: Option<u64> },
+    AllowSnapshot { path: String },
+    CreateSnapshot { path: String, name: String, time: u64 },
+    DeleteSnapshot { path: String, name: String },
+    RemoveXAttr { path: String, name: String },
+    SetXAttr { path: String, name: String, value: Vec<u8>,
+        create: bool, replace: bool },
+    ModifyAclEntries { path: String, entries: Vec<AclEntry> },
+    RemoveAclEntries { path: String, entries: Vec<AclEntry> },
+    SetAcl { path: String, entries: Vec<AclEntry> },
+    UnsetStoragePolicy { path: String },
+    SetReplication { path: String, replication: u32 },
+    SetGenerationStamp { generation_stamp: u64 },
+    UpdateBlock { block_id: u64, generation_stamp: u64,
+        length: Option<u64> },
+    CommitBlock { path: String, block_id: u64, length: u64 },
+    UpdatePipeline { path: String, block_id: u64,
+        generation_stamp: u64, length: u64 },
+}
+
+impl Operation {
+    pub fn apply(&self, file_store: &mut FileStore,
+            block_store: &mut BlockStore) -> Result<(), NahFSError> {
+        match self {
+            Operation::AddBlock {path, block_id, generation_stamp, time} =>
+                file_store.add_block(path, *block_id,
+                    *generation_stamp, *time),
+            Operation::AllowSnapshot {path} =>
+                file_store.allow_snapshot(path),
+            Operation::CommitBlock {path, block_id, length} =>
+                file_store.commit_block(path, *block_id, *length),
+            Operation::Complete {path, time} =>
+                file_store.complete(path, *time),
+            Operation::Create {path, permissions, owner, group,
+                    replication, block_size, client_name, time} =>
+                file_store.create(path, *permissions, owner, group,
+                    *replication, *block_size, client_name, *time),
+            Operation::CreateSnapshot {path, name, time} =>
+                file_store.create_snapshot(path, name, *time),
+            Operation::Delete {path, recursive, time} =>
+                file_store.delete(path, *recursive, *time).map(|_| ()),
+            Operation::DeleteSnapshot {path, name} =>
+                file_store.delete_snapshot(path, name).map(|_| ()),
+            Operation::Mkdirs {path, permissions, owner, group,
+                    create_parent, time} =>
+                file_store.mkdirs(path, *permissions, owner,
+                    group, *create_parent, *time),
+            Operation::ModifyAclEntries {path, entries} =>
+                file_store.modify_acl_entries(path, entries),
+            Operation::RemoveAclEntries {path, entries} =>
+                file_store.remove_acl_entries(path, entries),
+            Operation::RemoveBlock {path, block_id} =>
+                file_store.remove_block(path, *block_id),
+            Operation::RemoveXAttr {path, name} =>
+                file_store.remove_xattr(path, name),
+            Operation::Rename {src_path, dst_path, time} =>
+                file_store.rename(src_path, dst_path, *time),
+            Operation::Rename2 {src_path, dst_path, overwrite, time} =>
+                file_store.rename2(src_path, dst_path,
+                    *overwrite, *time).map(|_| ()),
+            Operation::SetAcl {path, entries} =>
+                file_store.set_acl(path, entries),
+            Operation::SetClientName {path, client_name} =>
+                file_store.set_client_name(path,
+                    client_name.as_ref().map(|x| x.as_str())),
+            Operation::SetGenerationStamp {generation_stamp} => {
+                file_store.set_generation_stamp(*generation_stamp);
+                Ok(())
+            },
+            Operation::SetOwner {path, owner, group} =>
+                file_store.set_owner(path,
+                    owner.as_ref().map(|x| x.as_str()),
+                    group.as_ref().map(|x| x.as_str())),
+            Operation::SetPermission {path, permissions} =>
+                file_store.set_permission(path, *permissions),
+            Operation::SetQuota {path, namespace_quota, space_quota} =>
+                file_store.set_quota(path, *namespace_quota, *space_quota),
+            Operation::SetReplication {path, replication} =>
+                file_store.set_replication(path, *replication).map(|_| ()),
+            Operation::SetStoragePolicy {path, storage_policy} =>
+                file_store.set_storage_policy(path, storage_policy),
+            Operation::SetTimes {path, modification_time, access_time} =>
+                file_store.set_times(path,
+                    *modification_time, *access_time),
+            Operation::SetXAttr {path, name, value, create, replace} =>
+                file_store.set_xattr(path, name, value, *create, *replace),
+            Operation::UnsetStoragePolicy {path} =>
+                file_store.unset_storage_policy(path),
+            Operation::UpdateBlock {block_id, generation_stamp, length} => {
+                file_store.set_generation_stamp(*generation_stamp);
+                block_store.set_generation_stamp(*block_id,
+                    *generation_stamp, *length);
+                Ok(())
+            },
+            Operation::UpdatePipeline {path, block_id,
+                    generation_stamp, length} => {
+                file_store.set_generation_stamp(*generation_stamp);
+                file_store.commit_block(path, *block_id, *length)?;
+                block_store.set_generation_stamp(*block_id,
+                    *generation_stamp, Some(*length));
+                Ok(())
+            },
+        }
+    }
+}
+
+pub struct Journal {
+    file: File,
+    count: u64,
+    transaction_id: u64,
+}
+
+impl Journal {
+    pub fn open(path: &str, file_store: &mut FileStore,
+            block_store: &mut BlockStore,
+            transaction_id: u64) -> Result<Journal, NahFSError> {
+        let mut file = OpenOptions::new().read(true)
+            .append(true).create(true).open(path)?;
+
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+
+        // read operations - journals written prior to versioning have
+        //  no header and are rewritten in the current layout
+        let header_length = bincode::serialized_size(&JournalHeader {
+            magic: MAGIC, version: LAYOUT_VERSION })? as usize;
+        let header: Option<JournalHeader> = match buf.len() {
+            x if x < header_length => None,
+            _ => bincode::deserialize(&buf[..header_length]).ok(),
+        };
+
+        let operations = match header {
+            Some(ref header) if header.magic == MAGIC => {
+                if header.version != LAYOUT_VERSION {
+                    return Err(NahFSError::from(format!(
+                        "unsupported journal layout version {}",
+                        header.version)));
+                }
+
+                let (operations, length) =
+                    read_operations(&buf, header_length)?;
+                if length != buf.len() {
+                    // partially written operation was never acknowledged
+                    warn!("truncating partially written operation in \
+                        journal '{}' at offset {}", path, length);
+                    file.set_len(length as u64)?;
+                }
+
+                operations
+            },
+            _ if buf.len() == 0 => {
+                write_header(&mut file)?;
+                Vec::new()
+            },
+            _ => {
+                let operations = read_unversioned_operations(&buf)?;
+                info!("migrating unversioned journal '{}'", path);
+                file = rewrite(path, &operations)?;
+                operations
+            },
+        };
+
+        // replay operations not yet included in the persisted image
+        let (mut count, mut last_transaction_id) = (0, transaction_id);
+        for (id, operation) in operations {
+            if id <= transaction_id {
+                continue;
+            }
+
+            trace!("replaying journal operation {} {:?}", id, operation);
+            operation.apply(file_store, block_store).map_err(|e|
+                NahFSError::from(format!("failed to replay journal \
+                    operation {} {:?}: {}", id, operation, e)))?;
+
+            last_transaction_id = id;
+            count += 1;
+        }
+
+        Ok(Journal {
+            file: file,
+            count: count,
+            transaction_id: last_transaction_id,
+        })
+    }
+
+    pub fn append(&mut self,
+            operation: &Operation) -> Result<(), NahFSError> {
+        // write operation and sync before acknowledging the request
+        //  - removing partial writes so later operations stay readable
+        let buf = encode_operation(self.transaction_id + 1, operation)?;
+        let length = self.file.metadata()?.len();
+        if let Err(e) = self.file.write_all(&buf)
+                .and_then(|_| self.file.sync_data()) {
+            let _ = self.file.set_len(length);
+            return Err(e.into());
+        }
+
+        self.count += 1;
+        self.transaction_id += 1;
+        Ok(())
+    }
+
+    pub fn write<T, F>(&mut self, operation: &Operation,
+            mutate: F) -> Result<T, NahFSError>
+            where F: FnOnce() -> Result<T, NahFSError> {
+        // journal operation before mutating in-memory state - failed
+        //  mutations are retracted so they are never replayed
+        let length = self.file.metadata()?.len();
+        self.append(operation)?;
+
+        let result = mutate();
+        if result.is_err() {
+            if let Err(e) = self.file.set_len(length)
+                    .and_then(|_| self.file.sync_data()) {
+                error!("failed to retract journal operation {} {:?}: {}",
+                    self.transaction_id, operation, e);
+                return result;
+            }
+
+            self.count -= 1;
+            self.transaction_id -= 1;
+        }
+
+        result
+    }
+
+    pub fn get_count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn get_transaction_id(&self) -> u64 {
+        self.transaction_id
+    }
+
+    pub fn truncate(&mut self) -> Result<(), NahFSError> {
+        self.file.set_len(0)?;
+        write_header(&mut self.file)?;
+
+        self.count = 0;
+        Ok(())
+    }
+}
+
+fn encode_operation(id: u64,
+        operation: &Operation) -> Result<Vec<u8>, NahFSError> {
+    // prefix operations with their length and checksum
+    let payload = bincode::serialize(&(id, operation))?;
+    let mut buf = bincode::serialize(&OperationHeader {
+        length: payload.len() as u32,
+        checksum: crc32fast::hash(&payload),
+    })?;
+
+    buf.extend(payload);
+    Ok(buf)
+}
+
+fn read_operations(buf: &[u8], offset: usize)
+        -> Result<(Vec<(u64, Operation)>, usize), NahFSError> {
+    let header_length = bincode::serialized_size(
+        &OperationHeader { length: 0, checksum: 0 })? as usize;
+
+    let (mut operations, mut offset) = (Vec::new(), offset);
+    while offset + header_length <= buf.len() {
+        let header: OperationHeader =
+            bincode::deserialize(&buf[offset..offset + header_length])?;
+        let start = offset + header_length;
+        let end = start + header.length as usize;
+        if end > buf.len() {
+            break;
+        }
+
+        // corrupt operations may not be skipped without losing state
+        let payload = &buf[start..end];
+        if crc32fast::hash(payload) != header.checksum {
+            return Err(NahFSError::from(format!(
+                "journal checksum mismatch at offset {}", offset)));
+        }
+
+        let operation = bincode::deserialize(payload).map_err(|e|
+            NahFSError::from(format!("failed to decode journal \
+                operation at offset {}: {}", offset, e)))?;
+        operations.push(operation);
+        offset = end;
+    }
+
+    Ok((operations, offset))
+}
+
+fn read_unversioned_operations(buf: &[u8])
+        -> Result<Vec<(u64, Operation)>, NahFSError> {
+    let mut operations = Vec::new();
+    let mut reader = buf;
+    while reader.len() != 0 {
+        let offset = buf.len() - reader.len();
+        let (id, operation): (u64, OperationV1) =
+            bincode::deserialize_from(&mut reader).map_err(|e|
+                NahFSError::from(format!("failed to decode unversioned \
+                    journal operation at offset {}: {}", offset, e)))?;
+        operations.push((id, operation.migrate()));
+    }
+
+    Ok(operations)
+}
+
+fn rewrite(path: &str,
+        operations: &Vec<(u64, Operation)>) -> Result<File, NahFSError> {
+    // write operations to temporary file and atomically replace journal
+    let tmp_path = format!("{}.tmp", path);
+    {
+        let mut file = File::create(&tmp_path)?;
+        write_header(&mut file)?;
+        for (id, operation) in operations.iter() {
+            file.write_all(&encode_operation(*id, operation)?)?;
+        }
+
+        file.sync_all()?;
+    }
+
+    std::fs::rename(&tmp_path, path)?;
+    let file = OpenOptions::new().read(true).append(true).open(path)?;
+    Ok(file)
+}
+
+fn write_header(file: &mut File) -> Result<(), NahFSError> {
+    file.write_all(&bincode::serialize(&JournalHeader {
+        magic: MAGIC,
+        version: LAYOUT_VERSION,
+    })?)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn corruption() {
+        use super::{Journal, Operation};
+        use crate::block::BlockStore;
+        use crate::file::FileStore;
+
+        use std::fs::OpenOptions;
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!(
+            "nahfs-journal-{}", rand::random::<u64>()));
+        let path = path.to_str().unwrap();
+
+        let mkdirs = |path: &str| Operation::Mkdirs {
+            path: path.to_string(), permissions: 493,
+            owner: "root".to_string(), group: "root".to_string(),
+            create_parent: true, time: 1 };
+
+        {
+            let (mut file_store, mut block_store) =
+                (FileStore::new(), BlockStore::new());
+            let mut journal = Journal::open(path,
+                &mut file_store, &mut block_store, 0).unwrap();
+            journal.append(&mkdirs("/a")).unwrap();
+            journal.append(&mkdirs("/b")).unwrap();
+        }
+
+        // partially written operations are truncated
+        let length = std::fs::metadata(path).unwrap().len();
+        OpenOptions::new().append(true).open(path).unwrap()
+            .write_all(&[16, 0, 0, 0, 1, 2]).unwrap();
+
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        let journal = Journal::open(path,
+            &mut file_store, &mut block_store, 0).unwrap();
+        assert_eq!(journal.get_count(), 2);
+        assert_eq!(std::fs::metadata(path).unwrap().len(), length);
+
+        // corrupt operations fail to open
+        let mut buf = std::fs::read(path).unwrap();
+        let index = buf.len() - 2;
+        buf[index] ^= 0xff;
+        std::fs::write(path, &buf).unwrap();
+
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        assert!(Journal::open(path,
+            &mut file_store, &mut block_store, 0).is_err());
+
+        // operations which fail to apply fail to open
+        let _ = std::fs::remove_file(path);
+        {
+            let (mut file_store, mut block_store) =
+                (FileStore::new(), BlockStore::new());
+            let mut journal = Journal::open(path,
+                &mut file_store, &mut block_store, 0).unwrap();
+            journal.append(&Operation::Rename { src_path: "/a".to_string(),
+                dst_path: "/b".to_string(), time: 2 }).unwrap();
+        }
+
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        assert!(Journal::open(path,
+            &mut file_store, &mut block_store, 0).is_err());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn migrate_unversioned() {
+        use super::{Journal, Operation};
+        use crate::block::BlockStore;
+        use crate::file::FileStore;
+        use crate::file::legacy::OperationV1;
+
+        let path = std::env::temp_dir().join(format!(
+            "nahfs-journal-{}", rand::random::<u64>()));
+        let path = path.to_str().unwrap();
+
+        // write operations in the unversioned layout
+        let mut buf = Vec::new();
+        buf.extend(bincode::serialize(&(1u64, OperationV1::Mkdirs {
+            path: "/a".to_string(), permissions: 493,
+            owner: "root".to_string(), group: "root".to_string(),
+            create_parent: true, time: 1 })).unwrap());
+        buf.extend(bincode::serialize(&(2u64, OperationV1::Create {
+            path: "/a/f".to_string(), permissions: 420,
+            owner: "root".to_string(), group: "root".to_string(),
+            replication: 3, block_size: 65536,
+            client_name: "client".to_string(), time: 2 })).unwrap());
+        buf.extend(bincode::serialize(&(3u64, OperationV1::AddBlock {
+            path: "/a/f".to_string(), block_id: 7, time: 3 })).unwrap());
+        buf.extend(bincode::serialize(&(4u64, OperationV1::SetTimes {
+            path: "/a/f".to_string(), modification_time: None,
+            access_time: Some(4) })).unwrap());
+        std::fs::write(path, &buf).unwrap();
+
+        // unversioned operations are replayed and rewritten
+        {
+            let (mut file_store, mut block_store) =
+                (FileStore::new(), BlockStore::new());
+            let mut journal = Journal::open(path,
+                &mut file_store, &mut block_store, 0).unwrap();
+            assert_eq!(journal.get_count(), 4);
+            let file = file_store.get_file("/a/f").unwrap();
+            assert_eq!(file.get_access_time(), 4);
+            assert!(file.is_under_construction());
+
+            journal.append(&Operation::Complete {
+                path: "/a/f".to_string(), time: 5 }).unwrap();
+        }
+
+        assert!(std::fs::read(path).unwrap() != buf);
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        let journal = Journal::open(path,
+            &mut file_store, &mut block_store, 0).unwrap();
+        assert_eq!(journal.get_transaction_id(), 5);
+        assert!(!file_store.get_file("/a/f")
+            .unwrap().is_under_construction());
+        assert_eq!(file_store.get_block_ids().len(), 1);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn replay() {
+        use super::{Journal, Operation};
+        use crate::block::BlockStore;
+        use crate::file::FileStore;
+
+        let path = std::env::temp_dir().join(format!(
+            "nahfs-journal-{}", rand::random::<u64>()));
+        let path = path.to_str().unwrap();
+
+        // journal operations
+        {
+            let (mut file_store, mut block_store) =
+                (FileStore::new(), BlockStore::new());
+            let mut journal = Journal::open(path,
+                &mut file_store, &mut block_store, 0).unwrap();
+            journal.append(&Operation::Mkdirs { path: "/a".to_string(),
+                permissions: 493, owner: "root".to_string(),
+                group: "root".to_string(), create_parent: true,
+                time: 1 }).unwrap();
+            journal.append(&Operation::Rename { src_path: "/a".to_string(),
+                dst_path: "/b".to_string(), time: 2 }).unwrap();
+            journal.append(&Operation::SetGenerationStamp {
+                generation_stamp: 5000 }).unwrap();
+            journal.append(&Operation::UpdateBlock { block_id: 7,
+                generation_stamp: 5002, length: Some(64) }).unwrap();
+        }
+
+        // replay operations onto an empty FileStore
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        let journal = Journal::open(path,
+            &mut file_store, &mut block_store, 0).unwrap();
+        assert_eq!(journal.get_count(), 4);
+        assert_eq!(journal.get_transaction_id(), 4);
+        assert!(file_store.get_file("/a").is_none());
+        assert!(file_store.get_file("/b").is_some());
+        assert_eq!(file_store.allocate_generation_stamp(), 5003);
+
+        // block updates are replayed onto the BlockStore
+        let block = block_store.get_block(&7).unwrap();
+        assert_eq!((block.generation_stamp, block.length), (5002, 64));
+
+        // operations included in the image are skipped
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        let journal = Journal::open(path,
+            &mut file_store, &mut block_store, 1).unwrap();
+        assert_eq!(journal.get_count(), 3);
+        assert!(file_store.get_file("/a").is_none());
+        assert!(file_store.get_file("/b").is_none());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn write() {
+        use super::{Journal, Operation};
+        use crate::block::BlockStore;
+        use crate::file::FileStore;
+
+        let path = std::env::temp_dir().join(format!(
+            "nahfs-journal-{}", rand::random::<u64>()));
+        let path = path.to_str().unwrap();
+
+        // operations are journaled before their mutation is applied
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        {
+            let mut journal = Journal::open(path,
+                &mut file_store, &mut block_store, 0).unwrap();
+            journal.write(&Operation::Mkdirs { path: "/a".to_string(),
+                permissions: 493, owner: "root".to_string(),
+                group: "root".to_string(), create_parent: true, time: 1 },
+                || file_store.mkdirs("/a", 493, "root", "root", true, 1))
+                .unwrap();
+
+            // failed mutations are retracted from the journal
+            assert!(journal.write(&Operation::Rename {
+                src_path: "/b".to_string(), dst_path: "/c".to_string(),
+                time: 2 }, || file_store.rename("/b", "/c", 2)).is_err());
+            assert_eq!(journal.get_count(), 1);
+            assert_eq!(journal.get_transaction_id(), 1);
+
+            journal.write(&Operation::Rename { src_path: "/a".to_string(),
+                dst_path: "/d".to_string(), time: 3 },
+                || file_store.rename("/a", "/d", 3)).unwrap();
+            assert_eq!(journal.get_transaction_id(), 2);
+        }
+
+        // retracted operations are never replayed
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        let journal = Journal::open(path,
+            &mut file_store, &mut block_store, 0).unwrap();
+        assert_eq!(journal.get_count(), 2);
+        assert!(file_store.get_file("/a").is_none());
+        assert!(file_store.get_file("/d").is_some());
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,420 @@
+use serde::{Deserialize, Serialize};
+
+use crate::file::{File, FileType, Quota};
+use crate::file::journal::Operation;
+
+use std::collections::{BTreeMap, HashMap};
+
+/**
+ * FileStore layout prior to tracking modification and access times
+ */
+
+#[derive(Deserialize, Serialize)]
+pub struct FileV1 {
+    pub inode: u64,
+    pub file_type: FileType,
+    pub name: String,
+    pub owner: String,
+    pub group: String,
+    pub permissions: u32,
+    pub storage_policy: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FileStoreV1 {
+    pub inodes: HashMap<u64, FileV1>,
+    pub children: HashMap<u64, Vec<u64>>,
+    pub parents: HashMap<u64, u64>,
+    pub storage_policies: HashMap<u32, String>,
+}
+
+impl FileStoreV1 {
+    pub fn migrate(self, time: u64) -> FileStoreV2 {
+        // initialize times to migration time
+        let mut inodes = HashMap::new();
+        for (inode, file) in self.inodes {
+            inodes.insert(inode, FileV2 {
+                inode: file.inode,
+                file_type: file.file_type,
+                name: file.name,
+                owner: file.owner,
+                group: file.group,
+                permissions: file.permissions,
+                storage_policy: file.storage_policy,
+                modification_time: time,
+                access_time: time,
+            });
+        }
+
+        FileStoreV2 {
+            inodes: inodes,
+            children: self.children,
+            parents: self.parents,
+            storage_policies: self.storage_policies,
+        }
+    }
+}
+
+/**
+ * FileStore layout prior to tracking under construction files
+ */
+
+#[derive(Deserialize, Serialize)]
+pub struct FileV2 {
+    pub inode: u64,
+    pub file_type: FileType,
+    pub name: String,
+    pub owner: String,
+    pub group: String,
+    pub permissions: u32,
+    pub storage_policy: Option<u32>,
+    pub modification_time: u64,
+    pub access_time: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FileStoreV2 {
+    pub inodes: HashMap<u64, FileV2>,
+    pub children: HashMap<u64, Vec<u64>>,
+    pub parents: HashMap<u64, u64>,
+    pub storage_policies: HashMap<u32, String>,
+}
+
+impl FileStoreV2 {
+    pub fn migrate(self) -> FileStoreV3 {
+        // no files are under construction
+        let mut inodes = HashMap::new();
+        for (inode, file) in self.inodes {
+            inodes.insert(inode, FileV3 {
+                inode: file.inode,
+                file_type: file.file_type,
+                name: file.name,
+                owner: file.owner,
+                group: file.group,
+                permissions: file.permissions,
+                storage_policy: file.storage_policy,
+                modification_time: file.modification_time,
+                access_time: file.access_time,
+                client_name: None,
+            });
+        }
+
+        FileStoreV3 {
+            inodes: inodes,
+            children: self.children,
+            parents: self.parents,
+            storage_policies: self.storage_policies,
+        }
+    }
+}
+
+/**
+ * FileStore layout prior to name ordered directory children
+ */
+
+#[derive(Deserialize, Serialize)]
+pub struct FileStoreV3 {
+    pub inodes: HashMap<u64, FileV3>,
+    pub children: HashMap<u64, Vec<u64>>,
+    pub parents: HashMap<u64, u64>,
+    pub storage_policies: HashMap<u32, String>,
+}
+
+impl FileStoreV3 {
+    pub fn migrate(self) -> FileStoreV4 {
+        // key children by name
+        let mut children = HashMap::new();
+        for (inode, child_inodes) in self.children {
+            let mut map = BTreeMap::new();
+            for child_inode in child_inodes {
+                let name = &self.inodes.get(&child_inode).unwrap().name;
+                map.insert(name.to_string(), child_inode);
+            }
+
+            children.insert(inode, map);
+        }
+
+        FileStoreV4 {
+            inodes: self.inodes,
+            children: children,
+            parents: self.parents,
+            storage_policies: self.storage_policies,
+        }
+    }
+}
+
+/**
+ * FileStore layout prior to directory quotas
+ */
+
+#[derive(Deserialize, Serialize)]
+pub struct FileStoreV4 {
+    pub inodes: HashMap<u64, FileV3>,
+    pub children: HashMap<u64, BTreeMap<String, u64>>,
+    pub parents: HashMap<u64, u64>,
+    pub storage_policies: HashMap<u32, String>,
+}
+
+impl FileStoreV4 {
+    pub fn migrate(self) -> FileStoreV5 {
+        // no directories have quotas
+        FileStoreV5 {
+            inodes: self.inodes,
+            children: self.children,
+            parents: self.parents,
+            quotas: HashMap::new(),
+            storage_policies: self.storage_policies,
+        }
+    }
+}
+
+/**
+ * FileStore layout prior to directory snapshots
+ */
+
+#[derive(Deserialize, Serialize)]
+pub struct FileStoreV5 {
+    pub inodes: HashMap<u64, FileV3>,
+    pub children: HashMap<u64, BTreeMap<String, u64>>,
+    pub parents: HashMap<u64, u64>,
+    pub quotas: HashMap<u64, Quota>,
+    pub storage_policies: HashMap<u32, String>,
+}
+
+impl FileStoreV5 {
+    pub fn migrate(self) -> FileStoreV6 {
+        // no directories are snapshottable
+        FileStoreV6 {
+            inodes: self.inodes,
+            children: self.children,
+            parents: self.parents,
+            quotas: self.quotas,
+            snapshot_directories: HashMap::new(),
+            storage_policies: self.storage_policies,
+        }
+    }
+}
+
+/**
+ * FileStore layout prior to extended attributes
+ */
+
+#[derive(Deserialize, Serialize)]
+pub struct FileV3 {
+    pub inode: u64,
+    pub file_type: FileType,
+    pub name: String,
+    pub owner: String,
+    pub group: String,
+    pub permissions: u32,
+    pub storage_policy: Option<u32>,
+    pub modification_time: u64,
+    pub access_time: u64,
+    pub client_name: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FileStoreV6 {
+    pub inodes: HashMap<u64, FileV3>,
+    pub children: HashMap<u64, BTreeMap<String, u64>>,
+    pub parents: HashMap<u64, u64>,
+    pub quotas: HashMap<u64, Quota>,
+    pub snapshot_directories: HashMap<u64, u64>,
+    pub storage_policies: HashMap<u32, String>,
+}
+
+impl FileStoreV6 {
+    pub fn migrate(self) -> FileStoreV7 {
+        // no files have extended attributes
+        let mut inodes = HashMap::new();
+        for (inode, file) in self.inodes {
+            inodes.insert(inode, FileV4 {
+                inode: file.inode,
+                file_type: file.file_type,
+                name: file.name,
+                owner: file.owner,
+                group: file.group,
+                permissions: file.permissions,
+                storage_policy: file.storage_policy,
+                modification_time: file.modification_time,
+                access_time: file.access_time,
+                client_name: file.client_name,
+                xattrs: BTreeMap::new(),
+            });
+        }
+
+        FileStoreV7 {
+            inodes: inodes,
+            children: self.children,
+            parents: self.parents,
+            quotas: self.quotas,
+            snapshot_directories: self.snapshot_directories,
+            storage_policies: self.storage_policies,
+        }
+    }
+}
+
+/**
+ * FileStore layout prior to access control lists
+ */
+
+#[derive(Deserialize, Serialize)]
+pub struct FileV4 {
+    pub inode: u64,
+    pub file_type: FileType,
+    pub name: String,
+    pub owner: String,
+    pub group: String,
+    pub permissions: u32,
+    pub storage_policy: Option<u32>,
+    pub modification_time: u64,
+    pub access_time: u64,
+    pub client_name: Option<String>,
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FileStoreV7 {
+    pub inodes: HashMap<u64, FileV4>,
+    pub children: HashMap<u64, BTreeMap<String, u64>>,
+    pub parents: HashMap<u64, u64>,
+    pub quotas: HashMap<u64, Quota>,
+    pub snapshot_directories: HashMap<u64, u64>,
+    pub storage_policies: HashMap<u32, String>,
+}
+
+impl FileStoreV7 {
+    pub fn migrate(self) -> FileStoreV8 {
+        // no files have access control lists
+        let mut inodes = HashMap::new();
+        for (inode, file) in self.inodes {
+            inodes.insert(inode, File {
+                inode: file.inode,
+                file_type: file.file_type,
+                name: file.name,
+                owner: file.owner,
+                group: file.group,
+                permissions: file.permissions,
+                storage_policy: file.storage_policy,
+                modification_time: file.modification_time,
+                access_time: file.access_time,
+                client_name: file.client_name,
+                xattrs: file.xattrs,
+                acl: Vec::new(),
+            });
+        }
+
+        FileStoreV8 {
+            inodes: inodes,
+            children: self.children,
+            parents: self.parents,
+            quotas: self.quotas,
+            snapshot_directories: self.snapshot_directories,
+            storage_policies: self.storage_policies,
+        }
+    }
+}
+
+/**
+ * FileStore layout prior to sequential id allocation
+ */
+
+#[derive(Deserialize, Serialize)]
+pub struct FileStoreV8 {
+    pub inodes: HashMap<u64, File>,
+    pub children: HashMap<u64, BTreeMap<String, u64>>,
+    pub parents: HashMap<u64, u64>,
+    pub quotas: HashMap<u64, Quota>,
+    pub snapshot_directories: HashMap<u64, u64>,
+    pub storage_policies: HashMap<u32, String>,
+}
+
+/**
+ * FileStore layout prior to committed block lengths
+ */
+
+#[derive(Deserialize, Serialize)]
+pub struct FileStoreV9 {
+    pub inodes: HashMap<u64, File>,
+    pub children: HashMap<u64, BTreeMap<String, u64>>,
+    pub parents: HashMap<u64, u64>,
+    pub quotas: HashMap<u64, Quota>,
+    pub snapshot_directories: HashMap<u64, u64>,
+    pub storage_policies: HashMap<u32, String>,
+    pub last_block_id: u64,
+    pub last_indexed_block_id: u64,
+    pub last_generation_stamp: u64,
+    pub last_inode: u64,
+    pub last_storage_policy_id: u32,
+}
+
+/**
+ * Journal operation layout prior to versioning
+ */
+
+#[derive(Deserialize, Serialize)]
+pub enum OperationV1 {
+    AddBlock { path: String, block_id: u64, time: u64 },
+    Complete { path: String, time: u64 },
+    Create { path: String, permissions: u32, owner: String, group: String,
+        replication: u32, block_size: u64, client_name: String, time: u64 },
+    Delete { path: String, recursive: bool, time: u64 },
+    Mkdirs { path: String, permissions: u32, owner: String,
+        group: String, create_parent: bool, time: u64 },
+    RemoveBlock { path: String, block_id: u64 },
+    Rename { src_path: String, dst_path: String, time: u64 },
+    SetClientName { path: String, client_name: Option<String> },
+    SetOwner { path: String, owner: Option<String>, group: Option<String> },
+    SetPermission { path: String, permissions: u32 },
+    SetStoragePolicy { path: String, storage_policy: String },
+    SetTimes { path: String, modification_time: Option<u64>,
+        access_time: Option<u64> },
+}
+
+impl OperationV1 {
+    pub fn migrate(self) -> Operation {
+        // generation stamps were not journaled
+        match self {
+            OperationV1::AddBlock {path, block_id, time} =>
+                Operation::AddBlock { path: path, block_id: block_id,
+                    generation_stamp: 0, time: time },
+            OperationV1::Complete {path, time} =>
+                Operation::Complete { path: path, time: time },
+            OperationV1::Create {path, permissions, owner, group,
+                    replication, block_size, client_name, time} =>
+                Operation::Create { path: path, permissions: permissions,
+                    owner: owner, group: group, replication: replication,
+                    block_size: block_size, client_name: client_name,
+                    time: time },
+            OperationV1::Delete {path, recursive, time} =>
+                Operation::Delete { path: path,
+                    recursive: recursive, time: time },
+            OperationV1::Mkdirs {path, permissions, owner, group,
+                    create_parent, time} =>
+                Operation::Mkdirs { path: path, permissions: permissions,
+                    owner: owner, group: group,
+                    create_parent: create_parent, time: time },
+            OperationV1::RemoveBlock {path, block_id} =>
+                Operation::RemoveBlock { path: path, block_id: block_id },
+            OperationV1::Rename {src_path, dst_path, time} =>
+                Operation::Rename { src_path: src_path,
+                    dst_path: dst_path, time: time },
+            OperationV1::SetClientName {path, client_name} =>
+                Operation::SetClientName { path: path,
+                    client_name: client_name },
+            OperationV1::SetOwner {path, owner, group} =>
+                Operation::SetOwner { path: path,
+                    owner: owner, group: group },
+            OperationV1::SetPermission {path, permissions} =>
+                Operation::SetPermission { path: path,
+                    permissions: permissions },
+            OperationV1::SetStoragePolicy {path, storage_policy} =>
+                Operation::SetStoragePolicy { path: path,
+                    storage_policy: storage_policy },
+            OperationV1::SetTimes {path, modification_time, access_time} =>
+                Operation::SetTimes { path: path,
+                    modification_time: modification_time,
+                    access_time: access_time },
+        }
+    }
+}
@@ -1,9 +1,17 @@
 use serde::{Deserialize, Serialize};
 
+use crate::file::acl::AclEntry;
+
+use std::collections::BTreeMap;
+
+pub mod acl;
+pub mod journal;
+pub mod legacy;
+pub mod permission;
 mod store;
-pub use store::FileStore;
+pub use store::{get_stored_block_id, is_snapshot_path, FileStore};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub enum FileType {
     Directory,
     Regular { blocks: Vec<u64>, replication: u32, block_size: u64 },
@@ -25,6 +33,18 @@ impl FileType {
 }
 
 #[derive(Deserialize, Serialize)]
+pub struct Quota {
+    pub namespace: Option<u64>,
+    pub space: Option<u64>,
+    // subtree usage is cached rather than persisted - it is rebuilt
+    //  when the image is read and maintained by each mutation
+    #[serde(skip)]
+    pub namespace_consumed: u64,
+    #[serde(skip)]
+    pub space_consumed: u64,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct File {
     inode: u64,
     file_type: FileType,
@@ -33,12 +53,18 @@ pub struct File {
     group: String,
     permissions: u32,
     storage_policy: Option<u32>,
+    modification_time: u64,
+    access_time: u64,
+    client_name: Option<String>,
+    xattrs: BTreeMap<String, Vec<u8>>,
+    acl: Vec<AclEntry>,
 }
 
 impl File {
     pub fn new(inode: u64, file_type: FileType, name: String,
             owner: String, group: String, permissions: u32,
-            storage_policy: Option<u32>) -> File {
+            storage_policy: Option<u32>, modification_time: u64,
+            access_time: u64, client_name: Option<String>) -> File {
         File {
             inode: inode,
             file_type: file_type,
@@ -47,15 +73,28 @@ impl File {
             group: group,
             permissions: permissions,
             storage_policy: storage_policy,
+            modification_time: modification_time,
+            access_time: access_time,
+            client_name: client_name,
+            xattrs: BTreeMap::new(),
+            acl: Vec::new(),
         }
     }
 
-    pub fn get_file_type(&self) -> &FileType {
-        &self.file_type
+    pub fn get_access_time(&self) -> u64 {
+        self.access_time
+    }
+
+    pub fn get_acl(&self) -> &Vec<AclEntry> {
+        &self.acl
     }
 
-    pub fn get_file_type_mut(&mut self) -> &mut FileType {
-        &mut self.file_type
+    pub fn get_client_name(&self) -> Option<&str> {
+        self.client_name.as_ref().map(|x| x.as_str())
+    }
+
+    pub fn get_file_type(&self) -> &FileType {
+        &self.file_type
     }
 
     pub fn get_file_type_code(&self) -> i32 {
@@ -73,6 +112,14 @@ impl File {
         self.inode
     }
 
+    pub fn get_modification_time(&self) -> u64 {
+        self.modification_time
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
     pub fn get_owner(&self) -> &str {
         &self.owner
     }
@@ -84,4 +131,12 @@ impl File {
     pub fn get_storage_policy(&self) -> &Option<u32> {
         &self.storage_policy
     }
+
+    pub fn get_xattrs(&self) -> &BTreeMap<String, Vec<u8>> {
+        &self.xattrs
+    }
+
+    pub fn is_under_construction(&self) -> bool {
+        self.client_name.is_some()
+    }
 }
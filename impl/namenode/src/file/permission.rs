@@ -0,0 +1,182 @@
+use crate::file::File;
+use crate::file::acl::{AclEntry, AclScope, AclType};
+
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+pub static READ: u32 = 4;
+pub static WRITE: u32 = 2;
+pub static EXECUTE: u32 = 1;
+pub static ALL: u32 = 7;
+
+pub static STICKY_BIT: u32 = 512;
+static GROUPS_TIMEOUT_SECS: u64 = 300;
+
+pub struct User {
+    name: String,
+    groups: Vec<String>,
+    superuser: bool,
+}
+
+impl User {
+    pub fn new(name: String, groups: Vec<String>,
+            superuser: bool) -> User {
+        User {
+            name: name,
+            groups: groups,
+            superuser: superuser,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_member(&self, group: &str) -> bool {
+        self.groups.iter().any(|value| value == group)
+    }
+
+    pub fn is_superuser(&self) -> bool {
+        self.superuser
+    }
+}
+
+pub struct UserStore {
+    superuser: String,
+    supergroup: String,
+    groups: RwLock<HashMap<String, (Instant, Vec<String>)>>,
+}
+
+impl UserStore {
+    pub fn new(superuser: &str, supergroup: &str) -> UserStore {
+        UserStore {
+            superuser: superuser.to_string(),
+            supergroup: supergroup.to_string(),
+            groups: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn get_supergroup(&self) -> &str {
+        &self.supergroup
+    }
+
+    pub fn get_superuser(&self) -> &str {
+        &self.superuser
+    }
+
+    pub fn get_user(&self, name: &str) -> User {
+        // check for unexpired cached groups
+        let cached_groups = {
+            let map = self.groups.read().unwrap();
+            match map.get(name) {
+                Some((instant, groups)) if instant.elapsed()
+                        < Duration::from_secs(GROUPS_TIMEOUT_SECS) =>
+                    Some(groups.clone()),
+                _ => None,
+            }
+        };
+
+        // if not cached -> lookup and cache groups
+        let groups = match cached_groups {
+            Some(groups) => groups,
+            None => {
+                let groups = lookup_groups(name);
+                let mut map = self.groups.write().unwrap();
+                map.insert(name.to_string(),
+                    (Instant::now(), groups.clone()));
+                groups
+            },
+        };
+
+        let superuser = name == self.superuser
+            || groups.contains(&self.supergroup);
+        User::new(name.to_string(), groups, superuser)
+    }
+}
+
+fn lookup_groups(name: &str) -> Vec<String> {
+    // names are supplied by clients -> never parse them as options
+    if name.starts_with("-") {
+        debug!("invalid user name '{}'", name);
+        return Vec::new();
+    }
+
+    // resolve groups using the local os user database
+    match Command::new("id").args(&["-Gn", "--", name]).output() {
+        Ok(ref output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace().map(|x| x.to_string()).collect()
+        },
+        Ok(_) => {
+            debug!("no groups found for user '{}'", name);
+            Vec::new()
+        },
+        Err(e) => {
+            warn!("failed to lookup groups for user '{}': {}", name, e);
+            Vec::new()
+        },
+    }
+}
+
+pub fn has_access(file: &File, user: &User, access: u32) -> bool {
+    if user.is_superuser() {
+        return true;
+    }
+
+    // retrieve permission bits for the users class
+    let permissions = file.get_permissions();
+    if file.get_owner() == user.get_name() {
+        return (permissions >> 6) & ALL & access == access;
+    }
+
+    // extended acl entries are limited by the mask in the group bits
+    let mask = (permissions >> 3) & ALL;
+    let entries: Vec<&AclEntry> = file.get_acl().iter()
+        .filter(|entry| entry.scope == AclScope::Access).collect();
+    if let Some(entry) = entries.iter().find(|entry| entry.acl_type
+            == AclType::User && entry.name.as_ref()
+                .map(|x| x.as_str()) == Some(user.get_name())) {
+        return entry.permissions & mask & access == access;
+    }
+
+    // access is granted if any matching group entry grants it
+    let groups: Vec<(&str, u32)> = match entries.is_empty() {
+        true => vec!((file.get_group(), mask)),
+        false => entries.iter()
+            .filter(|entry| entry.acl_type == AclType::Group)
+            .map(|entry| (entry.name.as_ref().map(|x| x.as_str())
+                .unwrap_or(file.get_group()), entry.permissions & mask))
+            .collect(),
+    };
+
+    let modes: Vec<u32> = groups.iter()
+        .filter(|(group, _)| user.is_member(group))
+        .map(|(_, mode)| *mode).collect();
+    match modes.is_empty() {
+        true => permissions & ALL & access == access,
+        false => modes.iter().any(|mode| mode & access == access),
+    }
+}
+
+pub fn is_sticky_restricted(parent: &File,
+        file: &File, user: &User) -> bool {
+    parent.get_permissions() & STICKY_BIT == STICKY_BIT
+        && !user.is_superuser()
+        && parent.get_owner() != user.get_name()
+        && file.get_owner() != user.get_name()
+}
+
+pub fn to_access_string(access: u32) -> &'static str {
+    match access & ALL {
+        0 => "NONE",
+        1 => "EXECUTE",
+        2 => "WRITE",
+        3 => "WRITE_EXECUTE",
+        4 => "READ",
+        5 => "READ_EXECUTE",
+        6 => "READ_WRITE",
+        _ => "ALL",
+    }
+}
@@ -1,15 +1,54 @@
 use serde::{Deserialize, Serialize};
+use shared::NahFSError;
+use shared::policy::StoragePolicy;
 
-use crate::file::{File, FileType};
+use crate::block::BlockStore;
+use crate::file::{File, FileType, Quota};
+use crate::file::acl::{self, AclEntry};
+use crate::file::legacy::{FileStoreV8, FileStoreV9};
+use crate::file::permission::{self, User};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+
+static FIRST_BIT: u64 = 9223372036854775808;
+static INDEXED_MASK: u64 = 9223371968135299072;
+static NON_INDEXED_MASK: u64 = 9223372036854775807;
+static INDEXED_SHIFT: u64 = 36;
+
+// generation stamps up to this value are reserved
+static RESERVED_GENERATION_STAMP: u64 = 1000;
+static ROOT_INODE: u64 = 2;
+static SNAPSHOT_DIRECTORY: &str = ".snapshot";
+static XATTR_LIMIT: usize = 32;
+static XATTR_SIZE_LIMIT: usize = 16384;
 
 #[derive(Deserialize, Serialize)]
 pub struct FileStore {
     inodes: HashMap<u64, File>,
-    children: HashMap<u64, Vec<u64>>,
+    children: HashMap<u64, BTreeMap<String, u64>>,
     parents: HashMap<u64, u64>,
+    quotas: HashMap<u64, Quota>,
+    snapshot_directories: HashMap<u64, u64>,
     storage_policies: HashMap<u32, String>,
+    block_lengths: HashMap<u64, u64>,
+    last_block_id: u64,
+    last_indexed_block_id: u64,
+    last_generation_stamp: u64,
+    last_inode: u64,
+    last_storage_policy_id: u32,
+    // snapshots share inodes with the live namespace until they are
+    //  modified - references are rebuilt when the image is read
+    #[serde(skip)]
+    block_references: HashMap<u64, Vec<u64>>,
+    #[serde(skip)]
+    snapshot_inodes: HashSet<u64>,
+    #[serde(skip)]
+    snapshot_parents: HashMap<u64, Vec<u64>>,
+    // inodes which directly reference each storage policy - rebuilt
+    //  when the image is read
+    #[serde(skip)]
+    storage_policy_inodes: HashMap<u32, HashSet<u64>>,
 }
 
 impl FileStore {
@@ -19,46 +58,473 @@ impl FileStore {
         let mut children = HashMap::new();
 
         // create root node
-        let root_inode = File::new(2, FileType::directory(),
+        let root_inode = File::new(ROOT_INODE, FileType::directory(),
             "".to_string(), "root".to_string(),
-            "root".to_string(), 0, None);
-        inodes.insert(2, root_inode);
-        children.insert(2, Vec::new());
+            "root".to_string(), 493, None, 0, 0, None);
+        inodes.insert(ROOT_INODE, root_inode);
+        children.insert(ROOT_INODE, BTreeMap::new());
 
         FileStore {
             inodes: inodes,
             children: children,
             parents: HashMap::new(),
+            quotas: HashMap::new(),
+            snapshot_directories: HashMap::new(),
             storage_policies: HashMap::new(),
+            block_lengths: HashMap::new(),
+            last_block_id: 0,
+            last_indexed_block_id: 0,
+            last_generation_stamp: RESERVED_GENERATION_STAMP,
+            last_inode: ROOT_INODE,
+            last_storage_policy_id: 0,
+            block_references: HashMap::new(),
+            snapshot_inodes: HashSet::new(),
+            snapshot_parents: HashMap::new(),
+            storage_policy_inodes: HashMap::new(),
         }
     }
 
-    pub fn create(&mut self, path: &str, permissions: u32, owner: &str,
-            group: &str, replication: u32, block_size: u64) {
-        // find longest path match
-        let components = parse_path(path);
-        let (inode, match_length) = self.get_longest_match(&components);
+    pub fn from_v8(file_store: FileStoreV8, time: u64,
+            block_store: &BlockStore) -> FileStore {
+        // ids were randomly allocated - allocators skip ids in use and
+        //  generation stamps were allocated from the current time
+        FileStore::from_v9(FileStoreV9 {
+            inodes: file_store.inodes,
+            children: file_store.children,
+            parents: file_store.parents,
+            quotas: file_store.quotas,
+            snapshot_directories: file_store.snapshot_directories,
+            storage_policies: file_store.storage_policies,
+            last_block_id: 0,
+            last_indexed_block_id: 0,
+            last_generation_stamp: time,
+            last_inode: ROOT_INODE,
+            last_storage_policy_id: 0,
+        }, block_store)
+    }
 
-        // check if directories are valid
-        if match_length == components.len() {
-            return; // directory already exists
-        } else if components.len() >= 1
-                && match_length < components.len() - 1 { 
-            return; // need to, and unable to create parents
+    pub fn from_v9(file_store: FileStoreV9,
+            block_store: &BlockStore) -> FileStore {
+        // commit reported lengths of blocks belonging to complete files
+        let mut block_lengths = HashMap::new();
+        for file in file_store.inodes.values() {
+            if let (None, FileType::Regular {blocks, replication: _,
+                    block_size: _}) = (&file.client_name, &file.file_type) {
+                for block_id in blocks.iter() {
+                    if let Some(block) = block_store.get_block(block_id) {
+                        block_lengths.insert(*block_id, block.length);
+                    }
+                }
+            }
+        }
+
+        FileStore {
+            inodes: file_store.inodes,
+            children: file_store.children,
+            parents: file_store.parents,
+            quotas: file_store.quotas,
+            snapshot_directories: file_store.snapshot_directories,
+            storage_policies: file_store.storage_policies,
+            block_lengths: block_lengths,
+            last_block_id: file_store.last_block_id,
+            last_indexed_block_id: file_store.last_indexed_block_id,
+            last_generation_stamp: file_store.last_generation_stamp,
+            last_inode: file_store.last_inode,
+            last_storage_policy_id: file_store.last_storage_policy_id,
+            block_references: HashMap::new(),
+            snapshot_inodes: HashSet::new(),
+            snapshot_parents: HashMap::new(),
+            storage_policy_inodes: HashMap::new(),
+        }
+    }
+
+    pub fn add_block(&mut self, path: &str, block_id: u64,
+            generation_stamp: u64, time: u64) -> Result<(), NahFSError> {
+        // advance allocators past ids of replayed operations
+        if block_id & FIRST_BIT == FIRST_BIT {
+            self.last_indexed_block_id = std::cmp::max(
+                self.last_indexed_block_id,
+                (block_id & INDEXED_MASK) >> INDEXED_SHIFT);
+        } else {
+            self.last_block_id =
+                std::cmp::max(self.last_block_id, block_id);
+        }
+
+        self.last_generation_stamp =
+            std::cmp::max(self.last_generation_stamp, generation_stamp);
+
+        let (inode, space) = self.get_file_space(path)?;
+        if self.is_directory(inode) {
+            return Err(NahFSError::FileNotFoundException(format!(
+                "Path is not a file: {}", path)));
+        }
+
+        let file = self.get_inode_mut(inode);
+        if let FileType::Regular {blocks, replication: _, block_size: _} =
+                &mut file.file_type {
+            blocks.push(block_id);
+            file.modification_time = time;
+        }
+
+        self.reference_blocks(inode, &[block_id]);
+        self.update_file_usage(inode, space);
+        Ok(())
+    }
+
+    pub fn allocate_block_id(&mut self, indexed: bool,
+            block_store: &BlockStore) -> Result<u64, NahFSError> {
+        // indexed block ids leave the low bits for the storage policy
+        loop {
+            let block_id = match indexed {
+                true if self.last_indexed_block_id
+                        < INDEXED_MASK >> INDEXED_SHIFT => {
+                    self.last_indexed_block_id += 1;
+                    FIRST_BIT | (self.last_indexed_block_id << INDEXED_SHIFT)
+                },
+                false if self.last_block_id < NON_INDEXED_MASK => {
+                    self.last_block_id += 1;
+                    self.last_block_id
+                },
+                _ => return Err(NahFSError::from(format!(
+                    "Failed to allocate block id: ids exhausted"))),
+            };
+
+            // skip ids of blocks allocated by legacy layouts
+            if block_store.get_block(&block_id).is_none() {
+                return Ok(block_id);
+            }
+        }
+    }
+
+    pub fn allocate_generation_stamp(&mut self) -> u64 {
+        self.last_generation_stamp += 1;
+        self.last_generation_stamp
+    }
+
+    fn allocate_inode(&mut self) -> u64 {
+        // skip inodes allocated by legacy layouts
+        self.last_inode += 1;
+        while self.inodes.contains_key(&self.last_inode) {
+            self.last_inode += 1;
+        }
+
+        self.last_inode
+    }
+
+    fn allocate_storage_policy_id(&mut self) -> u32 {
+        self.last_storage_policy_id += 1;
+        while self.storage_policies
+                .contains_key(&self.last_storage_policy_id) {
+            self.last_storage_policy_id += 1;
+        }
+
+        self.last_storage_policy_id
+    }
+
+    pub fn allow_snapshot(&mut self,
+            path: &str) -> Result<(), NahFSError> {
+        let inode = self.get_directory_inode(path)?;
+        if self.snapshot_directories.contains_key(&inode) {
+            return Ok(()); // directory is already snapshottable
+        }
+
+        // create hidden snapshot directory - which is not a child of
+        //  the directory but is resolved through the reserved name
+        let snapshot_inode = self.allocate_inode();
+        let file = self.inodes.get(&inode).unwrap();
+        let snapshot_file = File::new(snapshot_inode, FileType::directory(),
+            SNAPSHOT_DIRECTORY.to_string(), file.owner.clone(),
+            file.group.clone(), file.permissions, None,
+            file.modification_time, file.access_time, None);
+
+        self.inodes.insert(snapshot_inode, snapshot_file);
+        self.parents.insert(snapshot_inode, inode);
+        self.children.insert(snapshot_inode, BTreeMap::new());
+        self.snapshot_directories.insert(inode, snapshot_inode);
+        self.snapshot_inodes.insert(snapshot_inode);
+        Ok(())
+    }
+
+    pub fn commit_block(&mut self, path: &str, block_id: u64,
+            length: u64) -> Result<(), NahFSError> {
+        // record the client committed length of a written block
+        let (inode, space) = self.get_file_space(path)?;
+        match &self.inodes.get(&inode).unwrap().file_type {
+            FileType::Regular {blocks, replication: _, block_size: _}
+                if blocks.contains(&block_id) => {},
+            _ => return Err(NahFSError::from(format!(
+                "Block {} does not belong to file {}", block_id, path))),
+        }
+
+        // committing the block being written changes snapshot views
+        self.copy_on_write(inode);
+        self.block_lengths.insert(block_id, length);
+        self.update_file_usage(inode, space);
+        Ok(())
+    }
+
+    pub fn complete(&mut self, path: &str,
+            time: u64) -> Result<(), NahFSError> {
+        let (inode, space) = self.get_file_space(path)?;
+        let file = self.get_inode_mut(inode);
+        file.modification_time = time;
+        file.client_name = None;
+
+        self.update_file_usage(inode, space);
+        Ok(())
+    }
+
+    pub fn create(&mut self, path: &str, permissions: u32, owner: &str,
+            group: &str, replication: u32, block_size: u64,
+            client_name: &str, time: u64) -> Result<(), NahFSError> {
+        // check if path is valid
+        validate_path(path)?;
+        if self.get_file(path).is_some() {
+            return Err(NahFSError::FileAlreadyExistsException(format!(
+                "{} for client {} already exists", path, client_name)));
         }
 
+        // find parent directory
+        let components = parse_path(path);
+        let inode = self.get_parent_inode(&components)?;
+        self.check_quota(inode, 1, 0, &HashSet::new())?;
+        self.copy_on_write(inode);
+
         // create file
-        let child_inode = rand::random::<u64>();
+        let child_inode = self.allocate_inode();
         let filename = components[components.len() - 1].to_string();
-        let child_file = File::new(child_inode,
-            FileType::regular(replication, block_size), filename, 
-            owner.to_string(), group.to_string(), permissions, None);
+        let mut child_file = File::new(child_inode,
+            FileType::regular(replication, block_size), filename.clone(),
+            owner.to_string(), group.to_string(), permissions,
+            None, time, time, Some(client_name.to_string()));
+        self.inherit_acl(inode, &mut child_file, false);
 
         // update data inode data structures
         self.inodes.insert(child_inode, child_file);
         self.parents.insert(child_inode, inode);
-        self.children.get_mut(&inode).unwrap().push(child_inode);
-        self.children.insert(child_inode, Vec::new());
+        self.children.get_mut(&inode).unwrap().insert(filename, child_inode);
+        self.children.insert(child_inode, BTreeMap::new());
+        self.set_modification_time(inode, time);
+        self.update_usage(inode, 1, 0);
+        Ok(())
+    }
+
+    pub fn create_snapshot(&mut self, path: &str, name: &str,
+            time: u64) -> Result<(), NahFSError> {
+        // check if snapshot is valid
+        let inode = self.get_directory_inode(path)?;
+        let snapshot_inode = self.get_snapshot_inode(inode, path)?;
+        if name.is_empty() || name.contains("/")
+                || validate_path(&format!("/{}", name)).is_err() {
+            return Err(NahFSError::SnapshotException(format!(
+                "Invalid snapshot name: {}", name)));
+        } else if self.children.get(&snapshot_inode)
+                .unwrap().contains_key(name) {
+            return Err(NahFSError::SnapshotException(format!(
+                "Failed to add snapshot: there is already a snapshot \
+                with the same name \"{}\".", name)));
+        }
+
+        // copy the directory - children are shared with the live
+        //  namespace and copied when they are modified
+        let copy_inode = self.allocate_inode();
+        let mut file = self.inodes.get(&inode).unwrap().clone();
+        file.inode = copy_inode;
+        file.name = name.to_string();
+        file.modification_time = time;
+
+        let children = self.children.get(&inode).unwrap().clone();
+        for child_inode in children.values() {
+            self.snapshot_parents.entry(*child_inode)
+                .or_insert(Vec::new()).push(copy_inode);
+        }
+
+        self.reference_storage_policy(copy_inode, file.storage_policy);
+        self.inodes.insert(copy_inode, file);
+        self.parents.insert(copy_inode, snapshot_inode);
+        self.children.get_mut(&snapshot_inode)
+            .unwrap().insert(name.to_string(), copy_inode);
+        self.children.insert(copy_inode, children);
+        self.snapshot_inodes.insert(copy_inode);
+        self.snapshot_parents.insert(copy_inode, vec!(snapshot_inode));
+
+        self.set_modification_time(snapshot_inode, time);
+        Ok(())
+    }
+
+    pub fn check_owner(&self, path: &str,
+            user: &User) -> Result<(), NahFSError> {
+        // check traverse access
+        self.check_permission(path, user, 0, 0, 0)?;
+        if user.is_superuser() {
+            return Ok(());
+        }
+
+        // check if user owns file
+        match self.get_file(path) {
+            Some(file) if file.get_owner() != user.get_name() =>
+                Err(NahFSError::AccessControlException(format!(
+                    "Permission denied. user={} is not the owner of \
+                    inode={}", user.get_name(), path))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_permission(&self, path: &str, user: &User,
+            ancestor_access: u32, access: u32, sub_access: u32)
+            -> Result<(), NahFSError> {
+        if user.is_superuser() {
+            return Ok(());
+        }
+
+        // resolve inodes along path
+        let components = parse_path(path);
+        let inodes = self.get_path_inodes(&components);
+        let exists = inodes.len() == components.len() + 1;
+        let ancestor_count = match exists {
+            true => inodes.len() - 1,
+            false => inodes.len(),
+        };
+
+        // check traverse access on ancestors
+        for inode in inodes[..ancestor_count].iter() {
+            self.check_access(*inode, user, permission::EXECUTE)?;
+        }
+
+        // check access on closest ancestor
+        if ancestor_count > 0 {
+            let ancestor_inode = inodes[ancestor_count - 1];
+            self.check_access(ancestor_inode, user, ancestor_access)?;
+
+            // check sticky bit on modifications of existing files
+            if exists && ancestor_access & permission::WRITE != 0 {
+                let ancestor = self.inodes.get(&ancestor_inode).unwrap();
+                let file = self.inodes.get(inodes.last().unwrap()).unwrap();
+                if permission::is_sticky_restricted(ancestor, file, user) {
+                    return Err(NahFSError::AccessControlException(format!(
+                        "Permission denied by sticky bit: user={}, \
+                        inode={}", user.get_name(), path)));
+                }
+            }
+        }
+
+        // check access on file and subtree
+        if exists {
+            let inode = *inodes.last().unwrap();
+            self.check_access(inode, user, access)?;
+
+            if sub_access != 0 {
+                let mut directories = vec!(inode);
+                while let Some(directory) = directories.pop() {
+                    let children = match self.children.get(&directory) {
+                        Some(children) if children.len() != 0 => children,
+                        _ => continue, // ignore files and empty directories
+                    };
+
+                    self.check_access(directory, user, sub_access)?;
+                    directories.extend(children.values());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_access(&self, inode: u64, user: &User,
+            access: u32) -> Result<(), NahFSError> {
+        let file = self.inodes.get(&inode).unwrap();
+        match permission::has_access(file, user, access) {
+            true => Ok(()),
+            false => Err(NahFSError::AccessControlException(format!(
+                "Permission denied: user={}, access={}, \
+                inode=\"{}\":{}:{}:{:o}", user.get_name(),
+                permission::to_access_string(access),
+                self.compute_path(inode), file.get_owner(),
+                file.get_group(), file.get_permissions()))),
+        }
+    }
+
+    fn check_quota(&self, inode: u64, namespace: u64, space: u64,
+            common_inodes: &HashSet<u64>) -> Result<(), NahFSError> {
+        // check quotas of directory and its ancestors - stopping at
+        //  ancestors whose usage is unchanged (ex. rename source parents)
+        let mut current_inode = Some(inode);
+        while let Some(inode) = current_inode {
+            if common_inodes.contains(&inode) {
+                break;
+            }
+
+            if let Some(quota) = self.quotas.get(&inode) {
+                let file_count = quota.namespace_consumed + namespace;
+                let consumed = quota.space_consumed + space;
+                match (quota.namespace, quota.space) {
+                    (Some(quota), _) if namespace != 0
+                            && file_count > quota =>
+                        return Err(NahFSError::QuotaExceededException(
+                            format!("The NameSpace quota (directories and \
+                            files) of directory {} is exceeded: quota={} \
+                            file count={}", self.compute_path(inode),
+                            quota, file_count))),
+                    (_, Some(quota)) if space != 0 && consumed > quota =>
+                        return Err(NahFSError::QuotaExceededException(
+                            format!("The DiskSpace quota of {} is \
+                            exceeded: quota = {} B but diskspace consumed \
+                            = {} B", self.compute_path(inode),
+                            quota, consumed))),
+                    _ => {},
+                }
+            }
+
+            current_inode = self.parents.get(&inode).map(|x| *x);
+        }
+
+        Ok(())
+    }
+
+    fn check_rename_quota(&self, src_inode: u64, dst_parent_inode: u64,
+            released: (u64, u64)) -> Result<(), NahFSError> {
+        // usage of ancestors common to the source is unchanged
+        let mut common_inodes = HashSet::new();
+        let mut current_inode = self.parents.get(&src_inode);
+        while let Some(inode) = current_inode {
+            common_inodes.insert(*inode);
+            current_inode = self.parents.get(inode);
+        }
+
+        // overwritten destinations release their usage
+        let (namespace, space) = self.compute_usage(src_inode);
+        self.check_quota(dst_parent_inode,
+            namespace.saturating_sub(released.0),
+            space.saturating_sub(released.1), &common_inodes)
+    }
+
+    pub fn check_space_quota(&self, path: &str,
+            space: u64) -> Result<(), NahFSError> {
+        let inode = self.get_live_inode(path)?;
+        self.check_quota(inode, 0, space, &HashSet::new())
+    }
+
+    fn compute_file_space(&self, file: &File) -> u64 {
+        match &file.file_type {
+            FileType::Regular {blocks, replication, block_size} => {
+                // uncommitted blocks and the last block of files under
+                //  construction are charged the full block size
+                let mut space = 0;
+                for (i, block_id) in blocks.iter().enumerate() {
+                    let length = match self.block_lengths.get(block_id) {
+                        Some(_) if file.is_under_construction()
+                            && i == blocks.len() - 1 => *block_size,
+                        Some(length) => *length,
+                        None => *block_size,
+                    };
+
+                    space += length * *replication as u64;
+                }
+
+                space
+            },
+            FileType::Directory => 0,
+        }
     }
 
     pub fn compute_path(&self, inode: u64) -> String {
@@ -84,58 +550,395 @@ impl FileStore {
         path
     }
 
+    fn compute_usage(&self, inode: u64) -> (u64, u64) {
+        // count the subtree and its space - where quota directories
+        //  contribute their cached usage
+        let (mut namespace, mut space) = (0, 0);
+        let mut inodes = vec!(inode);
+        while let Some(current_inode) = inodes.pop() {
+            if let Some(quota) = self.quotas.get(&current_inode) {
+                namespace += quota.namespace_consumed;
+                space += quota.space_consumed;
+                continue;
+            }
+
+            if let Some(children) = self.children.get(&current_inode) {
+                inodes.extend(children.values());
+            }
+
+            let file = self.inodes.get(&current_inode).unwrap();
+            namespace += 1;
+            space += self.compute_file_space(file);
+        }
+
+        (namespace, space)
+    }
+
+    fn copy_on_write(&mut self, inode: u64) {
+        // copy inodes shared with snapshots before they are modified
+        //  - ancestors first so snapshots reference the inode directly
+        if self.snapshot_parents.is_empty() {
+            return;
+        }
+
+        if let Some(parent_inode) = self.parents.get(&inode).map(|x| *x) {
+            self.copy_on_write(parent_inode);
+        }
+
+        let parent_inodes = match self.snapshot_parents.remove(&inode) {
+            Some(parent_inodes) => parent_inodes,
+            None => return,
+        };
+
+        // the copy excludes the block being written
+        let copy_inode = self.allocate_inode();
+        let file = self.inodes.get(&inode).unwrap();
+        let block_count = self.get_snapshot_blocks(file).len();
+        let mut file = file.clone();
+        file.inode = copy_inode;
+        file.client_name = None;
+        if let FileType::Regular {blocks, replication: _, block_size: _} =
+                &mut file.file_type {
+            blocks.truncate(block_count);
+            self.reference_blocks(copy_inode, blocks);
+        }
+
+        let children = self.children.get(&inode).unwrap().clone();
+        for child_inode in children.values() {
+            self.snapshot_parents.entry(*child_inode)
+                .or_insert(Vec::new()).push(copy_inode);
+        }
+
+        // replace the inode in referencing snapshot directories
+        for parent_inode in parent_inodes.iter() {
+            self.children.get_mut(parent_inode)
+                .unwrap().insert(file.name.clone(), copy_inode);
+        }
+
+        self.reference_storage_policy(copy_inode, file.storage_policy);
+        self.inodes.insert(copy_inode, file);
+        self.parents.insert(copy_inode, parent_inodes[0]);
+        self.children.insert(copy_inode, children);
+        self.snapshot_inodes.insert(copy_inode);
+        self.snapshot_parents.insert(copy_inode, parent_inodes);
+    }
+
+    fn get_directory_inode(&self, path: &str) -> Result<u64, NahFSError> {
+        let inode = self.get_live_inode(path)?;
+        if !self.is_directory(inode) {
+            Err(NahFSError::SnapshotException(format!(
+                "Path is not a directory: {}", path)))
+        } else {
+            Ok(inode)
+        }
+    }
+
     pub fn get_file(&self, path: &str) -> Option<&File> {
+        // resolve inodes along path - including snapshots
         let components = parse_path(path);
-        let (inode, match_length) = self.get_longest_match(&components);
-        if match_length != components.len() {
-            return None;
+        let inodes = self.get_path_inodes(&components);
+        match inodes.len() == components.len() + 1 {
+            true => self.inodes.get(inodes.last().unwrap()),
+            false => None,
         }
+    }
+
+    pub fn get_file_by_inode(&self, inode: u64) -> Option<&File> {
+        self.inodes.get(&inode)
+    }
+
+    pub fn get_file_mut(&mut self,
+            path: &str) -> Result<&mut File, NahFSError> {
+        let inode = self.get_live_inode(path)?;
+        Ok(self.get_inode_mut(inode))
+    }
+
+    fn get_file_space(&self, path: &str) -> Result<(u64, u64), NahFSError> {
+        // resolve live file inode and its charged space
+        let inode = self.get_live_inode(path)?;
+        let file = self.inodes.get(&inode).unwrap();
+        Ok((inode, self.compute_file_space(file)))
+    }
 
-        Some(self.inodes.get(&inode).unwrap())
+    fn get_inode_mut(&mut self, inode: u64) -> &mut File {
+        self.copy_on_write(inode);
+        self.inodes.get_mut(&inode).unwrap()
     }
 
-    pub fn get_file_mut(&mut self, path: &str) -> Option<&mut File> {
+    fn get_live_inode(&self, path: &str) -> Result<u64, NahFSError> {
+        // snapshots are read-only
+        if is_snapshot_path(path) {
+            return Err(snapshot_modification());
+        }
+
         let components = parse_path(path);
         let (inode, match_length) = self.get_longest_match(&components);
-        if match_length != components.len() {
-            return None;
+        match match_length == components.len() {
+            true => Ok(inode),
+            false => Err(file_not_found(path)),
+        }
+    }
+
+    pub fn delete(&mut self, path: &str, recursive: bool,
+            time: u64) -> Result<Vec<u64>, NahFSError> {
+        // check if file is valid
+        let components = parse_path(path);
+        let inode = self.get_live_inode(path)?;
+        if components.len() == 0 {
+            return Err(NahFSError::InvalidPathException(format!(
+                "Invalid path name: deleting root is not allowed")));
+        } else if !recursive && self.get_children_count(inode) != 0 {
+            return Err(NahFSError::PathIsNotEmptyDirectoryException(
+                format!("`{} is non empty': Directory is not empty", path)));
+        }
+
+        // snapshotted directories may not be deleted
+        if self.snapshot_directories.len() != 0 {
+            let mut inodes = vec!(inode);
+            while let Some(current_inode) = inodes.pop() {
+                if let Some(snapshot_inode) =
+                        self.snapshot_directories.get(&current_inode) {
+                    if self.get_children_count(*snapshot_inode) != 0 {
+                        let snapshot_path = self.compute_path(current_inode);
+                        return Err(NahFSError::SnapshotException(format!(
+                            "The directory {} cannot be deleted since {} \
+                            is snapshottable and already has snapshots",
+                            snapshot_path, snapshot_path)));
+                    }
+                }
+
+                inodes.extend(self.children.get(&current_inode)
+                    .unwrap().values());
+            }
+        }
+
+        // remove file from parent children - releasing subtree usage
+        let (namespace, space) = self.compute_usage(inode);
+        let parent_inode = *self.parents.get(&inode).unwrap();
+        self.copy_on_write(parent_inode);
+        self.children.get_mut(&parent_inode)
+            .unwrap().remove(components[components.len() - 1]);
+        self.set_modification_time(parent_inode, time);
+        self.update_usage(parent_inode, -(namespace as i64), -(space as i64));
+
+        // blocks referenced by snapshots are retained
+        let block_ids = self.remove_subtree(inode);
+        for block_id in block_ids.iter() {
+            self.block_lengths.remove(block_id);
+        }
+
+        Ok(block_ids)
+    }
+
+    pub fn delete_snapshot(&mut self, path: &str,
+            name: &str) -> Result<Vec<u64>, NahFSError> {
+        let inode = self.get_directory_inode(path)?;
+        let snapshot_inode = self.get_snapshot_inode(inode, path)?;
+        let root_inode = match self.children.get_mut(&snapshot_inode)
+                .unwrap().remove(name) {
+            Some(root_inode) => root_inode,
+            None => return Err(NahFSError::SnapshotException(format!(
+                "Cannot delete snapshot {} from path {}: the snapshot \
+                does not exist.", name, path))),
+        };
+
+        // blocks referenced by live files or other snapshots are retained
+        let block_ids = self.release_snapshot(root_inode, snapshot_inode);
+        for block_id in block_ids.iter() {
+            self.block_lengths.remove(block_id);
+        }
+
+        Ok(block_ids)
+    }
+
+    fn freeze_file(&mut self, inode: u64) -> Vec<u64> {
+        // files retained by snapshots drop the block being written
+        let file = self.inodes.get(&inode).unwrap();
+        let block_count = self.get_snapshot_blocks(file).len();
+        let file = self.inodes.get_mut(&inode).unwrap();
+        file.client_name = None;
+        match &mut file.file_type {
+            FileType::Regular {blocks, replication: _, block_size: _} => {
+                let block_ids = blocks.split_off(block_count);
+                self.release_blocks(inode, &block_ids)
+            },
+            FileType::Directory => Vec::new(),
         }
+    }
+
+    pub fn get_block_length(&self, block_id: &u64) -> Option<u64> {
+        self.block_lengths.get(block_id).map(|x| *x)
+    }
+
+    pub fn get_block_ids(&self) -> HashSet<u64> {
+        self.block_references.keys().map(|x| *x).collect()
+    }
 
-        Some(self.inodes.get_mut(&inode).unwrap())
+    pub fn get_block_replication(&self, block_id: &u64) -> Option<u32> {
+        // blocks shared with snapshots use the largest replication
+        //  - blocks of files under construction are not managed
+        let inodes = self.block_references.get(block_id)?;
+        inodes.iter().filter_map(|inode| self.inodes.get(inode))
+            .filter(|file| !file.is_under_construction())
+            .filter_map(|file| match &file.file_type {
+                FileType::Regular {blocks: _, replication, block_size: _} =>
+                    Some(*replication),
+                FileType::Directory => None,
+            })
+            .max()
     }
 
     pub fn get_children(&self, inode: u64) -> Option<Vec<&File>> {
-        if !self.children.contains_key(&inode) {
-            return None;
+        self.get_children_after(inode, None)
+            .map(|children| children.collect())
+    }
+
+    pub fn get_children_after<'a>(&'a self, inode: u64,
+            start_after: Option<&str>)
+            -> Option<impl Iterator<Item=&'a File>> {
+        // iterate over children ordered by name
+        let start = match start_after {
+            Some(name) => Bound::Excluded(name),
+            None => Bound::Unbounded,
+        };
+
+        self.children.get(&inode).map(move |children| children
+            .range::<str, _>((start, Bound::Unbounded))
+            .map(move |(_, child_inode)|
+                self.inodes.get(child_inode).unwrap()))
+    }
+
+    pub fn get_children_count(&self, inode: u64) -> usize {
+        self.children.get(&inode)
+            .map(|children| children.len()).unwrap_or(0)
+    }
+
+    pub fn get_files(&self) -> Vec<&File> {
+        self.inodes.values().collect()
+    }
+
+    pub fn get_open_files(&self) -> Vec<&File> {
+        self.inodes.values()
+            .filter(|file| file.is_under_construction()).collect()
+    }
+
+    fn get_parent_inode(&self,
+            components: &Vec<&str>) -> Result<u64, NahFSError> {
+        // find longest match of parent components
+        let parent_components = components[..components.len() - 1].to_vec();
+        let (inode, match_length) =
+            self.get_longest_match(&parent_components);
+
+        // check if parent exists and is a directory
+        match self.inodes.get(&inode).unwrap().get_file_type() {
+            FileType::Regular {blocks: _, replication: _, block_size: _} =>
+                Err(NahFSError::ParentNotDirectoryException(format!(
+                    "{} (is not a directory)", self.compute_path(inode)))),
+            FileType::Directory if match_length != parent_components.len() =>
+                Err(NahFSError::FileNotFoundException(format!(
+                    "Parent directory doesn't exist: /{}",
+                    parent_components.join("/")))),
+            FileType::Directory => Ok(inode),
+        }
+    }
+
+    pub fn get_rename_destination(&self, src_path: &str,
+            dst_path: &str) -> String {
+        // if destination is a directory -> move source into it
+        match (self.get_file(dst_path), parse_path(src_path).last()) {
+            (Some(file), Some(name)) if self.is_directory(file.get_inode()) =>
+                format!("{}/{}", dst_path.trim_end_matches("/"), name),
+            _ => dst_path.to_string(),
+        }
+    }
+
+    fn get_rename_source(&self, src_path: &str,
+            dst_path: &str) -> Result<u64, NahFSError> {
+        validate_path(dst_path)?;
+        if is_snapshot_path(src_path) {
+            return Err(snapshot_modification());
         }
 
-        let mut children = Vec::new();
-        for child_inode in self.children.get(&inode).unwrap() {
-            children.push(self.inodes.get(child_inode).unwrap());
+        let src_components = parse_path(src_path);
+        let (src_inode, src_match_length) =
+            self.get_longest_match(&src_components);
+
+        if src_match_length != src_components.len() {
+            return Err(NahFSError::FileNotFoundException(format!(
+                "rename source {} is not found.", src_path)));
+        } else if src_components.len() == 0 {
+            return Err(NahFSError::InvalidPathException(format!(
+                "rename source {} is the root.", src_path)));
         }
 
-        Some(children)
+        Ok(src_inode)
     }
 
-    fn get_longest_match(&self, components: &Vec<&str>) -> (u64, usize) {
-        let (mut inode, mut match_length) = (2, 0);
-        for i in 0..components.len() {
-            for child_inode in self.children.get(&inode).unwrap() {
-                let child_file = self.inodes.get(child_inode).unwrap();
-                if &child_file.name == components[i] {
-                    inode = child_file.inode;
-                    match_length += 1;
-                }
+    fn get_path_inodes(&self, components: &Vec<&str>) -> Vec<u64> {
+        let mut inodes = vec!(ROOT_INODE);
+        for component in components.iter() {
+            // the reserved name resolves to the snapshot directory
+            let inode = *inodes.last().unwrap();
+            let child_inode = match self.snapshot_directories.get(&inode) {
+                Some(snapshot_inode) if *component == SNAPSHOT_DIRECTORY =>
+                    Some(snapshot_inode),
+                _ => self.children.get(&inode).unwrap().get(*component),
+            };
+
+            match child_inode {
+                Some(child_inode) => inodes.push(*child_inode),
+                None => break,
             }
+        }
 
-            if match_length != i + 1 {
-                break;
+        inodes
+    }
+
+    fn get_longest_match(&self, components: &Vec<&str>) -> (u64, usize) {
+        let (mut inode, mut match_length) = (ROOT_INODE, 0);
+        for component in components.iter() {
+            match self.children.get(&inode).unwrap().get(*component) {
+                Some(child_inode) => inode = *child_inode,
+                None => break,
             }
+
+            match_length += 1;
         }
 
         (inode, match_length)
     }
 
+    pub fn get_quota(&self, inode: u64) -> Option<&Quota> {
+        self.quotas.get(&inode)
+    }
+
+    pub fn get_snapshot_blocks<'a>(&self, file: &'a File) -> &'a [u64] {
+        // the last block of an open file is being written and is not
+        //  visible through snapshots
+        match &file.file_type {
+            FileType::Regular {blocks, replication: _, block_size: _}
+                if file.is_under_construction() && blocks.len() != 0 =>
+                    &blocks[..blocks.len() - 1],
+            FileType::Regular {blocks, replication: _, block_size: _} =>
+                blocks,
+            FileType::Directory => &[],
+        }
+    }
+
+    fn get_snapshot_inode(&self, inode: u64,
+            path: &str) -> Result<u64, NahFSError> {
+        self.snapshot_directories.get(&inode).map(|x| *x)
+            .ok_or_else(|| NahFSError::SnapshotException(format!(
+                "Directory is not a snapshottable directory: {}", path)))
+    }
+
+    pub fn get_storage_policies(&self) -> Vec<(u32, &String)> {
+        let mut storage_policies: Vec<(u32, &String)> = self
+            .storage_policies.iter().map(|(id, x)| (*id, x)).collect();
+        storage_policies.sort_by_key(|(id, _)| *id);
+        storage_policies
+    }
+
     pub fn get_storage_policy_id(&self, inode: &u64) -> Option<u32> {
         let mut current_inode = inode;
         loop {
@@ -156,105 +959,1409 @@ impl FileStore {
         None
     }
 
+    pub fn get_storage_policy_paths(&self, id: u32) -> Vec<String> {
+        // paths which reference the storage policy directly
+        let mut paths: Vec<String> = match self.storage_policy_inodes.get(&id) {
+            Some(inodes) => inodes.iter()
+                .map(|inode| self.compute_path(*inode)).collect(),
+            None => Vec::new(),
+        };
+
+        paths.sort();
+        paths
+    }
+
     pub fn get_storage_policy_str(&self, id: &u32) -> Option<&String> {
         self.storage_policies.get(id)
     }
 
+    fn inherit_acl(&self, parent_inode: u64,
+            file: &mut File, is_directory: bool) {
+        // apply default acl entries of the parent directory
+        let parent = self.inodes.get(&parent_inode).unwrap();
+        if let Some((permissions, acl)) =
+                acl::inherit(parent, file.permissions, is_directory) {
+            file.permissions = permissions;
+            file.acl = acl;
+        }
+    }
+
+    pub fn is_block_referenced(&self, block_id: &u64) -> bool {
+        self.block_references.contains_key(block_id)
+    }
+
+    fn is_directory(&self, inode: u64) -> bool {
+        match self.inodes.get(&inode).map(|file| file.get_file_type()) {
+            Some(FileType::Directory) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_snapshot_block(&self, inode: u64, block_id: &u64) -> bool {
+        // blocks are referenced by snapshot copies of the file or
+        //  through ancestors shared with snapshots
+        if self.block_references.get(block_id)
+                .map(|inodes| inodes.len()).unwrap_or(0) > 1 {
+            return true;
+        }
+
+        let mut current_inode = Some(inode);
+        while let Some(inode) = current_inode {
+            if self.snapshot_parents.contains_key(&inode) {
+                return true;
+            }
+
+            current_inode = self.parents.get(&inode).map(|x| *x);
+        }
+
+        false
+    }
+
     pub fn mkdirs(&mut self, directory: &str, permissions: u32,
-            owner: &str, group: &str, create_parent: bool) {
+            owner: &str, group: &str, create_parent: bool,
+            time: u64) -> Result<(), NahFSError> {
         // find longest path match
+        validate_path(directory)?;
         let components = parse_path(directory);
         let (mut inode, match_length) = self.get_longest_match(&components);
 
         // check if directories are valid
-        if match_length == components.len() {
-            return; // directory already exists
-        } else if components.len() >= 1 && match_length < components.len() - 1
-                && !create_parent {
-            return; // need to, and unable to create parents
+        let is_directory = self.is_directory(inode);
+        if match_length == components.len() && is_directory {
+            return Ok(()); // directory already exists
+        } else if match_length == components.len() {
+            return Err(NahFSError::FileAlreadyExistsException(format!(
+                "Path is not a directory: {}", directory)));
+        } else if !is_directory {
+            return Err(NahFSError::ParentNotDirectoryException(format!(
+                "{} (is not a directory)", self.compute_path(inode))));
+        } else if match_length < components.len() - 1 && !create_parent {
+            return Err(NahFSError::FileNotFoundException(format!(
+                "Parent directory doesn't exist: /{}",
+                components[..components.len() - 1].join("/"))));
         }
 
+        let count = (components.len() - match_length) as u64;
+        self.check_quota(inode, count, 0, &HashSet::new())?;
+
         // create directories
+        self.copy_on_write(inode);
+        self.set_modification_time(inode, time);
+        self.update_usage(inode, count as i64, 0);
         for i in match_length..components.len() {
             // initialize child file
-            let child_inode = rand::random::<u64>();
-            let child_file = File::new(child_inode,
+            let child_inode = self.allocate_inode();
+            let mut child_file = File::new(child_inode,
                 FileType::directory(), components[i].to_string(),
-                owner.to_string(), group.to_string(), permissions, None);
+                owner.to_string(), group.to_string(), permissions,
+                None, time, time, None);
+            self.inherit_acl(inode, &mut child_file, true);
 
             // update data inode data structures
             self.inodes.insert(child_inode, child_file);
             self.parents.insert(child_inode, inode);
-            self.children.get_mut(&inode).unwrap().push(child_inode);
-            self.children.insert(child_inode, Vec::new());
+            self.children.get_mut(&inode).unwrap()
+                .insert(components[i].to_string(), child_inode);
+            self.children.insert(child_inode, BTreeMap::new());
 
             inode = child_inode;
         }
+
+        Ok(())
     }
 
-    pub fn rename(&mut self, src_path: &str, dst_path: &str) {
-        // compute src path components
-        let src_components = parse_path(src_path);
-        let (src_inode, src_match_length) =
-            self.get_longest_match(&src_components);
+    pub fn modify_acl_entries(&mut self, path: &str,
+            entries: &Vec<AclEntry>) -> Result<(), NahFSError> {
+        let inode = self.get_live_inode(path)?;
+        let file = self.inodes.get(&inode).unwrap();
+        let (permissions, acl) = acl::modify(file, entries)?;
 
-        if src_match_length != src_components.len() {
-            return; // file does not exist
+        let file = self.get_inode_mut(inode);
+        file.permissions = permissions;
+        file.acl = acl;
+        Ok(())
+    }
+
+    fn reference_blocks(&mut self, inode: u64, block_ids: &[u64]) {
+        for block_id in block_ids.iter() {
+            self.block_references.entry(*block_id)
+                .or_insert(Vec::new()).push(inode);
         }
+    }
 
-        // compute dst path components
-        let dst_components = parse_path(dst_path);
-        let (dst_inode, dst_match_length) =
-            self.get_longest_match(&dst_components);
-
-        if dst_match_length != dst_components.len() - 1 {
-            return; // destination directory does not exist
-        }
-
-        // remove src file from children and parents
-        let parent_inode = self.parents
-            .get(&src_inode).unwrap().to_owned();
-        self.parents.remove(&src_inode);
-        let children = self.children.get_mut(&parent_inode).unwrap();
-        let mut index = 0;
-        for (i, value) in children.iter().enumerate() {
-            if value == &src_inode {
-                index = i;
+    fn release_blocks(&mut self, inode: u64,
+            block_ids: &[u64]) -> Vec<u64> {
+        // return blocks which are no longer referenced by any file
+        let mut released_block_ids = Vec::new();
+        for block_id in block_ids.iter() {
+            if let Some(inodes) = self.block_references.get_mut(block_id) {
+                inodes.retain(|x| *x != inode);
+                if inodes.len() == 0 {
+                    self.block_references.remove(block_id);
+                    released_block_ids.push(*block_id);
+                }
             }
         }
 
-        children.remove(index);
-        
-        // add dst file to children and parents
-        self.children.get_mut(&dst_inode).unwrap().push(src_inode);
-        self.parents.insert(src_inode, dst_inode);
-
-        // change file name
-        let mut file = self.inodes.get_mut(&src_inode).unwrap();
-        file.name = dst_components.last().unwrap().to_string();
+        released_block_ids
     }
 
-    pub fn set_storage_policy(&mut self, path: &str, storage_policy: &str) {
-        let components = parse_path(path);
-        let (inode, match_length) = self.get_longest_match(&components);
-        if match_length != components.len() {
-            return;
+    fn reference_storage_policy(&mut self, inode: u64, id: Option<u32>) {
+        if let Some(id) = id {
+            self.storage_policy_inodes.entry(id)
+                .or_insert(HashSet::new()).insert(inode);
         }
-
-        let mut file = self.inodes.get_mut(&inode).unwrap();
-
-        let storage_policy_id = rand::random::<u32>();
-        self.storage_policies.insert(storage_policy_id,
-            storage_policy.to_string());
-        file.storage_policy = Some(storage_policy_id);
     }
-}
 
-fn parse_path(path: &str) -> Vec<&str> {
-    let mut components: Vec<&str> = path.split("/").collect();
-    while components.len() > 0 && components[0] == "" {
-        components.remove(0);
+    fn release_snapshot(&mut self, inode: u64,
+            parent_inode: u64) -> Vec<u64> {
+        // remove snapshot inodes which are no longer referenced
+        //  - collecting blocks of removed files
+        let mut block_ids = Vec::new();
+        let mut inodes = vec!((inode, parent_inode));
+        while let Some((current_inode, parent_inode)) = inodes.pop() {
+            let parent_inodes =
+                self.snapshot_parents.get_mut(&current_inode).unwrap();
+            parent_inodes.retain(|x| *x != parent_inode);
+            if parent_inodes.len() != 0 {
+                // inode is retained by other snapshots
+                if self.parents.get(&current_inode) == Some(&parent_inode) {
+                    self.parents.insert(current_inode, parent_inodes[0]);
+                }
+
+                continue;
+            }
+
+            self.snapshot_parents.remove(&current_inode);
+            if !self.snapshot_inodes.remove(&current_inode) {
+                continue; // inode is retained by the live namespace
+            }
+
+            if let Some(children) = self.children.remove(&current_inode) {
+                inodes.extend(children.values()
+                    .map(|child_inode| (*child_inode, current_inode)));
+            }
+
+            self.parents.remove(&current_inode);
+            if let Some(file) = self.inodes.remove(&current_inode) {
+                self.release_storage_policy(current_inode,
+                    file.storage_policy);
+                if let FileType::Regular {blocks, replication: _,
+                        block_size: _} = file.file_type {
+                    block_ids.extend(
+                        self.release_blocks(current_inode, &blocks));
+                }
+            }
+        }
+
+        block_ids
+    }
+
+    fn release_storage_policy(&mut self, inode: u64, id: Option<u32>) {
+        // catalog storage policies while files reference them
+        let id = match id {
+            Some(id) => id,
+            None => return,
+        };
+
+        let referenced = match self.storage_policy_inodes.get_mut(&id) {
+            Some(inodes) => {
+                inodes.remove(&inode);
+                inodes.len() != 0
+            },
+            None => false,
+        };
+
+        if !referenced {
+            self.storage_policy_inodes.remove(&id);
+            self.storage_policies.remove(&id);
+        }
+    }
+
+    fn remove_subtree(&mut self, inode: u64) -> Vec<u64> {
+        // remove subtree - collecting blocks of removed files
+        let mut block_ids = Vec::new();
+        let mut inodes = vec!(inode);
+        while let Some(current_inode) = inodes.pop() {
+            // snapshot directories of removed directories are empty
+            if let Some(snapshot_inode) =
+                    self.snapshot_directories.remove(&current_inode) {
+                self.children.remove(&snapshot_inode);
+                self.parents.remove(&snapshot_inode);
+                self.inodes.remove(&snapshot_inode);
+                self.snapshot_inodes.remove(&snapshot_inode);
+            }
+
+            self.quotas.remove(&current_inode);
+
+            // inodes referenced by snapshots are retained as snapshot
+            //  inodes - along with their subtrees
+            let parent_inode = self.snapshot_parents.get(&current_inode)
+                .map(|parent_inodes| parent_inodes[0]);
+            if let Some(parent_inode) = parent_inode {
+                self.parents.insert(current_inode, parent_inode);
+                self.snapshot_inodes.insert(current_inode);
+                block_ids.extend(self.freeze_file(current_inode));
+
+                let children = self.children.get(&current_inode).unwrap();
+                for child_inode in children.values() {
+                    self.snapshot_parents.entry(*child_inode)
+                        .or_insert(Vec::new()).push(current_inode);
+                    inodes.push(*child_inode);
+                }
+
+                continue;
+            }
+
+            if let Some(children) = self.children.remove(&current_inode) {
+                inodes.extend(children.values());
+            }
+
+            self.parents.remove(&current_inode);
+            if let Some(file) = self.inodes.remove(&current_inode) {
+                self.release_storage_policy(current_inode,
+                    file.storage_policy);
+                if let FileType::Regular {blocks, replication: _,
+                        block_size: _} = file.file_type {
+                    block_ids.extend(
+                        self.release_blocks(current_inode, &blocks));
+                }
+            }
+        }
+
+        block_ids
+    }
+
+    pub fn remove_acl_entries(&mut self, path: &str,
+            entries: &Vec<AclEntry>) -> Result<(), NahFSError> {
+        let inode = self.get_live_inode(path)?;
+        let file = self.inodes.get(&inode).unwrap();
+        let (permissions, acl) = acl::remove(file, entries)?;
+
+        let file = self.get_inode_mut(inode);
+        file.permissions = permissions;
+        file.acl = acl;
+        Ok(())
+    }
+
+    pub fn remove_block(&mut self, path: &str,
+            block_id: u64) -> Result<(), NahFSError> {
+        let (inode, space) = self.get_file_space(path)?;
+        let file = self.get_inode_mut(inode);
+        if let FileType::Regular {blocks, replication: _, block_size: _} =
+                &mut file.file_type {
+            blocks.retain(|value| value != &block_id);
+        }
+
+        for block_id in self.release_blocks(inode, &[block_id]) {
+            self.block_lengths.remove(&block_id);
+        }
+
+        self.update_file_usage(inode, space);
+        Ok(())
+    }
+
+    pub fn remove_xattr(&mut self, path: &str,
+            name: &str) -> Result<(), NahFSError> {
+        let inode = self.get_live_inode(path)?;
+        if !self.inodes.get(&inode).unwrap().xattrs.contains_key(name) {
+            return Err(NahFSError::from(format!(
+                "No matching attributes found for remove operation")));
+        }
+
+        let file = self.get_inode_mut(inode);
+        file.xattrs.remove(name);
+        Ok(())
+    }
+
+    pub fn restore(&mut self) {
+        // inodes unreachable from the root are only referenced through
+        //  snapshots - rebuild references of snapshot directories
+        let mut live_inodes = HashSet::new();
+        let mut inodes = vec!(ROOT_INODE);
+        while let Some(inode) = inodes.pop() {
+            live_inodes.insert(inode);
+            inodes.extend(self.children.get(&inode).unwrap().values());
+        }
+
+        self.snapshot_inodes = self.inodes.keys()
+            .filter(|inode| !live_inodes.contains(inode))
+            .map(|x| *x).collect();
+        self.snapshot_parents.clear();
+        for inode in self.snapshot_inodes.iter() {
+            for child_inode in self.children.get(inode).unwrap().values() {
+                self.snapshot_parents.entry(*child_inode)
+                    .or_insert(Vec::new()).push(*inode);
+            }
+        }
+
+        // rebuild block references of live and snapshot files
+        self.block_references.clear();
+        for file in self.inodes.values() {
+            if let FileType::Regular {blocks, replication: _,
+                    block_size: _} = &file.file_type {
+                for block_id in blocks.iter() {
+                    self.block_references.entry(*block_id)
+                        .or_insert(Vec::new()).push(file.inode);
+                }
+            }
+        }
+
+        // rebuild storage policy references - removing catalog entries
+        //  which no file references
+        self.storage_policy_inodes.clear();
+        for file in self.inodes.values() {
+            if let Some(id) = file.storage_policy {
+                self.storage_policy_inodes.entry(id)
+                    .or_insert(HashSet::new()).insert(file.inode);
+            }
+        }
+
+        let storage_policy_inodes = &self.storage_policy_inodes;
+        self.storage_policies
+            .retain(|id, _| storage_policy_inodes.contains_key(id));
+
+        // rebuild cached quota usage - deepest directories first so
+        //  ancestors reuse the usage of nested quota directories
+        let mut inodes = Vec::new();
+        for inode in self.quotas.keys() {
+            let mut depth = 0;
+            let mut current_inode = self.parents.get(inode);
+            while let Some(inode) = current_inode {
+                depth += 1;
+                current_inode = self.parents.get(inode);
+            }
+
+            inodes.push((depth, *inode));
+        }
+
+        inodes.sort();
+        for (_, inode) in inodes.into_iter().rev() {
+            let mut quota = self.quotas.remove(&inode).unwrap();
+            let (namespace, space) = self.compute_usage(inode);
+            quota.namespace_consumed = namespace;
+            quota.space_consumed = space;
+            self.quotas.insert(inode, quota);
+        }
+    }
+
+    fn move_inode(&mut self, inode: u64, dst_parent_inode: u64,
+            name: &str, time: u64) {
+        // copy inodes shared with snapshots before they are modified
+        self.copy_on_write(inode);
+        self.copy_on_write(dst_parent_inode);
+
+        // move usage of subtree between ancestors
+        let parent_inode = *self.parents.get(&inode).unwrap();
+        let (namespace, space) = self.compute_usage(inode);
+        self.update_usage(parent_inode, -(namespace as i64), -(space as i64));
+        self.update_usage(dst_parent_inode, namespace as i64, space as i64);
+
+        // remove file from parent children
+        self.parents.remove(&inode);
+        let src_name = self.inodes.get(&inode).unwrap().name.clone();
+        self.children.get_mut(&parent_inode).unwrap().remove(&src_name);
+
+        // add file to destination children and parents
+        self.children.get_mut(&dst_parent_inode)
+            .unwrap().insert(name.to_string(), inode);
+        self.parents.insert(inode, dst_parent_inode);
+
+        // update parent modification times
+        self.set_modification_time(parent_inode, time);
+        self.set_modification_time(dst_parent_inode, time);
+
+        // change file name
+        let mut file = self.inodes.get_mut(&inode).unwrap();
+        file.name = name.to_string();
+    }
+
+    pub fn rename(&mut self, src_path: &str, dst_path: &str,
+            time: u64) -> Result<(), NahFSError> {
+        let src_inode = self.get_rename_source(src_path, dst_path)?;
+        let dst_path = self.get_rename_destination(src_path, dst_path);
+
+        // check if destination is valid
+        let src_components = parse_path(src_path);
+        let dst_components = parse_path(&dst_path);
+        if src_components == dst_components {
+            return Ok(()); // source and destination are the same
+        } else if dst_components.starts_with(&src_components) {
+            return Err(NahFSError::InvalidPathException(format!(
+                "rename destination {} is a directory or file under \
+                source {}", dst_path, src_path)));
+        } else if self.get_file(&dst_path).is_some() {
+            return Err(NahFSError::FileAlreadyExistsException(format!(
+                "rename destination {} already exists.", dst_path)));
+        }
+
+        let dst_parent_inode = self.get_parent_inode(&dst_components)?;
+        self.check_rename_quota(src_inode, dst_parent_inode, (0, 0))?;
+        self.move_inode(src_inode, dst_parent_inode,
+            dst_components.last().unwrap(), time);
+        Ok(())
+    }
+
+    pub fn rename2(&mut self, src_path: &str, dst_path: &str,
+            overwrite: bool, time: u64) -> Result<Vec<u64>, NahFSError> {
+        let src_inode = self.get_rename_source(src_path, dst_path)?;
+
+        // check if destination is valid
+        let src_components = parse_path(src_path);
+        let dst_components = parse_path(dst_path);
+        if src_components == dst_components {
+            return Err(NahFSError::FileAlreadyExistsException(format!(
+                "The source {} and destination {} are the same",
+                src_path, dst_path)));
+        } else if dst_components.starts_with(&src_components) {
+            return Err(NahFSError::InvalidPathException(format!(
+                "rename destination {} is a directory or file under \
+                source {}", dst_path, src_path)));
+        } else if dst_components.len() == 0 {
+            return Err(NahFSError::InvalidPathException(format!(
+                "rename destination cannot be the root")));
+        }
+
+        let dst_parent_inode = self.get_parent_inode(&dst_components)?;
+
+        // check if existing destination may be overwritten
+        let dst_inode = self.get_file(dst_path).map(|file| file.get_inode());
+        if let Some(dst_inode) = dst_inode {
+            let (src_directory, dst_directory) = (self.is_directory(src_inode),
+                self.is_directory(dst_inode));
+
+            if !overwrite {
+                return Err(NahFSError::FileAlreadyExistsException(format!(
+                    "rename destination {} already exists.", dst_path)));
+            } else if src_directory != dst_directory {
+                return Err(NahFSError::FileAlreadyExistsException(format!(
+                    "Source {} and destination {} must both be directories \
+                    or files", src_path, dst_path)));
+            } else if self.get_children_count(dst_inode) != 0 {
+                return Err(NahFSError::PathIsNotEmptyDirectoryException(
+                    format!("rename destination directory is not empty: {}",
+                        dst_path)));
+            }
+        }
+
+        let released = match dst_inode {
+            Some(dst_inode) => self.compute_usage(dst_inode),
+            None => (0, 0),
+        };
+        self.check_rename_quota(src_inode, dst_parent_inode, released)?;
+
+        // remove overwritten destination - returning its blocks
+        let block_ids = match dst_inode {
+            Some(_) => self.delete(dst_path, false, time)?,
+            None => Vec::new(),
+        };
+
+        self.move_inode(src_inode, dst_parent_inode,
+            dst_components.last().unwrap(), time);
+        Ok(block_ids)
+    }
+
+    pub fn set_acl(&mut self, path: &str,
+            entries: &Vec<AclEntry>) -> Result<(), NahFSError> {
+        let inode = self.get_live_inode(path)?;
+        let file = self.inodes.get(&inode).unwrap();
+        let (permissions, acl) = acl::set(file, entries)?;
+
+        let file = self.get_inode_mut(inode);
+        file.permissions = permissions;
+        file.acl = acl;
+        Ok(())
+    }
+
+    pub fn set_client_name(&mut self, path: &str,
+            client_name: Option<&str>) -> Result<(), NahFSError> {
+        let (inode, space) = self.get_file_space(path)?;
+        let file = self.get_inode_mut(inode);
+        file.client_name = client_name.map(|x| x.to_string());
+
+        self.update_file_usage(inode, space);
+        Ok(())
+    }
+
+    fn set_modification_time(&mut self, inode: u64, time: u64) {
+        if let Some(file) = self.inodes.get_mut(&inode) {
+            file.modification_time = time;
+        }
+    }
+
+    pub fn set_generation_stamp(&mut self, generation_stamp: u64) {
+        // advance allocator past stamps of replayed operations
+        self.last_generation_stamp =
+            std::cmp::max(self.last_generation_stamp, generation_stamp);
+    }
+
+    pub fn set_owner(&mut self, path: &str, owner: Option<&str>,
+            group: Option<&str>) -> Result<(), NahFSError> {
+        let file = self.get_file_mut(path)?;
+        if let Some(owner) = owner {
+            file.owner = owner.to_string();
+        }
+
+        if let Some(group) = group {
+            file.group = group.to_string();
+        }
+
+        Ok(())
+    }
+
+    pub fn set_permission(&mut self, path: &str,
+            permissions: u32) -> Result<(), NahFSError> {
+        let file = self.get_file_mut(path)?;
+        file.permissions = permissions;
+        Ok(())
+    }
+
+    pub fn set_quota(&mut self, path: &str, namespace: Option<u64>,
+            space: Option<u64>) -> Result<(), NahFSError> {
+        let inode = self.get_live_inode(path)?;
+        if !self.is_directory(inode) {
+            return Err(NahFSError::FileNotFoundException(format!(
+                "Cannot set quota on a file: {}", path)));
+        }
+
+        // directories without quotas are not tracked
+        if let (None, None) = (namespace, space) {
+            self.quotas.remove(&inode);
+            return Ok(());
+        }
+
+        // newly tracked directories compute their current usage
+        if !self.quotas.contains_key(&inode) {
+            let (namespace_consumed, space_consumed) =
+                self.compute_usage(inode);
+            self.quotas.insert(inode, Quota {
+                namespace: None,
+                space: None,
+                namespace_consumed: namespace_consumed,
+                space_consumed: space_consumed,
+            });
+        }
+
+        let quota = self.quotas.get_mut(&inode).unwrap();
+        quota.namespace = namespace;
+        quota.space = space;
+        Ok(())
+    }
+
+    pub fn set_replication(&mut self, path: &str,
+            replication: u32) -> Result<bool, NahFSError> {
+        let (inode, space) = self.get_file_space(path)?;
+        let file = self.inodes.get(&inode).unwrap();
+        let current_replication = match &file.file_type {
+            FileType::Regular {blocks: _, replication,
+                block_size: _} => *replication,
+            FileType::Directory => return Ok(false),
+        };
+
+        // check space quotas of file ancestors for additional replicas
+        if replication > current_replication {
+            let additional = space / std::cmp::max(current_replication, 1)
+                as u64 * (replication - current_replication) as u64;
+            self.check_quota(inode, 0, additional, &HashSet::new())?;
+        }
+
+        let file = self.get_inode_mut(inode);
+        if let FileType::Regular {blocks: _, replication: value,
+                block_size: _} = &mut file.file_type {
+            *value = replication;
+        }
+
+        self.update_file_usage(inode, space);
+        Ok(true)
+    }
+
+    pub fn set_times(&mut self, path: &str, modification_time: Option<u64>,
+            access_time: Option<u64>) -> Result<(), NahFSError> {
+        let file = self.get_file_mut(path)?;
+        if let Some(modification_time) = modification_time {
+            file.modification_time = modification_time;
+        }
+
+        if let Some(access_time) = access_time {
+            file.access_time = access_time;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_xattr(&mut self, path: &str, name: &str, value: &[u8],
+            create: bool, replace: bool) -> Result<(), NahFSError> {
+        let inode = self.get_live_inode(path)?;
+        let file = self.inodes.get(&inode).unwrap();
+
+        // check if xattr may be created or replaced
+        let exists = file.xattrs.contains_key(name);
+        let size = name.len() + value.len();
+        if size > XATTR_SIZE_LIMIT {
+            return Err(NahFSError::from(format!("The XAttr is too big. \
+                The maximum combined size of the name and value is {}, \
+                but the total size is {}", XATTR_SIZE_LIMIT, size)));
+        } else if exists && !replace {
+            return Err(NahFSError::from(format!("XAttr: {} already \
+                exists. The REPLACE flag must be specified.", name)));
+        } else if !exists && !create {
+            return Err(NahFSError::from(format!("XAttr: {} does not \
+                exist. The CREATE flag must be specified.", name)));
+        } else if !exists && file.xattrs.len() >= XATTR_LIMIT {
+            return Err(NahFSError::from(format!("Cannot add additional \
+                XAttr to inode, would exceed limit of {}", XATTR_LIMIT)));
+        }
+
+        let file = self.get_inode_mut(inode);
+        file.xattrs.insert(name.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    pub fn set_storage_policy(&mut self, path: &str,
+            storage_policy: &str) -> Result<(), NahFSError> {
+        // reject malformed policies before datanodes fail to index
+        StoragePolicy::parse(storage_policy)?;
+        let inode = self.get_live_inode(path)?;
+
+        // identical storage policies share the lowest existing id
+        let existing_id = self.storage_policies.iter()
+            .filter(|(_, value)| value.as_str() == storage_policy)
+            .map(|(id, _)| *id).min();
+        let storage_policy_id = match existing_id {
+            Some(storage_policy_id) => storage_policy_id,
+            None => {
+                let storage_policy_id = self.allocate_storage_policy_id();
+                self.storage_policies.insert(storage_policy_id,
+                    storage_policy.to_string());
+                storage_policy_id
+            },
+        };
+
+        let file = self.get_inode_mut(inode);
+        let previous_id = file.storage_policy.replace(storage_policy_id);
+        self.reference_storage_policy(inode, Some(storage_policy_id));
+        if previous_id != Some(storage_policy_id) {
+            self.release_storage_policy(inode, previous_id);
+        }
+
+        Ok(())
+    }
+
+    pub fn unset_storage_policy(&mut self,
+            path: &str) -> Result<(), NahFSError> {
+        let file = self.get_file_mut(path)?;
+        let (inode, previous_id) = (file.inode, file.storage_policy.take());
+        self.release_storage_policy(inode, previous_id);
+        Ok(())
+    }
+
+    fn update_file_usage(&mut self, inode: u64, space: u64) {
+        // charge the change in space of a mutated file to ancestors
+        let file = self.inodes.get(&inode).unwrap();
+        let delta = self.compute_file_space(file) as i64 - space as i64;
+        if delta != 0 {
+            self.update_usage(inode, 0, delta);
+        }
+    }
+
+    fn update_usage(&mut self, inode: u64, namespace: i64, space: i64) {
+        // update cached usage of quota directories along the path
+        let mut current_inode = Some(inode);
+        while let Some(inode) = current_inode {
+            if let Some(quota) = self.quotas.get_mut(&inode) {
+                quota.namespace_consumed =
+                    (quota.namespace_consumed as i64 + namespace) as u64;
+                quota.space_consumed =
+                    (quota.space_consumed as i64 + space) as u64;
+            }
+
+            current_inode = self.parents.get(&inode).map(|x| *x);
+        }
+    }
+}
+
+fn file_not_found(path: &str) -> NahFSError {
+    NahFSError::FileNotFoundException(
+        format!("File does not exist: {}", path))
+}
+
+pub fn get_stored_block_id(block_id: u64) -> u64 {
+    // clients address indexed blocks with the storage policy id in
+    //  the low bits - which datanodes strip before storing
+    match block_id & FIRST_BIT == FIRST_BIT {
+        true => block_id & (FIRST_BIT | INDEXED_MASK),
+        false => block_id,
+    }
+}
+
+pub fn is_snapshot_path(path: &str) -> bool {
+    path.split("/").any(|x| x == SNAPSHOT_DIRECTORY)
+}
+
+fn parse_path(path: &str) -> Vec<&str> {
+    path.split("/").filter(|x| !x.is_empty()).collect()
+}
+
+fn snapshot_modification() -> NahFSError {
+    NahFSError::SnapshotAccessControlException(
+        format!("Modification on a read-only snapshot is disallowed"))
+}
+
+fn validate_path(path: &str) -> Result<(), NahFSError> {
+    // paths must be absolute without relative or empty components
+    let components: Vec<&str> = path.split("/").collect();
+    for (i, component) in components.iter().enumerate() {
+        if component == &SNAPSHOT_DIRECTORY {
+            return Err(snapshot_modification());
+        } else if component == &"." || component == &".."
+                || component.contains(":")
+                || (component.is_empty() && i != 0
+                    && i != components.len() - 1) {
+            return Err(NahFSError::InvalidPathException(
+                format!("Invalid file name: {}", path)));
+        }
+    }
+
+    match path.starts_with("/") {
+        true => Ok(()),
+        false => Err(NahFSError::InvalidPathException(
+            format!("Invalid file name: {}", path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn acls() {
+        use super::FileStore;
+        use crate::file::acl::{AclEntry, AclScope, AclType};
+        use crate::file::permission::{self, User, READ, WRITE};
+
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a", 493, "root", "root", false, 0).unwrap();
+
+        // named entries extend the group bits to the mask
+        let bob_entry = AclEntry::new(AclScope::Access,
+            AclType::User, Some("bob".to_string()), 7);
+        let default_entry = AclEntry::new(AclScope::Default,
+            AclType::User, Some("bob".to_string()), 5);
+        file_store.modify_acl_entries("/a",
+            &vec!(bob_entry.clone(), default_entry)).unwrap();
+
+        let file = file_store.get_file("/a").unwrap();
+        assert_eq!(file.get_permissions(), 509);
+        assert!(file.get_acl().contains(&bob_entry));
+
+        let bob = User::new("bob".to_string(), Vec::new(), false);
+        let carol = User::new("carol".to_string(), Vec::new(), false);
+        assert!(permission::has_access(file, &bob, WRITE));
+        assert!(!permission::has_access(file, &carol, WRITE));
+
+        // new children inherit default entries
+        file_store.create("/a/b", 420, "root", "root",
+            3, 65536, "client", 0).unwrap();
+        let file = file_store.get_file("/a/b").unwrap();
+        assert_eq!(file.get_permissions(), 420);
+        assert!(permission::has_access(file, &bob, READ));
+        assert!(!permission::has_access(file, &bob, WRITE));
+
+        // removing named entries restores a minimal acl
+        file_store.remove_acl_entries("/a", &vec!(bob_entry)).unwrap();
+        let file = file_store.get_file("/a").unwrap();
+        assert_eq!(file.get_permissions(), 493);
+        assert!(file.get_acl().iter()
+            .all(|entry| entry.scope == AclScope::Default));
+        assert!(!permission::has_access(file, &bob, WRITE));
+
+        // setting access entries retains existing default entries
+        file_store.set_acl("/a", &vec!(
+            AclEntry::new(AclScope::Access, AclType::User, None, 7),
+            AclEntry::new(AclScope::Access, AclType::Group, None, 5),
+            AclEntry::new(AclScope::Access, AclType::Other, None, 0)))
+            .unwrap();
+        let file = file_store.get_file("/a").unwrap();
+        assert_eq!(file.get_permissions(), 488);
+        assert!(file.get_acl().contains(&AclEntry::new(AclScope::Default,
+            AclType::User, Some("bob".to_string()), 5)));
+
+        // base entries are required and default entries need directories
+        assert!(file_store.set_acl("/a", &vec!(AclEntry::new(
+            AclScope::Access, AclType::Other, None, 0))).is_err());
+        assert!(file_store.modify_acl_entries("/a/b", &vec!(AclEntry::new(
+            AclScope::Default, AclType::Other, None, 0))).is_err());
+    }
+
+    #[test]
+    fn allocators() {
+        use super::{FileStore, FIRST_BIT};
+        use crate::block::BlockStore;
+
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a", 493, "root", "root", false, 0).unwrap();
+        file_store.create("/a/b", 420, "root", "root",
+            3, 65536, "client", 0).unwrap();
+
+        // inodes are allocated sequentially after the root
+        assert_eq!(file_store.get_file("/a").unwrap().get_inode(), 3);
+        assert_eq!(file_store.get_file("/a/b").unwrap().get_inode(), 4);
+
+        // block ids skip reported blocks
+        let mut block_store = BlockStore::new();
+        block_store.update(2, 0, 64, "datanode", "storage");
+        assert_eq!(file_store.allocate_block_id(false,
+            &block_store).unwrap(), 1);
+        assert_eq!(file_store.allocate_block_id(false,
+            &block_store).unwrap(), 3);
+        assert_eq!(file_store.allocate_block_id(true,
+            &block_store).unwrap(), FIRST_BIT | 1 << 36);
+
+        // replayed operations advance allocators
+        let generation_stamp = file_store.allocate_generation_stamp();
+        file_store.add_block("/a/b", 10, generation_stamp + 5, 0).unwrap();
+        assert_eq!(file_store.allocate_block_id(false,
+            &block_store).unwrap(), 11);
+        assert_eq!(file_store.allocate_generation_stamp(),
+            generation_stamp + 6);
+        file_store.set_generation_stamp(generation_stamp + 10);
+        assert_eq!(file_store.allocate_generation_stamp(),
+            generation_stamp + 11);
+
+        // stored block ids drop the storage policy id of indexed blocks
+        assert_eq!(super::get_stored_block_id(FIRST_BIT | 1 << 36 | 5),
+            FIRST_BIT | 1 << 36);
+        assert_eq!(super::get_stored_block_id(5), 5);
+    }
+
+    #[test]
+    fn delete() {
+        use super::FileStore;
+
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a/b", 493, "root", "root", true, 0).unwrap();
+        file_store.create("/a/b/c", 420, "root", "root",
+            3, 65536, "client", 0).unwrap();
+        file_store.create("/a/d", 420, "root", "root",
+            3, 65536, "client", 0).unwrap();
+
+        // non-recursive delete of non-empty directory fails
+        assert!(file_store.delete("/a", false, 0).is_err());
+        assert!(file_store.get_file("/a/b/c").is_some());
+
+        // deleting missing files and root fails
+        assert!(file_store.delete("/a/e", true, 0).is_err());
+        assert!(file_store.delete("/", true, 0).is_err());
+
+        // recursive delete removes entire subtree
+        assert!(file_store.delete("/a/b", true, 0).is_ok());
+        assert!(file_store.get_file("/a/b").is_none());
+        assert!(file_store.get_file("/a/b/c").is_none());
+        assert!(file_store.get_file("/a/d").is_some());
+
+        let root = file_store.get_file("/").unwrap();
+        assert_eq!(file_store.get_children(root.get_inode())
+            .unwrap().len(), 1);
+    }
+
+    #[test]
+    fn check_permission() {
+        use super::FileStore;
+        use crate::file::permission::{self, User};
+
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a/b", 493, "bob", "staff", true, 0).unwrap();
+
+        let alice = User::new("alice".to_string(), Vec::new(), false);
+        let bob = User::new("bob".to_string(), Vec::new(), false);
+        let root = User::new("root".to_string(), Vec::new(), true);
+
+        // only the owner may write to the directory
+        assert!(file_store.check_permission("/a/b/c", &alice,
+            permission::WRITE, 0, 0).is_err());
+        assert!(file_store.check_permission("/a/b/c", &bob,
+            permission::WRITE, 0, 0).is_ok());
+        assert!(file_store.check_permission("/a/b/c", &root,
+            permission::WRITE, 0, 0).is_ok());
+
+        // traverse access is required on ancestors
+        file_store.set_permission("/a", 448).unwrap();
+        assert!(file_store.check_permission("/a/b", &alice,
+            0, 0, 0).is_err());
+        assert!(file_store.check_owner("/a/b", &alice).is_err());
+        assert!(file_store.check_owner("/a/b", &bob).is_ok());
+    }
+
+    #[test]
+    fn exceptions() {
+        use super::FileStore;
+        use shared::NahFSError;
+
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a", 493, "root", "root", true, 0).unwrap();
+        file_store.create("/a/b", 420, "root", "root",
+            3, 65536, "client", 0).unwrap();
+
+        // creating existing files fails
+        match file_store.create("/a/b", 420, "root", "root",
+                3, 65536, "client", 0) {
+            Err(NahFSError::FileAlreadyExistsException(_)) => {},
+            _ => panic!("expected FileAlreadyExistsException"),
+        }
+
+        // creating files under missing directories or files fails
+        match file_store.create("/c/d", 420, "root", "root",
+                3, 65536, "client", 0) {
+            Err(NahFSError::FileNotFoundException(_)) => {},
+            _ => panic!("expected FileNotFoundException"),
+        }
+
+        match file_store.mkdirs("/a/b/c", 493, "root", "root", true, 0) {
+            Err(NahFSError::ParentNotDirectoryException(_)) => {},
+            _ => panic!("expected ParentNotDirectoryException"),
+        }
+
+        // invalid paths are rejected
+        match file_store.mkdirs("/a/../c", 493, "root", "root", true, 0) {
+            Err(NahFSError::InvalidPathException(_)) => {},
+            _ => panic!("expected InvalidPathException"),
+        }
+
+        // renaming missing files or into an existing path fails
+        match file_store.rename("/c", "/d", 0) {
+            Err(NahFSError::FileNotFoundException(_)) => {},
+            _ => panic!("expected FileNotFoundException"),
+        }
+
+        match file_store.rename("/a", "/a/b", 0) {
+            Err(NahFSError::FileAlreadyExistsException(_)) => {},
+            _ => panic!("expected FileAlreadyExistsException"),
+        }
+
+        match file_store.rename("/a", "/a/c", 0) {
+            Err(NahFSError::InvalidPathException(_)) => {},
+            _ => panic!("expected InvalidPathException"),
+        }
+
+        // overwritten destinations must match and be empty
+        file_store.mkdirs("/d/e", 493, "root", "root", true, 0).unwrap();
+        file_store.mkdirs("/f", 493, "root", "root", true, 0).unwrap();
+        match file_store.rename2("/a/b", "/d", true, 0) {
+            Err(NahFSError::FileAlreadyExistsException(_)) => {},
+            _ => panic!("expected FileAlreadyExistsException"),
+        }
+
+        match file_store.rename2("/f", "/d", true, 0) {
+            Err(NahFSError::PathIsNotEmptyDirectoryException(_)) => {},
+            _ => panic!("expected PathIsNotEmptyDirectoryException"),
+        }
+
+        // existing directories are not recreated
+        assert!(file_store.mkdirs("/a", 493, "root", "root",
+            false, 0).is_ok());
+        assert!(file_store.set_storage_policy("/c",
+            "Wkt(spatial_index:3)").is_err());
+    }
+
+    #[test]
+    fn get_children_after() {
+        use super::FileStore;
+
+        let mut file_store = FileStore::new();
+        for name in vec!("c", "a", "d", "b") {
+            file_store.mkdirs(&format!("/x/{}", name),
+                493, "root", "root", true, 0).unwrap();
+        }
+
+        // children are ordered by name
+        let inode = file_store.get_file("/x").unwrap().get_inode();
+        let names: Vec<String> = file_store.get_children(inode).unwrap()
+            .iter().map(|file| file_store.compute_path(file.get_inode()))
+            .collect();
+        assert_eq!(names, vec!("/x/a", "/x/b", "/x/c", "/x/d"));
+
+        // listing resumes after the specified name
+        let names: Vec<String> = file_store
+            .get_children_after(inode, Some("b")).unwrap()
+            .map(|file| file_store.compute_path(file.get_inode()))
+            .collect();
+        assert_eq!(names, vec!("/x/c", "/x/d"));
+        assert_eq!(file_store.get_children_count(inode), 4);
+    }
+
+    #[test]
+    fn modification_times() {
+        use super::FileStore;
+
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a", 493, "root", "root", true, 1).unwrap();
+        file_store.create("/a/b", 420, "root", "root",
+            3, 65536, "client", 2).unwrap();
+        assert_eq!(file_store.get_file("/a")
+            .unwrap().get_modification_time(), 2);
+
+        file_store.add_block("/a/b", 0, 0, 3).unwrap();
+        assert_eq!(file_store.get_file("/a/b")
+            .unwrap().get_modification_time(), 3);
+
+        file_store.mkdirs("/c", 493, "root", "root", true, 4).unwrap();
+        file_store.rename("/a/b", "/c/b", 5).unwrap();
+        assert_eq!(file_store.get_file("/a")
+            .unwrap().get_modification_time(), 5);
+        assert_eq!(file_store.get_file("/c/b")
+            .unwrap().get_modification_time(), 3);
+
+        file_store.set_times("/c/b", None, Some(6)).unwrap();
+        assert_eq!(file_store.get_file("/c/b")
+            .unwrap().get_access_time(), 6);
+        assert_eq!(file_store.get_file("/c/b")
+            .unwrap().get_modification_time(), 3);
     }
 
-    components
+    #[test]
+    fn quotas() {
+        use super::FileStore;
+
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a", 493, "root", "root", true, 0).unwrap();
+        file_store.set_quota("/a", Some(3), Some(1024)).unwrap();
+
+        // namespace quota includes the directory itself
+        file_store.create("/a/b", 420, "root", "root",
+            2, 256, "client", 0).unwrap();
+        assert!(file_store.mkdirs("/a/c/d", 493,
+            "root", "root", true, 0).is_err());
+        file_store.mkdirs("/a/c", 493, "root", "root", true, 0).unwrap();
+        assert!(file_store.create("/a/e", 420, "root", "root",
+            2, 256, "client", 0).is_err());
+
+        // uncommitted blocks are charged the full block size
+        file_store.add_block("/a/b", 1, 0, 0).unwrap();
+        assert!(file_store.check_space_quota("/a/b", 512).is_ok());
+        file_store.add_block("/a/b", 2, 0, 0).unwrap();
+        assert!(file_store.check_space_quota("/a/b", 512).is_err());
+
+        // complete files are charged committed lengths
+        file_store.commit_block("/a/b", 1, 256).unwrap();
+        file_store.commit_block("/a/b", 2, 64).unwrap();
+        assert!(file_store.commit_block("/a/b", 3, 64).is_err());
+        file_store.complete("/a/b", 0).unwrap();
+        assert!(file_store.check_space_quota("/a/b", 384).is_ok());
+        assert!(file_store.check_space_quota("/a/b", 386).is_err());
+
+        // additional replicas are charged against space quotas
+        assert!(file_store.set_replication("/a/b", 3).unwrap());
+        assert!(file_store.set_replication("/a/b", 4).is_err());
+
+        // renames charge destination quotas - except common ancestors
+        file_store.mkdirs("/f/g", 493, "root", "root", true, 0).unwrap();
+        file_store.create("/f/g/h", 420, "root", "root",
+            1, 256, "client", 0).unwrap();
+        file_store.complete("/f/g/h", 0).unwrap();
+        assert!(file_store.rename("/f/g", "/a/g", 1).is_err());
+        assert!(file_store.rename2("/f/g", "/a/g", false, 1).is_err());
+        file_store.rename("/a/c", "/a/d", 1).unwrap();
+        file_store.rename("/a/d", "/f/d", 1).unwrap();
+        file_store.rename("/f/g/h", "/a/h", 1).unwrap();
+
+        let inode = file_store.get_file("/a").unwrap().get_inode();
+        let usage = |file_store: &FileStore| {
+            let quota = file_store.get_quota(inode).unwrap();
+            (quota.namespace_consumed, quota.space_consumed)
+        };
+        assert_eq!(usage(&file_store), (3, 960));
+
+        // cached usage matches usage rebuilt from the namespace
+        file_store.restore();
+        assert_eq!(usage(&file_store), (3, 960));
+
+        file_store.delete("/a/h", false, 2).unwrap();
+        assert_eq!(usage(&file_store), (2, 960));
+
+        // quotas may only be set on directories
+        assert!(file_store.set_quota("/a/b", Some(1), None).is_err());
+
+        // clearing quotas removes limits
+        file_store.set_quota("/a", None, None).unwrap();
+        let inode = file_store.get_file("/a").unwrap().get_inode();
+        assert!(file_store.get_quota(inode).is_none());
+        assert!(file_store.create("/a/e", 420, "root", "root",
+            2, 256, "client", 0).is_ok());
+    }
+
+    #[test]
+    fn rename() {
+        use super::FileStore;
+
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a/b", 493, "root", "root", true, 0).unwrap();
+        file_store.mkdirs("/c", 493, "root", "root", true, 0).unwrap();
+        file_store.create("/d", 420, "root", "root",
+            3, 65536, "client", 0).unwrap();
+        file_store.add_block("/d", 1, 0, 0).unwrap();
+        file_store.create("/e", 420, "root", "root",
+            3, 65536, "client", 0).unwrap();
+        file_store.add_block("/e", 2, 0, 0).unwrap();
+
+        // renaming into an existing directory keeps the name
+        file_store.rename("/a", "/c", 1).unwrap();
+        assert!(file_store.get_file("/c/a/b").is_some());
+        assert!(file_store.get_file("/a").is_none());
+
+        // renaming directories into their own subtree fails
+        assert!(file_store.rename("/c", "/c/a/b", 2).is_err());
+        assert!(file_store.rename2("/c", "/c/a/f", false, 2).is_err());
+        assert!(file_store.rename("/", "/f", 2).is_err());
+
+        // existing destinations are only replaced when overwriting
+        assert!(file_store.rename2("/d", "/e", false, 3).is_err());
+        assert!(file_store.rename2("/d", "/c", true, 3).is_err());
+        assert_eq!(file_store.rename2("/d", "/e", true, 3).unwrap(),
+            vec!(2));
+        assert!(file_store.get_file("/d").is_none());
+
+        let root = file_store.get_file("/").unwrap();
+        assert_eq!(file_store.get_children(root.get_inode())
+            .unwrap().len(), 2);
+    }
+
+    #[test]
+    fn replication() {
+        use super::FileStore;
+
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a", 493, "root", "root", true, 0).unwrap();
+        file_store.create("/a/b", 420, "root", "root",
+            3, 65536, "client", 0).unwrap();
+        file_store.add_block("/a/b", 1, 0, 0).unwrap();
+
+        // blocks of files under construction are not managed
+        assert!(file_store.get_block_replication(&1).is_none());
+        file_store.complete("/a/b", 1).unwrap();
+        assert_eq!(file_store.get_block_replication(&1), Some(3));
+
+        // replication is only set on regular files
+        assert!(file_store.set_replication("/a/b", 1).unwrap());
+        assert!(!file_store.set_replication("/a", 1).unwrap());
+        assert!(file_store.set_replication("/a/c", 1).is_err());
+        assert_eq!(file_store.get_block_replication(&1), Some(1));
+
+        // blocks shared with snapshots use the largest replication
+        file_store.allow_snapshot("/a").unwrap();
+        file_store.create_snapshot("/a", "s1", 2).unwrap();
+        file_store.set_replication("/a/b", 2).unwrap();
+        file_store.create_snapshot("/a", "s2", 3).unwrap();
+        file_store.set_replication("/a/b", 1).unwrap();
+        assert_eq!(file_store.get_block_replication(&1), Some(2));
+    }
+
+    #[test]
+    fn snapshots() {
+        use super::FileStore;
+        use shared::NahFSError;
+
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a/b", 493, "root", "root", true, 0).unwrap();
+        for (path, block_id) in vec!(("/a/b/c", 1), ("/a/d", 2)) {
+            file_store.create(path, 420, "root", "root",
+                3, 65536, "client", 0).unwrap();
+            file_store.add_block(path, block_id, 0, 0).unwrap();
+            file_store.commit_block(path, block_id, 64).unwrap();
+            file_store.complete(path, 0).unwrap();
+        }
+
+        // snapshots require snapshottable directories
+        assert!(file_store.create_snapshot("/a", "s1", 1).is_err());
+        file_store.allow_snapshot("/a").unwrap();
+        file_store.create_snapshot("/a", "s1", 1).unwrap();
+        assert!(file_store.create_snapshot("/a", "s1", 2).is_err());
+
+        // snapshots share unmodified inodes with the live namespace
+        let inode = file_store.get_file("/a/b/c").unwrap().get_inode();
+        assert_eq!(file_store.get_file("/a/.snapshot/s1/b/c")
+            .unwrap().get_inode(), inode);
+        let snapshot = file_store.get_file("/a/.snapshot").unwrap();
+        assert_eq!(file_store.get_children(snapshot.get_inode())
+            .unwrap().len(), 1);
+        assert_eq!(file_store.get_children_count(
+            file_store.get_file("/a").unwrap().get_inode()), 2);
+
+        // snapshots are read-only
+        match file_store.delete("/a/.snapshot/s1/b", true, 2) {
+            Err(NahFSError::SnapshotAccessControlException(_)) => {},
+            _ => panic!("expected SnapshotAccessControlException"),
+        }
+
+        match file_store.mkdirs("/a/.snapshot/s2",
+                493, "root", "root", true, 2) {
+            Err(NahFSError::SnapshotAccessControlException(_)) => {},
+            _ => panic!("expected SnapshotAccessControlException"),
+        }
+
+        assert!(file_store.set_permission("/a/.snapshot/s1/b/c",
+            384).is_err());
+
+        // modified inodes are copied - snapshots keep the original
+        assert!(file_store.is_snapshot_block(inode, &1));
+        file_store.set_client_name("/a/b/c", Some("client")).unwrap();
+        file_store.add_block("/a/b/c", 3, 0, 2).unwrap();
+
+        let file = file_store.get_file("/a/.snapshot/s1/b/c").unwrap();
+        assert_ne!(file.get_inode(), inode);
+        assert_eq!(file_store.compute_path(file.get_inode()),
+            "/a/.snapshot/s1/b/c");
+        assert_eq!(file_store.get_snapshot_blocks(file), &[1]);
+        assert!(!file_store.is_snapshot_block(inode, &3));
+
+        // snapshots exclude the block being written
+        file_store.create_snapshot("/a", "s2", 3).unwrap();
+        let file = file_store.get_file("/a/.snapshot/s2/b/c").unwrap();
+        assert_eq!(file.get_inode(), inode);
+        assert_eq!(file_store.get_snapshot_blocks(file), &[1]);
+
+        file_store.commit_block("/a/b/c", 3, 64).unwrap();
+        file_store.complete("/a/b/c", 4).unwrap();
+        let file = file_store.get_file("/a/.snapshot/s2/b/c").unwrap();
+        assert_eq!(file_store.get_snapshot_blocks(file), &[1]);
+        let file = file_store.get_file("/a/b/c").unwrap();
+        assert_eq!(file_store.get_snapshot_blocks(file), &[1, 3]);
+
+        // deleted files are retained by snapshots - releasing blocks
+        //  only referenced by the live namespace
+        assert_eq!(file_store.delete("/a/b", true, 5).unwrap(), vec!(3));
+        assert_eq!(file_store.delete("/a/d", true, 5).unwrap().len(), 0);
+        assert!(file_store.get_file("/a/b/c").is_none());
+        let file = file_store.get_file("/a/.snapshot/s1/d").unwrap();
+        assert_eq!(file_store.compute_path(file.get_inode()),
+            "/a/.snapshot/s1/d");
+
+        // snapshotted directories may not be deleted
+        assert!(file_store.delete("/a", true, 6).is_err());
+
+        // references are rebuilt from the namespace
+        file_store.restore();
+
+        // deleting the last reference releases blocks
+        assert_eq!(file_store.delete_snapshot("/a", "s1").unwrap().len(), 0);
+        assert!(file_store.get_file("/a/.snapshot/s1").is_none());
+        assert!(file_store.get_file("/a/.snapshot/s2/d").is_some());
+
+        let mut block_ids = file_store.delete_snapshot("/a", "s2").unwrap();
+        block_ids.sort();
+        assert_eq!(block_ids, vec!(1, 2));
+        assert!(file_store.get_block_ids().is_empty());
+        assert!(file_store.delete("/a", true, 7).is_ok());
+    }
+
+    #[test]
+    fn storage_policies() {
+        use super::FileStore;
+
+        let mut file_store = FileStore::new();
+        for path in vec!("/a", "/b", "/c") {
+            file_store.mkdirs(path, 493, "root", "root", false, 0).unwrap();
+        }
+
+        // malformed storage policies are rejected
+        assert!(file_store.set_storage_policy("/a",
+            "Wkt(spatial_idx:3)").is_err());
+
+        // identical storage policies share an id
+        file_store.set_storage_policy("/a", "Wkt(spatial_index:3)").unwrap();
+        file_store.set_storage_policy("/b", "Wkt(spatial_index:3)").unwrap();
+        file_store.set_storage_policy("/c", "Wkt(spatial_index:4)").unwrap();
+        let storage_policies = file_store.get_storage_policies();
+        assert_eq!(storage_policies.len(), 2);
+
+        let (id, _) = storage_policies[0];
+        assert_eq!(file_store.get_storage_policy_paths(id),
+            vec!("/a".to_string(), "/b".to_string()));
+
+        // storage policies remain cataloged while referenced
+        file_store.unset_storage_policy("/a").unwrap();
+        let inode = file_store.get_file("/a").unwrap().get_inode();
+        assert!(file_store.get_storage_policy_id(&inode).is_none());
+        assert_eq!(file_store.get_storage_policy_paths(id),
+            vec!("/b".to_string()));
+        assert_eq!(file_store.get_storage_policies().len(), 2);
+        assert!(file_store.unset_storage_policy("/d").is_err());
+
+        file_store.set_storage_policy("/b", "Wkt(spatial_index:4)").unwrap();
+        assert_eq!(file_store.get_storage_policies().len(), 1);
+        assert!(file_store.get_storage_policy_str(&id).is_none());
+
+        // snapshots reference storage policies of their files
+        let (id, _) = file_store.get_storage_policies()[0];
+        file_store.allow_snapshot("/c").unwrap();
+        file_store.create_snapshot("/c", "s1", 1).unwrap();
+        file_store.delete("/b", true, 1).unwrap();
+        file_store.unset_storage_policy("/c").unwrap();
+        assert_eq!(file_store.get_storage_policy_paths(id),
+            vec!("/c/.snapshot/s1".to_string()));
+
+        file_store.restore();
+        assert_eq!(file_store.get_storage_policy_paths(id),
+            vec!("/c/.snapshot/s1".to_string()));
+
+        file_store.delete_snapshot("/c", "s1").unwrap();
+        assert_eq!(file_store.get_storage_policies().len(), 0);
+    }
+
+    #[test]
+    fn under_construction() {
+        use super::FileStore;
+
+        let mut file_store = FileStore::new();
+        file_store.create("/a", 420, "root", "root",
+            3, 65536, "client", 0).unwrap();
+        assert_eq!(file_store.get_file("/a").unwrap()
+            .get_client_name(), Some("client"));
+        assert_eq!(file_store.get_open_files().len(), 1);
+
+        // completing the file releases it
+        file_store.complete("/a", 1).unwrap();
+        assert!(!file_store.get_file("/a").unwrap().is_under_construction());
+        assert_eq!(file_store.get_open_files().len(), 0);
+
+        // reopening the file for another client
+        file_store.set_client_name("/a", Some("other")).unwrap();
+        assert_eq!(file_store.get_file("/a").unwrap()
+            .get_client_name(), Some("other"));
+    }
+
+    #[test]
+    fn xattrs() {
+        use super::FileStore;
+
+        let mut file_store = FileStore::new();
+        file_store.create("/a", 420, "root", "root",
+            3, 65536, "client", 0).unwrap();
+
+        // xattrs require the create or replace flag
+        assert!(file_store.set_xattr("/a", "user.source",
+            b"http://x", false, true).is_err());
+        file_store.set_xattr("/a", "user.source",
+            b"http://x", true, false).unwrap();
+        assert!(file_store.set_xattr("/a", "user.source",
+            b"http://y", true, false).is_err());
+        file_store.set_xattr("/a", "user.source",
+            b"http://y", false, true).unwrap();
+
+        let file = file_store.get_file("/a").unwrap();
+        assert_eq!(file.get_xattrs().get("user.source"),
+            Some(&b"http://y".to_vec()));
+
+        // combined name and value sizes are limited
+        let value = vec!(0; 16384 - "user.blob".len());
+        file_store.set_xattr("/a", "user.blob", &value, true, false).unwrap();
+        assert!(file_store.set_xattr("/a", "user.blob",
+            &[0; 16384], false, true).is_err());
+        assert_eq!(file_store.get_file("/a").unwrap()
+            .get_xattrs().get("user.blob"), Some(&value));
+        file_store.remove_xattr("/a", "user.blob").unwrap();
+
+        // removing missing xattrs fails
+        file_store.remove_xattr("/a", "user.source").unwrap();
+        assert!(file_store.remove_xattr("/a", "user.source").is_err());
+        assert!(file_store.get_file("/a").unwrap()
+            .get_xattrs().is_empty());
+    }
 }
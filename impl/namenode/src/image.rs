@@ -0,0 +1,411 @@
+use serde::{Deserialize, Serialize};
+use shared::NahFSError;
+
+use crate::block::BlockStore;
+use crate::file::FileStore;
+use crate::file::journal::Journal;
+use crate::file::legacy::{FileStoreV1, FileStoreV2,
+    FileStoreV3, FileStoreV4, FileStoreV5, FileStoreV6, FileStoreV7,
+    FileStoreV8, FileStoreV9};
+use crate::index::Index;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+static CHECKPOINT_MONITOR_MS: u64 = 1000;
+static MAGIC: u64 = 0x474d_4953_4648_414e; // 'NAHFSIMG'
+
+// version 1: initial layout
+// version 2: file modification and access times
+// version 3: under construction files
+// version 4: block map and spatiotemporal index
+// version 5: name ordered directory children
+// version 6: directory quotas
+// version 7: directory snapshots
+// version 8: extended attributes
+// version 9: access control lists
+// version 10: sequential id allocation
+// version 11: committed block lengths
+static LAYOUT_VERSION: u32 = 11;
+
+// images written prior to versioning have no header
+static UNVERSIONED_LAYOUT_VERSION: u32 = 3;
+
+#[derive(Deserialize, Serialize)]
+struct ImageHeader {
+    magic: u64,
+    version: u32,
+    checksum: u32,
+    transaction_id: u64,
+}
+
+pub struct Checkpointer {
+    block_store: Arc<RwLock<BlockStore>>,
+    file_store: Arc<RwLock<FileStore>>,
+    index: Arc<RwLock<Index>>,
+    journal: Arc<Mutex<Journal>>,
+    last_checkpoint: Mutex<Instant>,
+    path: String,
+    retain_count: usize,
+}
+
+impl Checkpointer {
+    pub fn new(block_store: Arc<RwLock<BlockStore>>,
+            file_store: Arc<RwLock<FileStore>>, index: Arc<RwLock<Index>>,
+            journal: Arc<Mutex<Journal>>, path: &str,
+            retain_count: usize) -> Checkpointer {
+        Checkpointer {
+            block_store: block_store,
+            file_store: file_store,
+            index: index,
+            journal: journal,
+            last_checkpoint: Mutex::new(Instant::now()),
+            path: path.to_string(),
+            retain_count: retain_count,
+        }
+    }
+
+    pub fn checkpoint(&self) -> Result<(), NahFSError> {
+        // blocks namespace mutations until the journal is truncated
+        let file_store = self.file_store.read().unwrap();
+        let block_store = self.block_store.read().unwrap();
+        let index = self.index.read().unwrap();
+        let mut journal = self.journal.lock().unwrap();
+        write_image(&file_store, &block_store, &index,
+            journal.get_transaction_id(), &self.path, self.retain_count)?;
+
+        // image includes all journaled operations -> truncate journal
+        info!("checkpointed {} journal operations to '{}'",
+            journal.get_count(), self.path);
+        journal.truncate()?;
+
+        *self.last_checkpoint.lock().unwrap() = Instant::now();
+        Ok(())
+    }
+
+    pub fn start(checkpointer: Arc<Checkpointer>, interval_ms: u64,
+            operation_count: u64) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_millis(CHECKPOINT_MONITOR_MS));
+
+            // checkpoint if the interval or operation count is exceeded
+            let count = checkpointer.journal.lock().unwrap().get_count();
+            let elapsed = checkpointer.last_checkpoint
+                .lock().unwrap().elapsed();
+            if count == 0 || (count < operation_count
+                    && elapsed < Duration::from_millis(interval_ms)) {
+                continue;
+            }
+
+            if let Err(e) = checkpointer.checkpoint() {
+                error!("failed to checkpoint image: {}", e);
+            }
+        })
+    }
+}
+
+pub fn read_image(path: &str)
+        -> Result<(FileStore, BlockStore, Index, u64), NahFSError> {
+    let mut file = File::open(path)?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+
+    // parse and validate header
+    let mut payload = &buf[..];
+    match bincode::deserialize_from::<_, ImageHeader>(&mut payload) {
+        Ok(ref header) if header.magic == MAGIC => {
+            let checksum = crc32fast::hash(payload);
+            if checksum != header.checksum {
+                return Err(NahFSError::from(format!(
+                    "image '{}' checksum {:x} does not match expected {:x}",
+                    path, checksum, header.checksum)));
+            }
+
+            let (file_store, block_store, index) =
+                read_layout(header.version, payload)?;
+            Ok((file_store, block_store, index, header.transaction_id))
+        },
+        _ => {
+            // attempt unversioned layouts - newest first
+            for version in (1..UNVERSIONED_LAYOUT_VERSION + 1).rev() {
+                if let Ok((file_store, block_store, index)) =
+                        read_layout(version, &buf) {
+                    return Ok((file_store, block_store, index, 0));
+                }
+            }
+
+            Err(NahFSError::from(format!(
+                "image '{}' has an unknown layout", path)))
+        },
+    }
+}
+
+fn read_layout(version: u32, buf: &[u8])
+        -> Result<(FileStore, BlockStore, Index), NahFSError> {
+    if version != LAYOUT_VERSION {
+        info!("migrating image from layout version {} to {}",
+            version, LAYOUT_VERSION);
+    }
+
+    // migrate legacy layouts through each subsequent layout - legacy
+    //  layouts rebuild the block map and index from datanode reports
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)
+        .unwrap().as_millis() as u64;
+    let (mut file_store, block_store, mut index) = match version {
+        1 => {
+            let file_store: FileStoreV1 = bincode::deserialize(buf)?;
+            (FileStore::from_v8(file_store.migrate(time).migrate()
+                .migrate().migrate().migrate().migrate().migrate(), time,
+                &BlockStore::new()), BlockStore::new(), Index::new())
+        },
+        2 => {
+            let file_store: FileStoreV2 = bincode::deserialize(buf)?;
+            (FileStore::from_v8(file_store.migrate().migrate().migrate()
+                .migrate().migrate().migrate(), time,
+                &BlockStore::new()), BlockStore::new(), Index::new())
+        },
+        3 => {
+            let file_store: FileStoreV3 = bincode::deserialize(buf)?;
+            (FileStore::from_v8(file_store.migrate().migrate()
+                .migrate().migrate().migrate(), time,
+                &BlockStore::new()), BlockStore::new(), Index::new())
+        },
+        4 => {
+            let (file_store, block_store, index): (FileStoreV3,
+                BlockStore, Index) = bincode::deserialize(buf)?;
+            (FileStore::from_v8(file_store.migrate().migrate().migrate()
+                .migrate().migrate(), time, &block_store), block_store, index)
+        },
+        5 => {
+            let (file_store, block_store, index): (FileStoreV4,
+                BlockStore, Index) = bincode::deserialize(buf)?;
+            (FileStore::from_v8(file_store.migrate().migrate().migrate()
+                .migrate(), time, &block_store), block_store, index)
+        },
+        6 => {
+            let (file_store, block_store, index): (FileStoreV5,
+                BlockStore, Index) = bincode::deserialize(buf)?;
+            (FileStore::from_v8(file_store.migrate().migrate().migrate(),
+                time, &block_store), block_store, index)
+        },
+        7 => {
+            let (file_store, block_store, index): (FileStoreV6,
+                BlockStore, Index) = bincode::deserialize(buf)?;
+            (FileStore::from_v8(file_store.migrate().migrate(), time,
+                &block_store), block_store, index)
+        },
+        8 => {
+            let (file_store, block_store, index): (FileStoreV7,
+                BlockStore, Index) = bincode::deserialize(buf)?;
+            (FileStore::from_v8(file_store.migrate(), time, &block_store),
+                block_store, index)
+        },
+        9 => {
+            let (file_store, block_store, index): (FileStoreV8,
+                BlockStore, Index) = bincode::deserialize(buf)?;
+            (FileStore::from_v8(file_store, time, &block_store),
+                block_store, index)
+        },
+        10 => {
+            let (file_store, block_store, index): (FileStoreV9,
+                BlockStore, Index) = bincode::deserialize(buf)?;
+            (FileStore::from_v9(file_store, &block_store),
+                block_store, index)
+        },
+        11 => bincode::deserialize(buf)?,
+        _ => return Err(NahFSError::from(format!(
+            "unsupported image layout version {}", version))),
+    };
+
+    file_store.restore();
+    index.restore()?;
+    Ok((file_store, block_store, index))
+}
+
+pub fn write_image(file_store: &FileStore, block_store: &BlockStore,
+        index: &Index, transaction_id: u64, path: &str,
+        retain_count: usize) -> Result<(), NahFSError> {
+    // serialize image with header
+    let payload = bincode::serialize(&(file_store, block_store, index))?;
+    let header = ImageHeader {
+        magic: MAGIC,
+        version: LAYOUT_VERSION,
+        checksum: crc32fast::hash(&payload),
+        transaction_id: transaction_id,
+    };
+
+    // write image to temporary file
+    let tmp_path = format!("{}.tmp", path);
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&bincode::serialize(&header)?)?;
+        file.write_all(&payload)?;
+        file.sync_all()?;
+    }
+
+    // rotate previous images - 'path.1' being the most recent
+    if retain_count > 0 && Path::new(path).exists() {
+        for i in (1..retain_count).rev() {
+            let src_path = format!("{}.{}", path, i);
+            if Path::new(&src_path).exists() {
+                std::fs::rename(&src_path, format!("{}.{}", path, i + 1))?;
+            }
+        }
+
+        let previous_path = format!("{}.1", path);
+        if Path::new(&previous_path).exists() {
+            std::fs::remove_file(&previous_path)?;
+        }
+
+        std::fs::hard_link(path, &previous_path)?;
+    }
+
+    // atomically replace image and sync parent directory
+    std::fs::rename(&tmp_path, path)?;
+    let parent = match Path::new(path).parent() {
+        Some(parent) if parent.as_os_str().len() != 0 => parent,
+        _ => Path::new("."),
+    };
+
+    File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn read_image() {
+        use super::{read_image, write_image};
+        use crate::block::BlockStore;
+        use crate::file::{FileStore, FileType};
+        use crate::file::legacy::{FileStoreV1, FileV1};
+        use crate::index::Index;
+
+        use std::collections::HashMap;
+
+        let directory = std::env::temp_dir().join(format!(
+            "nahfs-image-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("namenode.bin");
+        let path = path.to_str().unwrap();
+
+        // read versioned image
+        let mut file_store = FileStore::new();
+        file_store.mkdirs("/a", 493, "root", "root", true, 0).unwrap();
+        let mut block_store = BlockStore::new();
+        block_store.update(1, 0, 64, "datanode", "storage");
+        let mut index = Index::new();
+        index.update_spatial(1, "9xj", 64).unwrap();
+        write_image(&file_store, &block_store, &index, 7, path, 0).unwrap();
+
+        let (file_store, block_store, index, transaction_id) =
+            read_image(path).unwrap();
+        assert!(file_store.get_file("/a").is_some());
+        assert!(block_store.get_block(&1).is_some());
+        assert_eq!(index.spatial_blocks_query("9xj"), vec!((1, 64)));
+        assert_eq!(transaction_id, 7);
+
+        // corrupted images fail checksum validation
+        let mut buf = std::fs::read(path).unwrap();
+        let length = buf.len();
+        buf[length - 1] ^= 1;
+        std::fs::write(path, &buf).unwrap();
+        assert!(read_image(path).is_err());
+
+        // migrate unversioned legacy image
+        let file = |inode: u64, file_type: FileType, name: &str,
+                permissions: u32, storage_policy: Option<u32>| FileV1 {
+            inode: inode,
+            file_type: file_type,
+            name: name.to_string(),
+            owner: "bob".to_string(),
+            group: "staff".to_string(),
+            permissions: permissions,
+            storage_policy: storage_policy,
+        };
+
+        let mut file_store = FileStoreV1 {
+            inodes: HashMap::new(),
+            children: HashMap::new(),
+            parents: HashMap::new(),
+            storage_policies: HashMap::new(),
+        };
+        file_store.inodes.insert(2,
+            file(2, FileType::directory(), "", 493, None));
+        file_store.inodes.insert(3,
+            file(3, FileType::directory(), "a", 448, Some(1)));
+        file_store.inodes.insert(4, file(4, FileType::Regular {
+            blocks: vec!(5, 6), replication: 2, block_size: 1024 },
+            "f", 416, None));
+        file_store.children.insert(2, vec!(3));
+        file_store.children.insert(3, vec!(4));
+        file_store.parents.insert(3, 2);
+        file_store.parents.insert(4, 3);
+        file_store.storage_policies.insert(1, "dt(8)".to_string());
+        std::fs::write(path, bincode::serialize(&file_store).unwrap())
+            .unwrap();
+
+        let (file_store, _, _, transaction_id) = read_image(path).unwrap();
+        assert_eq!(transaction_id, 0);
+
+        let directory_file = file_store.get_file("/a").unwrap();
+        assert!(match directory_file.get_file_type() {
+            FileType::Directory => true,
+            _ => false,
+        });
+        assert_eq!(directory_file.get_permissions(), 448);
+        assert_eq!(file_store.get_storage_policy_id(
+            &directory_file.get_inode()), Some(1));
+        assert_eq!(file_store.get_storage_policy_str(&1).unwrap(), "dt(8)");
+
+        let regular_file = file_store.get_file("/a/f").unwrap();
+        assert!(match regular_file.get_file_type() {
+            FileType::Regular {blocks, replication, block_size} =>
+                blocks == &vec!(5, 6) && *replication == 2
+                    && *block_size == 1024,
+            _ => false,
+        });
+        assert_eq!((regular_file.get_owner(), regular_file.get_group()),
+            ("bob", "staff"));
+        assert_eq!(regular_file.get_permissions(), 416);
+        assert!(!regular_file.is_under_construction());
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn write_image() {
+        use super::write_image;
+        use crate::block::BlockStore;
+        use crate::file::FileStore;
+        use crate::index::Index;
+
+        let directory = std::env::temp_dir().join(format!(
+            "nahfs-image-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("namenode.bin");
+        let path = path.to_str().unwrap();
+
+        // retain at most two previous images
+        let (file_store, block_store, index) =
+            (FileStore::new(), BlockStore::new(), Index::new());
+        for _ in 0..4 {
+            write_image(&file_store, &block_store,
+                &index, 0, path, 2).unwrap();
+        }
+
+        let exists = |suffix: &str| std::path::Path::new(
+            &format!("{}{}", path, suffix)).exists();
+        assert!(exists(""));
+        assert!(exists(".1"));
+        assert!(exists(".2"));
+        assert!(!exists(".3"));
+        assert!(!exists(".tmp"));
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
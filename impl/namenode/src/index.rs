@@ -1,20 +1,25 @@
 use radix::RadixTrie;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use shared::{self, NahFSError};
 
 use crate::query::{self, BinaryExpression, CompareExpression, CompareOp, ConstantExpression, EvaluateExpression};
 use crate::query::prefix::{PrefixExpression, PrefixOperation};
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::collections::hash_map::Iter;
 
 pub type TemporalQuery = query::BooleanExpression<u64>;
 pub type SpatialQuery = query::prefix::BooleanExpression;
 
+#[derive(Deserialize, Serialize)]
 pub struct Index {
     spatial_map: HashMap<u64, Vec<(String, u32)>>,
+    #[serde(skip, default = "RadixTrie::new")]
     spatial_trie: RadixTrie<Vec<(u64, usize)>>,
     temporal_map: HashMap<u64, (u64, u64)>,
+    #[serde(skip)]
+    unverified: HashSet<u64>,
 }
 
 impl Index {
@@ -23,9 +28,54 @@ impl Index {
             spatial_map: HashMap::new(),
             spatial_trie: RadixTrie::new(),
             temporal_map: HashMap::new(),
+            unverified: HashSet::new(),
         }
     }
 
+    pub fn get_block_ids(&self) -> HashSet<u64> {
+        self.spatial_map.keys().chain(self.temporal_map.keys())
+            .map(|x| *x).collect()
+    }
+
+    pub fn remove(&mut self, block_id: &u64) {
+        // remove spatial index entries
+        if let Some(geohashes) = self.spatial_map.remove(block_id) {
+            for (geohash, _) in geohashes.iter() {
+                if let Some(blocks) =
+                        self.spatial_trie.get_mut(geohash.as_bytes()) {
+                    blocks.retain(|(value, _)| value != block_id);
+                }
+            }
+
+            trace!("removed spatial index on block {}", block_id);
+        }
+
+        // remove temporal index entry
+        if let Some(_) = self.temporal_map.remove(block_id) {
+            trace!("removed temporal index on block {}", block_id);
+        }
+    }
+
+    pub fn restore(&mut self) -> Result<(), NahFSError> {
+        // rebuild spatial trie from spatial map
+        let mut spatial_trie = RadixTrie::new();
+        for (block_id, geohashes) in self.spatial_map.iter() {
+            for (i, (geohash, _)) in geohashes.iter().enumerate() {
+                let geohash_bytes = geohash.as_bytes();
+                match spatial_trie.get_mut(geohash_bytes) {
+                    Some(blocks) => blocks.push((*block_id, i)),
+                    None => spatial_trie.insert(geohash_bytes,
+                        vec!((*block_id, i)))?,
+                }
+            }
+        }
+
+        // restored entries are unverified until reported by a datanode
+        self.spatial_trie = spatial_trie;
+        self.unverified = self.get_block_ids();
+        Ok(())
+    }
+
     pub fn update_spatial(&mut self, block_id: u64, geohash: &str,
             length: u32) -> Result<(), NahFSError> {
         // add block entry in spatial map 
@@ -70,6 +120,13 @@ impl Index {
         Ok(())
     }
 
+    pub fn verify(&mut self, block_id: &u64) {
+        // replace restored entries with those reported by the datanode
+        if self.unverified.remove(block_id) {
+            self.remove(block_id);
+        }
+    }
+
     pub fn spatial_iter(&self) -> Iter<u64, Vec<(String, u32)>> {
         self.spatial_map.iter()
     }
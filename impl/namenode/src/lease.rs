@@ -0,0 +1,227 @@
+use crate::block::BlockStore;
+use crate::file::{FileStore, FileType};
+use crate::file::journal::{Journal, Operation};
+use crate::file::permission::User;
+use crate::replication::{ReplicationQueue, NEW_BLOCK_GRACE_MS};
+
+use shared::NahFSError;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub struct Lease {
+    pub holder: String,
+    pub user: String,
+    pub inodes: HashSet<u64>,
+    pub last_update: u64,
+}
+
+pub struct LeaseStore {
+    soft_limit: u64,
+    hard_limit: u64,
+    map: HashMap<String, Lease>,
+}
+
+impl LeaseStore {
+    pub fn new(soft_limit: u64, hard_limit: u64) -> LeaseStore {
+        LeaseStore {
+            soft_limit: soft_limit,
+            hard_limit: hard_limit,
+            map: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, holder: &str, user: &str, inode: u64, time: u64) {
+        // get lease, creating if it doesn't exist
+        let lease = self.map.entry(holder.to_string()).or_insert(
+            Lease {
+                holder: holder.to_string(),
+                user: user.to_string(),
+                inodes: HashSet::new(),
+                last_update: time,
+            });
+
+        lease.inodes.insert(inode);
+        lease.last_update = time;
+    }
+
+    pub fn check_user(&self, holder: &str,
+            user: &User) -> Result<(), NahFSError> {
+        // leases may only be used by the user which acquired them
+        match self.map.get(holder) {
+            Some(lease) if lease.user != user.get_name()
+                    && !user.is_superuser() =>
+                Err(NahFSError::AccessControlException(format!(
+                    "Lease of client {} is owned by {} but is accessed \
+                    by {}", holder, lease.user, user.get_name()))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get_expired(&self, time: u64) -> Vec<(String, u64)> {
+        let mut expired = Vec::new();
+        for lease in self.map.values() {
+            if lease.last_update + self.hard_limit < time {
+                for inode in lease.inodes.iter() {
+                    expired.push((lease.holder.clone(), *inode));
+                }
+            }
+        }
+
+        expired
+    }
+
+    pub fn is_soft_limit_expired(&self, holder: &str, time: u64) -> bool {
+        match self.map.get(holder) {
+            Some(lease) => lease.last_update + self.soft_limit < time,
+            None => true,
+        }
+    }
+
+    pub fn remove(&mut self, holder: &str, inode: u64) {
+        let empty = match self.map.get_mut(holder) {
+            Some(lease) => {
+                lease.inodes.remove(&inode);
+                lease.inodes.is_empty()
+            },
+            None => false,
+        };
+
+        if empty {
+            self.map.remove(holder);
+        }
+    }
+
+    pub fn renew(&mut self, holder: &str, user: &User,
+            time: u64) -> Result<(), NahFSError> {
+        self.check_user(holder, user)?;
+        if let Some(lease) = self.map.get_mut(holder) {
+            lease.last_update = time;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn recover_lease(file_store: &mut FileStore, block_store: &BlockStore,
+        lease_store: &mut LeaseStore, journal: &mut Journal,
+        replication_queue: &Mutex<ReplicationQueue>,
+        inode: u64, time: u64) -> Result<(), NahFSError> {
+    // retrieve file path and lease holder
+    let file = file_store.get_file_by_inode(inode);
+    let (path, holder, block_ids) = match file {
+        Some(file) => match (file.get_client_name(), file.get_file_type()) {
+            (Some(client_name), FileType::Regular {blocks,
+                    replication: _, block_size: _}) =>
+                (file_store.compute_path(inode),
+                    client_name.to_string(), blocks.clone()),
+            _ => return Ok(()),
+        },
+        None => return Ok(()),
+    };
+
+    info!("recovering lease for '{}' held by '{}'", path, holder);
+
+    // remove blocks which were never reported by a datanode
+    let mut reported_block_ids = Vec::new();
+    for block_id in block_ids {
+        if block_store.get_block(&block_id)
+                .map(|block| block.locations.is_empty()).unwrap_or(true) {
+            journal.write(&Operation::RemoveBlock {
+                path: path.clone(), block_id: block_id },
+                || file_store.remove_block(&path, block_id))?;
+        } else {
+            reported_block_ids.push(block_id);
+        }
+    }
+
+    // close file and release lease
+    journal.write(&Operation::Complete { path: path.clone(), time: time },
+        || file_store.complete(&path, time))?;
+    lease_store.remove(&holder, inode);
+
+    let mut replication_queue = replication_queue.lock().unwrap();
+    for block_id in reported_block_ids {
+        replication_queue.add(block_id, time + NEW_BLOCK_GRACE_MS);
+    }
+
+    Ok(())
+}
+
+pub fn start_monitor(block_store: Arc<RwLock<BlockStore>>,
+        file_store: Arc<RwLock<FileStore>>, journal: Arc<Mutex<Journal>>,
+        lease_store: Arc<RwLock<LeaseStore>>,
+        replication_queue: Arc<Mutex<ReplicationQueue>>,
+        interval_ms: u64, shutdown: Arc<AtomicBool>) -> JoinHandle<()> {
+    std::thread::spawn(move || while !shutdown.load(Ordering::SeqCst) {
+        std::thread::sleep(Duration::from_millis(interval_ms));
+
+        // check for leases exceeding the hard limit
+        let time = crate::protocol::get_current_time();
+        let expired = {
+            let lease_store = lease_store.read().unwrap();
+            lease_store.get_expired(time)
+        };
+
+        if expired.is_empty() {
+            continue;
+        }
+
+        // recover expired leases
+        let mut file_store = file_store.write().unwrap();
+        let block_store = block_store.read().unwrap();
+        let mut lease_store = lease_store.write().unwrap();
+        let mut journal = journal.lock().unwrap();
+        for (holder, inode) in expired {
+            // ignore files which were removed or reopened by another client
+            let held = file_store.get_file_by_inode(inode)
+                .map(|file| file.get_client_name() == Some(holder.as_str()))
+                .unwrap_or(false);
+
+            if !held {
+                lease_store.remove(&holder, inode);
+            } else if let Err(e) = recover_lease(&mut file_store,
+                    &block_store, &mut lease_store, &mut journal,
+                    &replication_queue, inode, time) {
+                error!("failed to recover lease on inode {}: {}", inode, e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn lease_store() {
+        use super::LeaseStore;
+        use crate::file::permission::User;
+
+        let bob = User::new("bob".to_string(), Vec::new(), false);
+        let eve = User::new("eve".to_string(), Vec::new(), false);
+
+        let mut lease_store = LeaseStore::new(10, 100);
+        lease_store.add("client", "bob", 5, 0);
+        assert!(!lease_store.is_soft_limit_expired("client", 5));
+        assert!(lease_store.is_soft_limit_expired("client", 11));
+        assert!(lease_store.is_soft_limit_expired("other", 0));
+
+        // renewing a lease resets its limits
+        lease_store.renew("client", &bob, 50).unwrap();
+        assert!(lease_store.get_expired(101).is_empty());
+        assert_eq!(lease_store.get_expired(151),
+            vec!(("client".to_string(), 5)));
+
+        // leases are only usable by the user which acquired them
+        assert!(lease_store.check_user("client", &bob).is_ok());
+        assert!(lease_store.check_user("client", &eve).is_err());
+        assert!(lease_store.renew("client", &eve, 200).is_err());
+        assert_eq!(lease_store.get_expired(151).len(), 1);
+
+        // removing the last inode drops the lease
+        lease_store.remove("client", 5);
+        assert!(lease_store.get_expired(151).is_empty());
+    }
+}
@@ -0,0 +1,14 @@
+#[macro_use]
+extern crate log;
+
+pub mod block;
+pub mod datanode;
+pub mod file;
+pub mod image;
+pub mod index;
+pub mod lease;
+pub mod protocol;
+pub mod query;
+pub mod replication;
+pub mod storage;
+pub mod trash;
@@ -4,29 +4,29 @@ extern crate structopt;
 
 use comm::Server;
 use hdfs_comm::rpc::Protocols;
-use shared::NahFSError;
+use signal_hook::iterator::Signals;
 use structopt::StructOpt;
 
-mod block;
-mod datanode;
-mod file;
-mod index;
-mod protocol;
-mod query;
-mod storage;
-
-use block::BlockStore;
-use datanode::DatanodeStore;
-use file::FileStore;
-use index::Index;
-use protocol::{ClientNamenodeProtocol, DatanodeProtocol, NahFSProtocol};
-use storage::StorageStore;
-
-use std::fs::File;
-use std::io::Read;
+use namenode::{image, lease, protocol, replication, trash};
+use namenode::block::BlockStore;
+use namenode::datanode::DatanodeStore;
+use namenode::file::FileStore;
+use namenode::file::journal::Journal;
+use namenode::file::permission::UserStore;
+use namenode::image::Checkpointer;
+use namenode::index::Index;
+use namenode::lease::LeaseStore;
+use namenode::protocol::{ClientNamenodeProtocol, DatanodeProtocol, NahFSProtocol};
+use namenode::replication::{ReplicationQueue, NEW_BLOCK_GRACE_MS};
+use namenode::storage::StorageStore;
+
 use std::net::TcpListener;
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static LEASE_MONITOR_MS: u64 = 2000;
+static REPLICATION_MONITOR_MS: u64 = 3000;
 
 fn main() {
     // initialize logger
@@ -35,16 +35,12 @@ fn main() {
     // parse arguments
     let config = Config::from_args();
 
-    // initialize BlockStore
-    let block_store = Arc::new(RwLock::new(BlockStore::new()));
-    info!("initialized block store");
-
     // initialize DatanodeStore
     let datanode_store = Arc::new(RwLock::new(
         DatanodeStore::new(config.state_queue_length)));
     info!("initialized datanode store");
 
-    // initialize FileStore
+    // initialize FileStore, BlockStore, and Index
     let path = Path::new(&config.persist_path);
     if let Some(parent) = path.parent() {
         if !parent.exists() {
@@ -55,29 +51,102 @@ fn main() {
         }
     }
 
-    let file_store_result: Result<FileStore, _> = match path.exists() {
-        true => read_file_store(&config.persist_path),
-        false => Ok(FileStore::new()),
+    let image_result = match path.exists() {
+        true => image::read_image(&config.persist_path),
+        false => Ok((FileStore::new(), BlockStore::new(), Index::new(), 0)),
     };
 
-    let file_store = match file_store_result {
-        Ok(file_store) => Arc::new(RwLock::new(file_store)),
+    let (mut file_store, mut block_store, mut index, transaction_id) =
+            match image_result {
+        Ok(result) => result,
         Err(e) => {
             error!("failed to initialize file store: {}", e);
             return;
         }
     };
 
+    // initialize Journal - replaying operations since the last persist
+    let journal_path = format!("{}.edits", config.persist_path);
+    let journal = match Journal::open(&journal_path,
+            &mut file_store, &mut block_store, transaction_id) {
+        Ok(journal) => journal,
+        Err(e) => {
+            error!("failed to open journal '{}': {}", journal_path, e);
+            return;
+        }
+    };
+
+    info!("replayed {} journal operations", journal.get_count());
+
+    // remove blocks no longer referenced by a file (ex. deleted since
+    //  the last checkpoint)
+    let file_block_ids = file_store.get_block_ids();
+    let mut orphan_block_ids = index.get_block_ids();
+    orphan_block_ids.extend(block_store.get_block_ids());
+    orphan_block_ids.retain(|block_id| !file_block_ids.contains(block_id));
+    for block_id in orphan_block_ids.iter() {
+        block_store.remove(block_id);
+        index.remove(block_id);
+    }
+
+    if orphan_block_ids.len() != 0 {
+        info!("removed {} orphaned block(s)", orphan_block_ids.len());
+    }
+
+    let file_store = Arc::new(RwLock::new(file_store));
+    let journal = Arc::new(Mutex::new(journal));
     info!("initialized file store");
 
-    // initialize Index
-    let index = Arc::new(RwLock::new(Index::new()));
+    let block_store = Arc::new(RwLock::new(block_store));
+    info!("initialized block store");
+
+    let index = Arc::new(RwLock::new(index));
     info!("initialized index");
- 
+
+    // initialize LeaseStore - reacquiring leases on open files
+    let mut lease_store = LeaseStore::new(config.lease_soft_limit_ms,
+        config.lease_hard_limit_ms);
+    {
+        let time = protocol::get_current_time();
+        let file_store = file_store.read().unwrap();
+        for file in file_store.get_open_files() {
+            lease_store.add(file.get_client_name().unwrap(),
+                file.get_owner(), file.get_inode(), time);
+        }
+    }
+
+    let lease_store = Arc::new(RwLock::new(lease_store));
+    info!("initialized lease store");
+
+    // initialize ReplicationQueue - checking all blocks once datanodes
+    //  have reported
+    let mut replication_queue = ReplicationQueue::new();
+    {
+        let time = protocol::get_current_time();
+        let file_store = file_store.read().unwrap();
+        for block_id in file_store.get_block_ids() {
+            replication_queue.add(block_id, time + NEW_BLOCK_GRACE_MS);
+        }
+    }
+
+    let replication_queue = Arc::new(Mutex::new(replication_queue));
+    info!("initialized replication queue");
+
     // initialize StorageStore
     let storage_store = Arc::new(RwLock::new(
         StorageStore::new(config.state_queue_length)));
     info!("initialized storage store");
+
+    // initialize UserStore - superuser defaults to the namenode user
+    let superuser = match &config.superuser {
+        Some(superuser) => superuser.to_string(),
+        None => std::env::var("USER").unwrap_or("root".to_string()),
+    };
+
+    let user_store = Arc::new(
+        UserStore::new(&superuser, &config.supergroup));
+    info!("initialized user store with superuser '{}' and \
+        supergroup '{}'", superuser, config.supergroup);
     
     // start TcpListener
     let address = format!("{}:{}", config.ip_address, config.port);
@@ -93,19 +162,28 @@ fn main() {
     let mut protocols = Protocols::new();
 
     let client_namenode_protocol = ClientNamenodeProtocol::new(
-        block_store.clone(), datanode_store.clone(),
-        file_store.clone(), index.clone(), storage_store.clone());
+        config.access_time_precision_ms, config.min_replication,
+        config.trash_interval_ms, block_store.clone(), datanode_store.clone(),
+        file_store.clone(), index.clone(), journal.clone(),
+        lease_store.clone(), replication_queue.clone(),
+        storage_store.clone(), user_store.clone());
     protocols.register("org.apache.hadoop.hdfs.protocol.ClientProtocol",
         Box::new(client_namenode_protocol));
 
     let datanode_protocol = DatanodeProtocol::new(block_store.clone(),
-        datanode_store.clone(), storage_store.clone());
+        datanode_store.clone(), file_store.clone(),
+        replication_queue.clone(), storage_store.clone());
     protocols.register("org.apache.hadoop.hdfs.server.protocol.DatanodeProtocol",
         Box::new(datanode_protocol));
 
+    let checkpointer = Arc::new(Checkpointer::new(block_store.clone(),
+        file_store.clone(), index.clone(), journal.clone(),
+        &config.persist_path, config.checkpoint_retain_count));
+
     let nahfs_protocol = NahFSProtocol::new(block_store.clone(),
-        datanode_store.clone(), file_store.clone(),
-        index.clone(), &config.persist_path);
+        checkpointer.clone(), datanode_store.clone(),
+        file_store.clone(), index.clone(), journal.clone(),
+        user_store.clone());
     protocols.register("io.blackpine.nahfs.protocol.NahFSProtocol",
         Box::new(nahfs_protocol));
 
@@ -120,15 +198,52 @@ fn main() {
     }
     info!("started rpc server");
 
-    // keep running indefinitely
-    std::thread::park();
-}
+    // start lease monitor
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let _ = lease::start_monitor(block_store.clone(), file_store.clone(),
+        journal.clone(), lease_store.clone(), replication_queue.clone(),
+        LEASE_MONITOR_MS, shutdown.clone());
+    info!("started lease monitor");
 
-fn read_file_store(path: &str) -> Result<FileStore, NahFSError> {
-    let mut file = File::open(path)?;
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf)?;
-    Ok(bincode::deserialize(&buf[..])?)
+    // start replication monitor
+    let _ = replication::start_monitor(block_store.clone(),
+        datanode_store.clone(), file_store.clone(), storage_store.clone(),
+        replication_queue.clone(), REPLICATION_MONITOR_MS, shutdown.clone());
+    info!("started replication monitor");
+
+    // start checkpointer
+    let _ = Checkpointer::start(checkpointer.clone(),
+        config.checkpoint_interval_ms, config.checkpoint_operations);
+    info!("started checkpointer");
+
+    // start trash monitor
+    if config.trash_interval_ms != 0 {
+        let _ = trash::start_monitor(block_store.clone(),
+            datanode_store.clone(), file_store.clone(), index.clone(),
+            journal.clone(), config.trash_checkpoint_interval_ms,
+            config.trash_interval_ms, shutdown.clone());
+        info!("started trash monitor");
+    }
+
+    // run until terminated
+    let signals = match Signals::new(&[signal_hook::SIGINT,
+            signal_hook::SIGTERM]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("failed to register signal handlers: {}", e);
+            return;
+        },
+    };
+
+    if let Some(signal) = signals.forever().next() {
+        info!("received signal {} - shutting down", signal);
+    }
+
+    // stop monitors from starting new intervals and checkpoint
+    shutdown.store(true, Ordering::SeqCst);
+    if let Err(e) = checkpointer.checkpoint() {
+        error!("failed to checkpoint image on shutdown: {}", e);
+    }
 }
 
 #[derive(Debug, StructOpt)]
@@ -145,4 +260,26 @@ struct Config {
     socket_wait_ms: u64,
     #[structopt(short="s", long="state_queue_length", default_value="10")]
     state_queue_length: usize,
+    #[structopt(short="a", long="access_time_precision_ms", default_value="3600000")]
+    access_time_precision_ms: u64,
+    #[structopt(short="u", long="superuser")]
+    superuser: Option<String>,
+    #[structopt(short="g", long="supergroup", default_value="supergroup")]
+    supergroup: String,
+    #[structopt(short="l", long="lease_soft_limit_ms", default_value="60000")]
+    lease_soft_limit_ms: u64,
+    #[structopt(short="L", long="lease_hard_limit_ms", default_value="3600000")]
+    lease_hard_limit_ms: u64,
+    #[structopt(short="m", long="min_replication", default_value="1")]
+    min_replication: u32,
+    #[structopt(short="c", long="checkpoint_interval_ms", default_value="3600000")]
+    checkpoint_interval_ms: u64,
+    #[structopt(short="o", long="checkpoint_operations", default_value="100000")]
+    checkpoint_operations: u64,
+    #[structopt(short="r", long="checkpoint_retain_count", default_value="2")]
+    checkpoint_retain_count: usize,
+    #[structopt(short="T", long="trash_interval_ms", default_value="0")]
+    trash_interval_ms: u64,
+    #[structopt(short="e", long="trash_checkpoint_interval_ms", default_value="3600000")]
+    trash_checkpoint_interval_ms: u64,
 }
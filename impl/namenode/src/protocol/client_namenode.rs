@@ -1,46 +1,107 @@
 use hdfs_comm::rpc::Protocol;
-use hdfs_comm::protos::hdfs::{AddBlockResponseProto, AddBlockRequestProto, BlockStoragePolicyProto, CompleteResponseProto, CompleteRequestProto, CreateResponseProto, CreateRequestProto, DirectoryListingProto, GetBlockLocationsResponseProto, GetBlockLocationsRequestProto, GetFileInfoResponseProto, GetFileInfoRequestProto, GetListingResponseProto, GetListingRequestProto, GetServerDefaultsResponseProto, GetServerDefaultsRequestProto, GetStoragePolicyResponseProto, GetStoragePolicyRequestProto, MkdirsResponseProto, MkdirsRequestProto, RenameResponseProto, RenameRequestProto, RenewLeaseResponseProto, RenewLeaseRequestProto, SetStoragePolicyResponseProto, SetStoragePolicyRequestProto};
+use hdfs_comm::protos::hdfs::{AbandonBlockResponseProto, AbandonBlockRequestProto, AclEntryProto, AddBlockResponseProto, AddBlockRequestProto, AllowSnapshotResponseProto, AllowSnapshotRequestProto, AppendResponseProto, AppendRequestProto, BlockStoragePolicyProto, CompleteResponseProto, CompleteRequestProto, CreateResponseProto, CreateRequestProto, CreateSnapshotResponseProto, CreateSnapshotRequestProto, DeleteResponseProto, DeleteRequestProto, DeleteSnapshotResponseProto, DeleteSnapshotRequestProto, DirectoryListingProto, ExtendedBlockProto, GetAclStatusResponseProto, GetAclStatusRequestProto, GetAdditionalDatanodeResponseProto, GetAdditionalDatanodeRequestProto, GetBlockLocationsResponseProto, GetBlockLocationsRequestProto, GetContentSummaryResponseProto, GetContentSummaryRequestProto, GetFileInfoResponseProto, GetFileInfoRequestProto, GetListingResponseProto, GetListingRequestProto, GetServerDefaultsResponseProto, GetServerDefaultsRequestProto, GetStoragePoliciesResponseProto, GetStoragePoliciesRequestProto, GetStoragePolicyResponseProto, GetStoragePolicyRequestProto, GetXAttrsResponseProto, GetXAttrsRequestProto, FsPermissionProto, ListXAttrsResponseProto, ListXAttrsRequestProto, MkdirsResponseProto, MkdirsRequestProto, ModifyAclEntriesResponseProto, ModifyAclEntriesRequestProto, RecoverLeaseResponseProto, RecoverLeaseRequestProto, RemoveAclEntriesResponseProto, RemoveAclEntriesRequestProto, RemoveXAttrResponseProto, RemoveXAttrRequestProto, RenameResponseProto, RenameRequestProto, Rename2ResponseProto, Rename2RequestProto, RenewLeaseResponseProto, RenewLeaseRequestProto, SetAclResponseProto, SetAclRequestProto, SetOwnerResponseProto, SetOwnerRequestProto, SetPermissionResponseProto, SetPermissionRequestProto, SetQuotaResponseProto, SetQuotaRequestProto, SetReplicationResponseProto, SetReplicationRequestProto, SetStoragePolicyResponseProto, SetStoragePolicyRequestProto, SetTimesResponseProto, SetTimesRequestProto, SetXAttrResponseProto, SetXAttrRequestProto, UnsetStoragePolicyResponseProto, UnsetStoragePolicyRequestProto, UpdateBlockForPipelineResponseProto, UpdateBlockForPipelineRequestProto, UpdatePipelineResponseProto, UpdatePipelineRequestProto, XAttrProto};
 use prost::Message;
 use shared::NahFSError;
 
 use crate::block::BlockStore;
 use crate::datanode::DatanodeStore;
-use crate::file::{FileStore, FileType};
+use crate::file::{get_stored_block_id, is_snapshot_path, FileStore, FileType};
+use crate::file::acl::{AclEntry, AclScope, AclType};
+use crate::file::journal::{Journal, Operation};
+use crate::file::permission::{self, User, UserStore};
 use crate::index::{Index, SpatialQuery, TemporalQuery};
+use crate::lease::{self, LeaseStore};
+use crate::replication::{ReplicationQueue, NEW_BLOCK_GRACE_MS};
 use crate::storage::StorageStore;
+use crate::trash;
 
-use std::sync::{Arc, RwLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
 
-static FIRST_BIT: u64 = 9223372036854775808;
-static INDEXED_MASK: u64 = 9223371968135299072;
-static NON_INDEXED_MASK: u64 = 9223372036854775807;
+static NEW_BLOCK_FLAG: u32 = 32;
+static QUOTA_DONT_SET: u64 = 9223372036854775807;
+static XATTR_CREATE_FLAG: u32 = 1;
+static XATTR_REPLACE_FLAG: u32 = 2;
 
 pub struct ClientNamenodeProtocol {
+    access_time_precision: u64,
+    min_replication: u32,
+    trash_interval: u64,
     block_store: Arc<RwLock<BlockStore>>,
     datanode_store: Arc<RwLock<DatanodeStore>>,
     file_store: Arc<RwLock<FileStore>>,
     index: Arc<RwLock<Index>>,
+    journal: Arc<Mutex<Journal>>,
+    lease_store: Arc<RwLock<LeaseStore>>,
+    replication_queue: Arc<Mutex<ReplicationQueue>>,
     storage_store: Arc<RwLock<StorageStore>>,
+    user_store: Arc<UserStore>,
 }
 
 impl ClientNamenodeProtocol {
-    pub fn new(block_store: Arc<RwLock<BlockStore>>, 
+    pub fn new(access_time_precision: u64, min_replication: u32,
+            trash_interval: u64, block_store: Arc<RwLock<BlockStore>>,
             datanode_store: Arc<RwLock<DatanodeStore>>,
             file_store: Arc<RwLock<FileStore>>,
             index: Arc<RwLock<Index>>,
-            storage_store: Arc<RwLock<StorageStore>>)
-            -> ClientNamenodeProtocol {
+            journal: Arc<Mutex<Journal>>,
+            lease_store: Arc<RwLock<LeaseStore>>,
+            replication_queue: Arc<Mutex<ReplicationQueue>>,
+            storage_store: Arc<RwLock<StorageStore>>,
+            user_store: Arc<UserStore>) -> ClientNamenodeProtocol {
         ClientNamenodeProtocol {
+            access_time_precision: access_time_precision,
+            min_replication: min_replication,
+            trash_interval: trash_interval,
             block_store: block_store,
             datanode_store: datanode_store,
             file_store: file_store,
             index: index,
+            journal: journal,
+            lease_store: lease_store,
+            replication_queue: replication_queue,
             storage_store: storage_store,
+            user_store: user_store,
         }
     }
 
-    fn add_block(&self, req_buf: &[u8],
+    fn abandon_block(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = AbandonBlockRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = AbandonBlockResponseProto::default();
+
+        // abandon block - ignoring blocks other than the last since
+        //  clients may retry the call
+        debug!("abandonBlock({:?})", request);
+        let block_id = get_stored_block_id(request.b.block_id);
+        let mut file_store = self.file_store.write().unwrap();
+        check_lease(&file_store, &request.src, &request.holder)?;
+        self.check_lease_user(&request.holder, user)?;
+
+        let last_block_id = match file_store.get_file(&request.src)
+                .map(|file| file.get_file_type()) {
+            Some(FileType::Regular {blocks, replication: _,
+                block_size: _}) => blocks.last().map(|x| *x),
+            _ => None,
+        };
+
+        if last_block_id == Some(block_id) {
+            self.log(Operation::RemoveBlock { path: request.src.clone(),
+                block_id: block_id },
+                || file_store.remove_block(&request.src, block_id))?;
+
+            // invalidate partially written replicas unless snapshotted
+            if !file_store.is_block_referenced(&block_id) {
+                self.remove_blocks(&vec!(block_id));
+            }
+        }
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn add_block(&self, user: &User, req_buf: &[u8],
             resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = AddBlockRequestProto
             ::decode_length_delimited(req_buf)?;
@@ -48,22 +109,33 @@ impl ClientNamenodeProtocol {
 
         // add block
         debug!("addBlock({:?})", request);
-        let mut block_id = rand::random::<u64>();
+        let time = super::get_current_time();
+        let (mut block_id, mut generation_stamp) = (0, 0);
         let mut file_store = self.file_store.write().unwrap();
+        check_lease(&file_store, &request.src, &request.client_name)?;
+        self.check_lease_user(&request.client_name, user)?;
+        if let Some(eb_proto) = &request.previous {
+            self.commit_block(&mut file_store, &request.src, eb_proto)?;
+        }
+
         if let Some(file) = file_store.get_file(&request.src) {
-            if let FileType::Regular {blocks: _, replication, block_size: _} =
+            if let FileType::Regular {blocks: _, replication, block_size} =
                     file.get_file_type() {
                 let lb_proto = &mut response.block;
 
-                // compute block id 
-                let mask = if let Some(storage_policy_id) = file_store
-                        .get_storage_policy_id(&file.get_inode()) {
-                    block_id = (block_id & INDEXED_MASK) | FIRST_BIT;
-                    storage_policy_id
-                } else {
-                    block_id = block_id & NON_INDEXED_MASK;
-                    0
-                };
+                // check space quotas of file ancestors
+                let block_store = self.block_store.read().unwrap();
+                file_store.check_space_quota(&request.src,
+                    block_size * *replication as u64)?;
+
+                // allocate block id and generation stamp
+                let storage_policy_id = file_store
+                    .get_storage_policy_id(&file.get_inode());
+                let replication = *replication;
+                block_id = file_store.allocate_block_id(
+                    storage_policy_id.is_some(), &block_store)?;
+                generation_stamp = file_store.allocate_generation_stamp();
+                let mask = storage_policy_id.unwrap_or(0);
 
                 // compute datanode storage usage
                 let datanode_store = self.datanode_store.read().unwrap();
@@ -72,8 +144,17 @@ impl ClientNamenodeProtocol {
                     &datanode_store, &storage_store);
 
                 // choose 'replication' datanodes based on storage usage
-                while lb_proto.locs.len() < *replication as usize {
-                    let index = super::select_block_replica(&datanodes);    
+                //  - excluding nodes which failed previous pipelines
+                let excluded_ids: Vec<String> = request.exclude_nodes.iter()
+                    .map(|din_proto| din_proto.id.datanode_uuid.clone())
+                    .collect();
+                while lb_proto.locs.len() < replication as usize {
+                    let index = match super::select_block_replica(
+                            &datanodes, &excluded_ids) {
+                        Some(index) => index,
+                        None => break,
+                    };
+
                     let datanode = datanode_store
                         .get_datanode(&datanodes[index].0).unwrap();
                     lb_proto.locs.push(super
@@ -82,35 +163,192 @@ impl ClientNamenodeProtocol {
                     datanodes.remove(index);
                 }
 
-                // TODO - remove
-                /*let ids = datanode_store.get_random_ids(*replication);
-                for id in ids {
-                    let datanode = datanode_store.get_datanode(id).unwrap();
-                    lb_proto.locs.push(super
-                        ::to_datanode_info_proto(datanode, None));
-                }*/
+                if lb_proto.locs.len() < self.min_replication as usize {
+                    return Err(NahFSError::from(format!("File {} could \
+                        only be replicated to {} nodes instead of \
+                        minReplication (={}). There are {} datanode(s) \
+                        running and {} node(s) are excluded in this \
+                        operation.", request.src, lb_proto.locs.len(),
+                        self.min_replication, datanode_store
+                            .get_datanodes().len(), excluded_ids.len())));
+                }
 
                 // populate ExtendedBlockProto
                 let mut ex_proto = &mut lb_proto.b;
                 ex_proto.block_id = block_id | mask as u64;
-                ex_proto.generation_stamp = SystemTime::now()
-                    .duration_since(UNIX_EPOCH).unwrap().as_secs() * 1000;
+                ex_proto.generation_stamp = generation_stamp;
             }
         }
 
         // add blockid to file
-        if let Some(file) = file_store.get_file_mut(&request.src) {
-            if let FileType::Regular {blocks, replication: _, block_size: _} =
-                    file.get_file_type_mut() {
-                blocks.push(block_id);
+        self.log(Operation::AddBlock { path: request.src.clone(),
+            block_id: block_id, generation_stamp: generation_stamp,
+            time: time }, || file_store.add_block(&request.src,
+                block_id, generation_stamp, time))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn allow_snapshot(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = AllowSnapshotRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = AllowSnapshotResponseProto::default();
+
+        // only superusers may allow snapshots
+        debug!("allowSnapshot({:?})", request);
+        if !user.is_superuser() {
+            return Err(NahFSError::AccessControlException(format!(
+                "Access denied for user {}. Superuser privilege is \
+                required", user.get_name())));
+        }
+
+        let mut file_store = self.file_store.write().unwrap();
+        self.log(Operation::AllowSnapshot {
+            path: request.snapshot_root.clone() },
+            || file_store.allow_snapshot(&request.snapshot_root))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn append(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = AppendRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = AppendResponseProto::default();
+
+        // append file
+        debug!("append({:?})", request);
+        let time = super::get_current_time();
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_permission(&request.src, user,
+            0, permission::WRITE, 0)?;
+        self.check_lease_user(&request.client_name, user)?;
+
+        let inode = match file_store.get_file(&request.src) {
+            Some(file) => match file.get_file_type() {
+                FileType::Regular {blocks: _, replication: _,
+                    block_size: _} => file.get_inode(),
+                FileType::Directory =>
+                    return Err(NahFSError::FileAlreadyExistsException(
+                        format!("Cannot append to directory {}; already \
+                            exists as a directory.", request.src))),
+            },
+            None => return Err(NahFSError::FileNotFoundException(format!(
+                "Failed to append to non-existent file {} for client {}",
+                request.src, request.client_name))),
+        };
+
+        // recover expired leases before the file is inspected
+        self.recover_expired_lease(&mut file_store, &request.src,
+            &request.client_name, "APPEND_FILE", time)?;
+
+        let mut block_store = self.block_store.write().unwrap();
+        let index = self.index.read().unwrap();
+
+        // find a partial last block to reopen
+        let reopen_block_id = {
+            let file = file_store.get_file(&request.src).unwrap();
+            let (blocks, block_size) = match file.get_file_type() {
+                FileType::Regular {blocks, replication: _, block_size} =>
+                    (blocks, *block_size),
+                FileType::Directory => unreachable!(),
+            };
+
+            // indexed blocks are reordered by geohash and may not be
+            //  extended -> appends must explicitly request a new block
+            let indexed = file_store
+                .get_storage_policy_id(&file.get_inode()).is_some();
+            let new_block = request.flag.unwrap_or(0) & NEW_BLOCK_FLAG != 0;
+            if indexed && !new_block {
+                return Err(NahFSError::Nah(format!("Cannot append to \
+                    indexed file {} without NEW_BLOCK flag",
+                    request.src)));
+            }
+
+            // snapshotted blocks are immutable -> never extended
+            match (new_block, blocks.last()) {
+                (false, Some(block_id)) => match block_store
+                        .get_block(block_id) {
+                    Some(block) if block.length < block_size
+                        && !file_store.is_snapshot_block(inode, block_id) =>
+                            Some(*block_id),
+                    _ => None,
+                },
+                _ => None,
             }
+        };
+
+        // open file for writing
+        self.log(Operation::SetClientName { path: request.src.clone(),
+            client_name: Some(request.client_name.clone()) },
+            || file_store.set_client_name(&request.src,
+                Some(&request.client_name)))?;
+        {
+            let mut lease_store = self.lease_store.write().unwrap();
+            lease_store.add(&request.client_name,
+                user.get_name(), inode, time);
+        }
+
+        // reopen last block under a new generation stamp so datanodes
+        //  may distinguish appended replicas from stale ones
+        if let Some(block_id) = reopen_block_id {
+            let generation_stamp = file_store.allocate_generation_stamp();
+            self.log(Operation::UpdateBlock { block_id: block_id,
+                generation_stamp: generation_stamp, length: None }, || {
+                    block_store.set_generation_stamp(block_id,
+                        generation_stamp, None);
+                    Ok(())
+                })?;
+
+            let file = file_store.get_file(&request.src).unwrap();
+            let blocks = match file.get_file_type() {
+                FileType::Regular {blocks, replication: _,
+                    block_size: _} => blocks,
+                FileType::Directory => unreachable!(),
+            };
+
+            let datanode_infos =
+                super::get_datanode_infos(&self.datanode_store);
+
+            let block = block_store.get_block(&block_id).unwrap();
+            let offset = super::compute_length(blocks,
+                &block_store, &index, &None) - block.length;
+            response.block = Some(super::to_located_block_proto(
+                block, offset, &datanode_infos));
         }
 
+        let file = file_store.get_file(&request.src).unwrap();
+        response.stat = Some(super::to_hdfs_file_status_proto(&request.src,
+            file, &None, &block_store, &file_store, &index));
+
         response.encode_length_delimited(resp_buf)?;
         Ok(())
     }
 
-    fn complete(&self, req_buf: &[u8],
+    fn check_lease_user(&self, client_name: &str,
+            user: &User) -> Result<(), NahFSError> {
+        let lease_store = self.lease_store.read().unwrap();
+        lease_store.check_user(client_name, user)
+    }
+
+    fn commit_block(&self, file_store: &mut FileStore, path: &str,
+            eb_proto: &ExtendedBlockProto) -> Result<(), NahFSError> {
+        // record the length the client wrote to a block - which is
+        //  charged against space quotas instead of the block size
+        if let Some(length) = eb_proto.num_bytes {
+            let block_id = get_stored_block_id(eb_proto.block_id);
+            self.log(Operation::CommitBlock { path: path.to_string(),
+                block_id: block_id, length: length },
+                || file_store.commit_block(path, block_id, length))?;
+        }
+
+        Ok(())
+    }
+
+    fn complete(&self, user: &User, req_buf: &[u8],
             resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = CompleteRequestProto
             ::decode_length_delimited(req_buf)?;
@@ -118,17 +356,66 @@ impl ClientNamenodeProtocol {
 
         // complete file
         debug!("complete({:?})", request);
-        let file_store = self.file_store.write().unwrap();
-        if let Some(_) = file_store.get_file(&request.src) {
-            // TODO complete
+
+        // check if last block has reached minimum replication - under
+        //  read locks since clients poll complete until it has
+        let (inode, replicated) = {
+            let file_store = self.file_store.read().unwrap();
+            check_lease(&file_store, &request.src, &request.client_name)?;
+            self.check_lease_user(&request.client_name, user)?;
+
+            let file = file_store.get_file(&request.src).unwrap();
+            let last_block_id = match file.get_file_type() {
+                FileType::Regular {blocks, replication: _, block_size: _} =>
+                    blocks.last().map(|x| *x),
+                FileType::Directory => None,
+            };
+
+            let replicated = match last_block_id {
+                Some(block_id) => {
+                    let block_store = self.block_store.read().unwrap();
+                    match block_store.get_block(&block_id) {
+                        Some(block) => block.locations.len()
+                            >= self.min_replication as usize,
+                        None => false,
+                    }
+                },
+                None => true,
+            };
+
+            (file.get_inode(), replicated)
+        };
+
+        // if replicated -> close file and release lease
+        if replicated {
+            let time = super::get_current_time();
+            let mut file_store = self.file_store.write().unwrap();
+
+            // lease may have been recovered since checking replication
+            check_lease(&file_store, &request.src, &request.client_name)?;
+            if let Some(eb_proto) = &request.last {
+                self.commit_block(&mut file_store,
+                    &request.src, eb_proto)?;
+            }
+
+            self.log(Operation::Complete {
+                path: request.src.clone(), time: time },
+                || file_store.complete(&request.src, time))?;
+
+            let mut lease_store = self.lease_store.write().unwrap();
+            lease_store.remove(&request.client_name, inode);
+
+            // check replication once pipeline replicas are reported
+            self.queue_replication(&file_store, &request.src,
+                time + NEW_BLOCK_GRACE_MS);
         }
 
-        response.result = true;
+        response.result = replicated;
         response.encode_length_delimited(resp_buf)?;
         Ok(())
     }
 
-    fn create(&self, user: &str, req_buf: &[u8],
+    fn create(&self, user: &User, req_buf: &[u8],
             resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = CreateRequestProto
             ::decode_length_delimited(req_buf)?;
@@ -136,24 +423,226 @@ impl ClientNamenodeProtocol {
 
         // create file
         debug!("create({:?})", request);
+        let time = super::get_current_time();
         let mut file_store = self.file_store.write().unwrap();
-        file_store.create(&request.src, request.masked.perm,
-            user, user, request.replication, request.block_size);
+        file_store.check_permission(&request.src, user,
+            permission::WRITE, 0, 0)?;
+        self.check_lease_user(&request.client_name, user)?;
+        self.recover_expired_lease(&mut file_store, &request.src,
+            &request.client_name, "CREATE_FILE", time)?;
+
+        self.log(Operation::Create { path: request.src.clone(),
+            permissions: request.masked.perm,
+            owner: user.get_name().to_string(),
+            group: user.get_name().to_string(),
+            replication: request.replication,
+            block_size: request.block_size,
+            client_name: request.client_name.clone(), time: time },
+            || file_store.create(&request.src, request.masked.perm,
+                user.get_name(), user.get_name(), request.replication,
+                request.block_size, &request.client_name, time))?;
+
+        // acquire lease on file
+        let file = file_store.get_file(&request.src).unwrap();
+        {
+            let mut lease_store = self.lease_store.write().unwrap();
+            lease_store.add(&request.client_name,
+                user.get_name(), file.get_inode(), time);
+        }
 
         // get file
-        if let Some(file) = file_store.get_file(&request.src) {
-            let block_store = self.block_store.read().unwrap();
-            let index = self.index.read().unwrap();
-            response.fs = Some(crate::protocol
-                ::to_hdfs_file_status_proto(file, &None,
-                    &block_store, &file_store, &index));
+        let block_store = self.block_store.read().unwrap();
+        let index = self.index.read().unwrap();
+        response.fs = Some(crate::protocol::to_hdfs_file_status_proto(
+            &request.src, file, &None, &block_store, &file_store, &index));
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn create_snapshot(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = CreateSnapshotRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = CreateSnapshotResponseProto::default();
+
+        // create snapshot - named by creation time if unspecified
+        debug!("createSnapshot({:?})", request);
+        let time = super::get_current_time();
+        let name = match &request.snapshot_name {
+            Some(name) => name.to_string(),
+            None => format!("s{}", time),
+        };
+
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_owner(&request.snapshot_root, user)?;
+        self.log(Operation::CreateSnapshot {
+            path: request.snapshot_root.clone(),
+            name: name.clone(), time: time }, || file_store
+                .create_snapshot(&request.snapshot_root, &name, time))?;
+
+        response.snapshot_path = format!("{}/.snapshot/{}",
+            request.snapshot_root.trim_end_matches("/"), name);
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn delete(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = DeleteRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = DeleteResponseProto::default();
+
+        // delete file
+        debug!("delete({:?})", request);
+        let mut file_store = self.file_store.write().unwrap();
+        let sub_access = match request.recursive {
+            true => permission::ALL,
+            false => 0,
+        };
+        file_store.check_permission(&request.src, user,
+            permission::WRITE, 0, sub_access)?;
+        let time = super::get_current_time();
+
+        // move file to trash if enabled
+        if self.trash_interval != 0 {
+            let result = trash::move_to_trash(&mut file_store,
+                &mut self.journal.lock().unwrap(), &self.user_store,
+                user, &request.src, request.recursive, time);
+
+            match result {
+                Ok(true) => {
+                    response.result = true;
+                    response.encode_length_delimited(resp_buf)?;
+                    return Ok(());
+                },
+                Ok(false) => {}, // delete permanently
+                Err(NahFSError::FileNotFoundException(_)) => {
+                    response.encode_length_delimited(resp_buf)?;
+                    return Ok(());
+                },
+                Err(e) => return Err(e),
+            }
+        }
+
+        let block_ids = match self.log(Operation::Delete {
+                path: request.src.clone(), recursive: request.recursive,
+                time: time }, || file_store.delete(&request.src,
+                    request.recursive, time)) {
+            Ok(block_ids) => Some(block_ids),
+            Err(NahFSError::FileNotFoundException(_)) => None,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(block_ids) = block_ids {
+            self.remove_blocks(&block_ids);
+
+            response.result = true;
+        }
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn delete_snapshot(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = DeleteSnapshotRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = DeleteSnapshotResponseProto::default();
+
+        // delete snapshot
+        debug!("deleteSnapshot({:?})", request);
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_owner(&request.snapshot_root, user)?;
+        let block_ids = self.log(Operation::DeleteSnapshot {
+            path: request.snapshot_root.clone(),
+            name: request.snapshot_name.clone() }, || file_store
+                .delete_snapshot(&request.snapshot_root,
+                    &request.snapshot_name))?;
+        self.remove_blocks(&block_ids);
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn get_acl_status(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = GetAclStatusRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = GetAclStatusResponseProto::default();
+
+        // get acl status
+        debug!("getAclStatus({:?})", request);
+        let file_store = self.file_store.read().unwrap();
+        file_store.check_permission(&request.src, user, 0, 0, 0)?;
+        let file = file_store.get_file(&request.src).ok_or_else(||
+            NahFSError::FileNotFoundException(
+                format!("File does not exist: {}", request.src)))?;
+
+        let as_proto = &mut response.result;
+        as_proto.owner = file.get_owner().to_string();
+        as_proto.group = file.get_group().to_string();
+        as_proto.sticky = file.get_permissions()
+            & permission::STICKY_BIT != 0;
+        for entry in file.get_acl().iter() {
+            as_proto.entries.push(to_acl_entry_proto(entry));
+        }
+
+        let mut fp_proto = FsPermissionProto::default();
+        fp_proto.perm = file.get_permissions();
+        as_proto.permission = Some(fp_proto);
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn get_additional_datanode(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = GetAdditionalDatanodeRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = GetAdditionalDatanodeResponseProto::default();
+
+        // replace failed pipeline datanodes
+        debug!("getAdditionalDatanode({:?})", request);
+        let file_store = self.file_store.read().unwrap();
+        check_lease(&file_store, &request.src, &request.client_name)?;
+        self.check_lease_user(&request.client_name, user)?;
+
+        let datanode_store = self.datanode_store.read().unwrap();
+        let storage_store = self.storage_store.read().unwrap();
+        let mut datanodes = super::get_datanode_usage(
+            &datanode_store, &storage_store);
+
+        // existing datanodes are returned followed by additional
+        //  datanodes - which exclude existing and failed nodes
+        let lb_proto = &mut response.block;
+        lb_proto.b = request.blk.clone();
+        lb_proto.locs.extend(request.existings.iter().cloned());
+
+        let excluded_ids: Vec<String> = request.existings.iter()
+            .chain(request.excludes.iter())
+            .map(|din_proto| din_proto.id.datanode_uuid.clone())
+            .collect();
+        for _ in 0..request.num_additional_nodes {
+            let index = match super::select_block_replica(
+                    &datanodes, &excluded_ids) {
+                Some(index) => index,
+                None => break,
+            };
+
+            let datanode = datanode_store
+                .get_datanode(&datanodes[index].0).unwrap();
+            lb_proto.locs.push(super
+                ::to_datanode_info_proto(datanode, None));
+
+            datanodes.remove(index);
         }
 
         response.encode_length_delimited(resp_buf)?;
         Ok(())
     }
 
-    fn get_block_locations(&self, req_buf: &[u8],
+    fn get_block_locations(&self, user: &User, req_buf: &[u8],
             resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = GetBlockLocationsRequestProto
             ::decode_length_delimited(req_buf)?;
@@ -163,23 +652,78 @@ impl ClientNamenodeProtocol {
         debug!("getBlockLocations({:?})", request);
         let (path, query) = parse_embedded_query_path(&request.src)?;
 
-        let file_store = self.file_store.read().unwrap();
-        if let Some(file) = file_store.get_file(path) {
-            let block_store = self.block_store.read().unwrap();
-            let datanode_store = self.datanode_store.read().unwrap();
-            let index = self.index.read().unwrap();
-            let storage_store = self.storage_store.read().unwrap();
+        // update access time if older than configured precision
+        //  - checking read access first and never updating snapshots
+        let time = super::get_current_time();
+        let expired = {
+            let file_store = self.file_store.read().unwrap();
+            file_store.check_permission(path, user,
+                0, permission::READ, 0)?;
+            match file_store.get_file(path) {
+                Some(file) => self.access_time_precision != 0
+                    && !is_snapshot_path(path)
+                    && file.get_access_time()
+                        + self.access_time_precision <= time,
+                None => false,
+            }
+        };
 
-            response.locations = Some(crate::protocol
-                ::to_located_blocks_proto(file, &query, &block_store,
-                    &datanode_store, &index, &storage_store));
+        if expired {
+            let mut file_store = self.file_store.write().unwrap();
+            self.log(Operation::SetTimes { path: path.to_string(),
+                modification_time: None, access_time: Some(time) },
+                || file_store.set_times(path, None, Some(time)))?;
         }
 
+        // retrieve datanode state before acquiring metadata locks
+        let datanode_infos =
+            super::get_datanode_infos(&self.datanode_store);
+
+        let file_store = self.file_store.read().unwrap();
+        file_store.check_permission(path, user, 0, permission::READ, 0)?;
+        let file = file_store.get_file(path).ok_or_else(||
+            NahFSError::FileNotFoundException(
+                format!("File does not exist: {}", path)))?;
+
+        let block_store = self.block_store.read().unwrap();
+        let index = self.index.read().unwrap();
+
+        response.locations = Some(crate::protocol
+            ::to_located_blocks_proto(path, file, &query, &block_store,
+                &file_store, &index, &datanode_infos));
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn get_content_summary(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = GetContentSummaryRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = GetContentSummaryResponseProto::default();
+
+        // get content summary
+        debug!("getContentSummary({:?})", request);
+        let (path, query) = parse_embedded_query_path(&request.path)?;
+
+        let file_store = self.file_store.read().unwrap();
+        file_store.check_permission(path, user, 0, 0,
+            permission::READ | permission::EXECUTE)?;
+        let file = file_store.get_file(path).ok_or_else(||
+            NahFSError::FileNotFoundException(
+                format!("File does not exist: {}", path)))?;
+
+        let block_store = self.block_store.read().unwrap();
+        let index = self.index.read().unwrap();
+
+        response.summary = super::to_content_summary_proto(path, file,
+            &query, &block_store, &file_store, &index);
+
         response.encode_length_delimited(resp_buf)?;
         Ok(())
     }
 
-    fn get_file_info(&self, req_buf: &[u8],
+    fn get_file_info(&self, user: &User, req_buf: &[u8],
             resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = GetFileInfoRequestProto
             ::decode_length_delimited(req_buf)?;
@@ -190,11 +734,12 @@ impl ClientNamenodeProtocol {
         let (path, query) = parse_embedded_query_path(&request.src)?;
 
         let file_store = self.file_store.read().unwrap();
+        file_store.check_permission(path, user, 0, 0, 0)?;
         if let Some(file) = file_store.get_file(path) {
             let block_store = self.block_store.read().unwrap();
             let index = self.index.read().unwrap();
             response.fs = Some(crate::protocol
-                ::to_hdfs_file_status_proto(file, &query,
+                ::to_hdfs_file_status_proto(path, file, &query,
                     &block_store, &file_store, &index));
         }
 
@@ -202,7 +747,7 @@ impl ClientNamenodeProtocol {
         Ok(())
     }
 
-    fn get_listing(&self, req_buf: &[u8],
+    fn get_listing(&self, user: &User, req_buf: &[u8],
             resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = GetListingRequestProto
             ::decode_length_delimited(req_buf)?;
@@ -213,54 +758,55 @@ impl ClientNamenodeProtocol {
         let (path, query) =
             parse_embedded_query_path(&request.src).unwrap();
 
+        // retrieve datanode state before acquiring metadata locks
+        let datanode_infos = match request.need_location {
+            true => super::get_datanode_infos(&self.datanode_store),
+            false => Arc::new(HashMap::new()),
+        };
+
         let file_store = self.file_store.read().unwrap();
         let mut remaining_entries = 0;
         if let Some(file) = file_store.get_file(path) {
+            let access = match file.get_file_type() {
+                FileType::Directory => permission::READ | permission::EXECUTE,
+                _ => 0,
+            };
+            file_store.check_permission(path, user, 0, access, 0)?;
+
             let block_store = self.block_store.read().unwrap();
             let index = self.index.read().unwrap();
 
             let mut partial_listing = Vec::new();
             match file.get_file_type_code() {
                 1 => {
-                    // parse start_after from request
+                    // resume after the last returned child - which may
+                    // be a full path with an embedded query
                     let start_after =
                         String::from_utf8_lossy(&request.start_after);
-                    let mut process = 
-                            match request.start_after.len() {
-                        0 => true,
-                        _ => false,
+                    let start_after = match start_after.len() {
+                        0 => None,
+                        _ => start_after.split("+").next()
+                            .and_then(|path| path.rsplit("/").next()),
                     };
 
                     let mut byte_count = 0;
-                    let children = file_store
-                        .get_children(file.get_inode()).unwrap();
-                    for (i, child_file) in children.iter().enumerate() {
-                        // check if we process this file
-                        if !process {
-                            if start_after == file_store.compute_path(
-                                    child_file.get_inode()) {
-                                process = true;
-                            }
-
-                            continue;
-                        }
-
+                    let mut children = file_store.get_children_after(
+                        file.get_inode(), start_after).unwrap();
+                    while let Some(child_file) = children.next() {
                         // process this file
+                        let child_path = format!("{}/{}",
+                            path.trim_end_matches("/"), child_file.get_name());
                         let mut hfs_proto = crate::protocol
-                            ::to_hdfs_file_status_proto(child_file,
-                                &query, &block_store, &file_store, &index);
+                            ::to_hdfs_file_status_proto(&child_path,
+                                child_file, &query, &block_store,
+                                &file_store, &index);
 
                         // add locations if necessary
                         if request.need_location {
-                            let datanode_store =
-                                self.datanode_store.read().unwrap();
-                            let storage_store =
-                                self.storage_store.read().unwrap();
-
                             hfs_proto.locations = Some(crate::protocol
-                                ::to_located_blocks_proto(child_file,
-                                    &query, &block_store, &datanode_store,
-                                    &index, &storage_store));
+                                ::to_located_blocks_proto(&child_path,
+                                    child_file, &query, &block_store,
+                                    &file_store, &index, &datanode_infos));
                         }
 
                         byte_count += hfs_proto.encoded_len();
@@ -268,27 +814,22 @@ impl ClientNamenodeProtocol {
 
                         // check if message is too large
                         if byte_count >= 65536 {
-                            remaining_entries = children.len() - 1 - i;
+                            remaining_entries = children.by_ref().count();
                             break;
                         }
                     }
                 },
                 2 => {
                     let mut hfs_proto = crate::protocol
-                        ::to_hdfs_file_status_proto(file, &query,
+                        ::to_hdfs_file_status_proto(path, file, &query,
                             &block_store, &file_store, &index);
 
                     // add locations if necessary
                     if request.need_location {
-                        let datanode_store =
-                            self.datanode_store.read().unwrap();
-                        let storage_store =
-                            self.storage_store.read().unwrap();
-
                         hfs_proto.locations = Some(crate::protocol
-                            ::to_located_blocks_proto(file, &query,
-                                &block_store, &datanode_store,
-                                &index, &storage_store));
+                            ::to_located_blocks_proto(path, file, &query,
+                                &block_store, &file_store, &index,
+                                &datanode_infos));
                     }
 
                     partial_listing.push(hfs_proto);
@@ -329,7 +870,29 @@ impl ClientNamenodeProtocol {
         Ok(())
     }
 
-    fn get_storage_policy(&self, req_buf: &[u8],
+    fn get_storage_policies(&self, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = GetStoragePoliciesRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = GetStoragePoliciesResponseProto::default();
+
+        // get storage policies
+        debug!("getStoragePolicies({:?})", request);
+        let file_store = self.file_store.read().unwrap();
+        for (storage_policy_id, storage_policy) in
+                file_store.get_storage_policies() {
+            let mut bsp_proto = BlockStoragePolicyProto::default();
+            bsp_proto.policy_id = storage_policy_id;
+            bsp_proto.name = storage_policy.to_string();
+
+            response.policies.push(bsp_proto);
+        }
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn get_storage_policy(&self, user: &User, req_buf: &[u8],
               resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = GetStoragePolicyRequestProto
             ::decode_length_delimited(req_buf)?;
@@ -338,15 +901,83 @@ impl ClientNamenodeProtocol {
         // get storage policy
         debug!("getStoragePolicy({:?})", request);
         let file_store = self.file_store.read().unwrap();
-        if let Some(file) = file_store.get_file(&request.path) {
-            if let Some(storage_policy_id) =
-                    file_store.get_storage_policy_id(&file.get_inode()) {
-                let mut bsp_proto = BlockStoragePolicyProto::default();
-                bsp_proto.policy_id = storage_policy_id;
-                bsp_proto.name = file_store.get_storage_policy_str(
-                    &storage_policy_id).unwrap().to_string();
-
-                response.storage_policy = bsp_proto;
+        file_store.check_permission(&request.path, user, 0, 0, 0)?;
+        let file = file_store.get_file(&request.path).ok_or_else(||
+            NahFSError::FileNotFoundException(
+                format!("File does not exist: {}", request.path)))?;
+
+        if let Some(storage_policy_id) =
+                file_store.get_storage_policy_id(&file.get_inode()) {
+            let mut bsp_proto = BlockStoragePolicyProto::default();
+            bsp_proto.policy_id = storage_policy_id;
+            bsp_proto.name = file_store.get_storage_policy_str(
+                &storage_policy_id).unwrap().to_string();
+
+            response.storage_policy = bsp_proto;
+        }
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn get_xattrs(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = GetXAttrsRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = GetXAttrsResponseProto::default();
+
+        // get xattrs
+        debug!("getXAttrs({:?})", request);
+        let file_store = self.file_store.read().unwrap();
+        file_store.check_permission(&request.src, user,
+            0, permission::READ, 0)?;
+        let file = file_store.get_file(&request.src).ok_or_else(||
+            NahFSError::FileNotFoundException(
+                format!("File does not exist: {}", request.src)))?;
+
+        let xattrs = file.get_xattrs();
+        if request.x_attrs.is_empty() {
+            // no names requested -> return all visible xattrs
+            for (name, value) in xattrs.iter() {
+                if is_xattr_visible(name, user) {
+                    response.x_attrs.push(to_xattr_proto(name, Some(value)));
+                }
+            }
+        } else {
+            for xa_proto in request.x_attrs.iter() {
+                let name = to_xattr_name(xa_proto, user)?;
+                match xattrs.get(&name) {
+                    Some(value) => response.x_attrs
+                        .push(to_xattr_proto(&name, Some(value))),
+                    None => return Err(NahFSError::from(format!(
+                        "At least one of the attributes provided \
+                        was not found."))),
+                }
+            }
+        }
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn list_xattrs(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = ListXAttrsRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = ListXAttrsResponseProto::default();
+
+        // list visible xattr names
+        debug!("listXAttrs({:?})", request);
+        let file_store = self.file_store.read().unwrap();
+        file_store.check_permission(&request.src, user,
+            0, permission::READ, 0)?;
+        let file = file_store.get_file(&request.src).ok_or_else(||
+            NahFSError::FileNotFoundException(
+                format!("File does not exist: {}", request.src)))?;
+
+        for name in file.get_xattrs().keys() {
+            if is_xattr_visible(name, user) {
+                response.x_attrs.push(to_xattr_proto(name, None));
             }
         }
 
@@ -354,7 +985,14 @@ impl ClientNamenodeProtocol {
         Ok(())
     }
 
-    fn mkdirs(&self, user: &str, req_buf: &[u8],
+    fn log<T, F>(&self, operation: Operation,
+            mutate: F) -> Result<T, NahFSError>
+            where F: FnOnce() -> Result<T, NahFSError> {
+        let mut journal = self.journal.lock().unwrap();
+        journal.write(&operation, mutate)
+    }
+
+    fn mkdirs(&self, user: &User, req_buf: &[u8],
               resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = MkdirsRequestProto
             ::decode_length_delimited(req_buf)?;
@@ -363,15 +1001,167 @@ impl ClientNamenodeProtocol {
         // create directories
         debug!("mkdirs({:?})", request);
         let mut file_store = self.file_store.write().unwrap();
-        file_store.mkdirs(&request.src, request.masked.perm,
-            user, user, request.create_parent);
+        let ancestor_access = match file_store.get_file(&request.src) {
+            Some(_) => 0,
+            None => permission::WRITE,
+        };
+        file_store.check_permission(&request.src, user,
+            ancestor_access, 0, 0)?;
+        let time = super::get_current_time();
+        self.log(Operation::Mkdirs { path: request.src.clone(),
+            permissions: request.masked.perm,
+            owner: user.get_name().to_string(),
+            group: user.get_name().to_string(),
+            create_parent: request.create_parent, time: time },
+            || file_store.mkdirs(&request.src, request.masked.perm,
+                user.get_name(), user.get_name(),
+                request.create_parent, time))?;
 
         response.result = true;
         response.encode_length_delimited(resp_buf)?;
         Ok(())
     }
 
-    fn rename(&self, req_buf: &[u8],
+    fn modify_acl_entries(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = ModifyAclEntriesRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = ModifyAclEntriesResponseProto::default();
+
+        // modify acl entries
+        debug!("modifyAclEntries({:?})", request);
+        let entries = to_acl_entries(&request.acl_spec)?;
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_owner(&request.src, user)?;
+        self.log(Operation::ModifyAclEntries { path: request.src.clone(),
+            entries: entries.clone() },
+            || file_store.modify_acl_entries(&request.src, &entries))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn recover_expired_lease(&self, file_store: &mut FileStore,
+            path: &str, client_name: &str, operation: &str,
+            time: u64) -> Result<(), NahFSError> {
+        let (inode, holder) = match file_store.get_file(path) {
+            Some(file) => match file.get_client_name() {
+                Some(holder) => (file.get_inode(), holder.to_string()),
+                None => return Ok(()), // file is not under construction
+            },
+            None => return Ok(()),
+        };
+
+        // only leases past the soft limit may be recovered
+        let block_store = self.block_store.read().unwrap();
+        let mut lease_store = self.lease_store.write().unwrap();
+        if holder == client_name {
+            return Err(NahFSError::AlreadyBeingCreatedException(format!(
+                "Failed to {} {} for {} because current leaseholder \
+                is trying to recreate file.", operation, path, client_name)));
+        } else if !lease_store.is_soft_limit_expired(&holder, time) {
+            return Err(NahFSError::AlreadyBeingCreatedException(format!(
+                "Failed to {} {} for {} because this file lease is \
+                currently owned by {}", operation, path, client_name, holder)));
+        }
+
+        let mut journal = self.journal.lock().unwrap();
+        lease::recover_lease(file_store, &block_store, &mut lease_store,
+            &mut journal, &self.replication_queue, inode, time)
+    }
+
+    fn queue_replication(&self, file_store: &FileStore,
+            path: &str, time: u64) {
+        if let Some(FileType::Regular {blocks, replication: _,
+                block_size: _}) = file_store.get_file(path)
+                    .map(|file| file.get_file_type()) {
+            let mut replication_queue = self.replication_queue.lock().unwrap();
+            for block_id in blocks.iter() {
+                replication_queue.add(*block_id, time);
+            }
+        }
+    }
+
+    fn recover_lease(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = RecoverLeaseRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = RecoverLeaseResponseProto::default();
+
+        // recover lease - closing the file
+        debug!("recoverLease({:?})", request);
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_permission(&request.src, user,
+            0, permission::WRITE, 0)?;
+
+        let inode = match file_store.get_file(&request.src) {
+            Some(file) => file.get_inode(),
+            None => return Err(NahFSError::FileNotFoundException(format!(
+                "File does not exist: {}", request.src))),
+        };
+
+        let block_store = self.block_store.read().unwrap();
+        let mut lease_store = self.lease_store.write().unwrap();
+        let mut journal = self.journal.lock().unwrap();
+        lease::recover_lease(&mut file_store, &block_store, &mut lease_store,
+            &mut journal, &self.replication_queue, inode,
+            super::get_current_time())?;
+
+        response.result = true;
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn remove_acl_entries(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = RemoveAclEntriesRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = RemoveAclEntriesResponseProto::default();
+
+        // remove acl entries
+        debug!("removeAclEntries({:?})", request);
+        let entries = to_acl_entries(&request.acl_spec)?;
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_owner(&request.src, user)?;
+        self.log(Operation::RemoveAclEntries { path: request.src.clone(),
+            entries: entries.clone() },
+            || file_store.remove_acl_entries(&request.src, &entries))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn remove_blocks(&self, block_ids: &Vec<u64>) {
+        super::remove_blocks(block_ids, &self.block_store,
+            &self.datanode_store, &self.index);
+    }
+
+    fn remove_xattr(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = RemoveXAttrRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = RemoveXAttrResponseProto::default();
+
+        // remove xattr
+        debug!("removeXAttr({:?})", request);
+        let name = match &request.x_attr {
+            Some(xa_proto) => to_xattr_name(xa_proto, user)?,
+            None => return Err(NahFSError::from(
+                format!("XAttr name cannot be null"))),
+        };
+
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_permission(&request.src, user,
+            0, permission::WRITE, 0)?;
+        self.log(Operation::RemoveXAttr { path: request.src.clone(),
+            name: name.clone() },
+            || file_store.remove_xattr(&request.src, &name))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn rename(&self, user: &User, req_buf: &[u8],
               resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = RenameRequestProto
             ::decode_length_delimited(req_buf)?;
@@ -380,25 +1170,264 @@ impl ClientNamenodeProtocol {
         // create directories
         debug!("rename({:?})", request);
         let mut file_store = self.file_store.write().unwrap();
-        file_store.rename(&request.src, &request.dst);
+        file_store.check_permission(&request.src, user,
+            permission::WRITE, 0, 0)?;
+
+        // existing directory destinations receive the source - so
+        //  the directory itself must be writable
+        let dst_path = file_store
+            .get_rename_destination(&request.src, &request.dst);
+        file_store.check_permission(&dst_path, user,
+            permission::WRITE, 0, 0)?;
+        let time = super::get_current_time();
+        self.log(Operation::Rename { src_path: request.src.clone(),
+            dst_path: request.dst.clone(), time: time },
+            || file_store.rename(&request.src, &request.dst, time))?;
 
         response.result = true;
         response.encode_length_delimited(resp_buf)?;
         Ok(())
     }
 
-    fn renew_lease(&self, req_buf: &[u8],
+    fn rename2(&self, user: &User, req_buf: &[u8],
+              resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = Rename2RequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = Rename2ResponseProto::default();
+
+        // rename file - overwriting destination if requested
+        debug!("rename2({:?})", request);
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_permission(&request.src, user,
+            permission::WRITE, 0, 0)?;
+        file_store.check_permission(&request.dst, user,
+            permission::WRITE, 0, 0)?;
+        let time = super::get_current_time();
+        let block_ids = self.log(Operation::Rename2 {
+            src_path: request.src.clone(), dst_path: request.dst.clone(),
+            overwrite: request.overwrite_dest, time: time },
+            || file_store.rename2(&request.src, &request.dst,
+                request.overwrite_dest, time))?;
+
+        // remove blocks of overwritten destination
+        self.remove_blocks(&block_ids);
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn renew_lease(&self, user: &User, req_buf: &[u8],
               resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = RenewLeaseRequestProto
             ::decode_length_delimited(req_buf)?;
         let response = RenewLeaseResponseProto::default();
 
+        // renew lease
         debug!("renewLease({:?})", request);
+        let mut lease_store = self.lease_store.write().unwrap();
+        lease_store.renew(&request.client_name,
+            user, super::get_current_time())?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn set_acl(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = SetAclRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = SetAclResponseProto::default();
+
+        // set acl
+        debug!("setAcl({:?})", request);
+        let entries = to_acl_entries(&request.acl_spec)?;
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_owner(&request.src, user)?;
+        self.log(Operation::SetAcl { path: request.src.clone(),
+            entries: entries.clone() },
+            || file_store.set_acl(&request.src, &entries))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn set_owner(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = SetOwnerRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = SetOwnerResponseProto::default();
+
+        // only superusers may change owners and owners
+        // may only change the group to one they belong to
+        debug!("setOwner({:?})", request);
+        if !user.is_superuser() {
+            if let Some(username) = &request.username {
+                if username != user.get_name() {
+                    return Err(NahFSError::AccessControlException(format!(
+                        "User {} is not a super user (non-super user \
+                        cannot change owner)", user.get_name())));
+                }
+            }
+
+            if let Some(groupname) = &request.groupname {
+                if !user.is_member(groupname) {
+                    return Err(NahFSError::AccessControlException(format!(
+                        "User {} does not belong to {}",
+                        user.get_name(), groupname)));
+                }
+            }
+        }
+
+        // set owner
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_owner(&request.src, user)?;
+        self.log(Operation::SetOwner { path: request.src.clone(),
+            owner: request.username.clone(),
+            group: request.groupname.clone() },
+            || file_store.set_owner(&request.src,
+                request.username.as_ref().map(|x| x.as_str()),
+                request.groupname.as_ref().map(|x| x.as_str())))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn set_permission(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = SetPermissionRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = SetPermissionResponseProto::default();
+
+        // set permission
+        debug!("setPermission({:?})", request);
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_owner(&request.src, user)?;
+        self.log(Operation::SetPermission { path: request.src.clone(),
+            permissions: request.permission.perm }, || file_store
+                .set_permission(&request.src, request.permission.perm))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn set_quota(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = SetQuotaRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = SetQuotaResponseProto::default();
+
+        // only superusers may set quotas
+        debug!("setQuota({:?})", request);
+        if !user.is_superuser() {
+            return Err(NahFSError::AccessControlException(format!(
+                "Access denied for user {}. Superuser privilege is \
+                required", user.get_name())));
+        } else if request.storage_type.is_some() {
+            return Err(NahFSError::from(format!(
+                "storage type quotas are not supported")));
+        }
+
+        // set quota - where QUOTA_DONT_SET leaves a quota unchanged
+        //  and a value of -1 (QUOTA_RESET) clears it
+        let mut file_store = self.file_store.write().unwrap();
+        let file = file_store.get_file(&request.path).ok_or_else(||
+            NahFSError::FileNotFoundException(
+                format!("File does not exist: {}", request.path)))?;
+        let quota = file_store.get_quota(file.get_inode());
+
+        let namespace_quota = match request.namespace_quota {
+            std::u64::MAX => None,
+            x if x == QUOTA_DONT_SET =>
+                quota.and_then(|quota| quota.namespace),
+            namespace_quota => Some(namespace_quota),
+        };
+
+        let space_quota = match request.storagespace_quota {
+            std::u64::MAX => None,
+            x if x == QUOTA_DONT_SET => quota.and_then(|quota| quota.space),
+            space_quota => Some(space_quota),
+        };
+
+        self.log(Operation::SetQuota { path: request.path.clone(),
+            namespace_quota: namespace_quota, space_quota: space_quota },
+            || file_store.set_quota(&request.path,
+                namespace_quota, space_quota))?;
+
         response.encode_length_delimited(resp_buf)?;
         Ok(())
     }
 
-    fn set_storage_policy(&self, req_buf: &[u8],
+    fn set_replication(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = SetReplicationRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = SetReplicationResponseProto::default();
+
+        // set replication - the replication monitor schedules block
+        //  transfers and deletions to match the new value
+        debug!("setReplication({:?})", request);
+        if request.replication < self.min_replication {
+            return Err(NahFSError::from(format!("Requested replication \
+                factor of {} is less than the required minimum of {} \
+                for {}", request.replication, self.min_replication,
+                request.src)));
+        }
+
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_permission(&request.src, user,
+            0, permission::WRITE, 0)?;
+
+        // replication of directories is never set or journaled
+        response.result = match file_store.get_file(&request.src)
+                .map(|file| file.get_file_type()) {
+            Some(FileType::Directory) => false,
+            _ => self.log(Operation::SetReplication {
+                path: request.src.clone(),
+                replication: request.replication }, || file_store
+                    .set_replication(&request.src, request.replication))?,
+        };
+
+        if response.result {
+            self.queue_replication(&file_store, &request.src,
+                super::get_current_time());
+        }
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn set_times(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = SetTimesRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = SetTimesResponseProto::default();
+
+        // set times - where a value of -1 leaves the time unchanged
+        debug!("setTimes({:?})", request);
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_permission(&request.src, user,
+            0, permission::WRITE, 0)?;
+
+        let modification_time = match request.mtime {
+            std::u64::MAX => None,
+            mtime => Some(mtime),
+        };
+
+        let access_time = match request.atime {
+            std::u64::MAX => None,
+            atime => Some(atime),
+        };
+
+        self.log(Operation::SetTimes { path: request.src.clone(),
+            modification_time: modification_time,
+            access_time: access_time }, || file_store.set_times(
+                &request.src, modification_time, access_time))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn set_storage_policy(&self, user: &User, req_buf: &[u8],
             resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = SetStoragePolicyRequestProto
             ::decode_length_delimited(req_buf).unwrap();
@@ -407,7 +1436,153 @@ impl ClientNamenodeProtocol {
         // create directories
         debug!("setStoragePolicy({:?})", request);
         let mut file_store = self.file_store.write().unwrap();
-        file_store.set_storage_policy(&request.src, &request.policy_name);
+        file_store.check_permission(&request.src, user,
+            0, permission::WRITE, 0)?;
+        self.log(Operation::SetStoragePolicy { path: request.src.clone(),
+            storage_policy: request.policy_name.clone() }, || file_store
+                .set_storage_policy(&request.src, &request.policy_name))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn set_xattr(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = SetXAttrRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = SetXAttrResponseProto::default();
+
+        // set xattr
+        debug!("setXAttr({:?})", request);
+        let (name, value) = match &request.x_attr {
+            Some(xa_proto) => (to_xattr_name(xa_proto, user)?,
+                xa_proto.value.clone().unwrap_or(Vec::new())),
+            None => return Err(NahFSError::from(
+                format!("XAttr name cannot be null"))),
+        };
+
+        let flag = request.flag.unwrap_or(0);
+        let create = flag & XATTR_CREATE_FLAG != 0;
+        let replace = flag & XATTR_REPLACE_FLAG != 0;
+
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_permission(&request.src, user,
+            0, permission::WRITE, 0)?;
+        self.log(Operation::SetXAttr { path: request.src.clone(),
+            name: name.clone(), value: value.clone(), create: create,
+            replace: replace }, || file_store.set_xattr(&request.src,
+                &name, &value, create, replace))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn unset_storage_policy(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = UnsetStoragePolicyRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = UnsetStoragePolicyResponseProto::default();
+
+        // unset storage policy
+        debug!("unsetStoragePolicy({:?})", request);
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_permission(&request.src, user,
+            0, permission::WRITE, 0)?;
+        self.log(Operation::UnsetStoragePolicy {
+            path: request.src.clone() },
+            || file_store.unset_storage_policy(&request.src))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn update_block_for_pipeline(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = UpdateBlockForPipelineRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = UpdateBlockForPipelineResponseProto::default();
+
+        // allocate a new generation stamp for the recovered pipeline
+        debug!("updateBlockForPipeline({:?})", request);
+        let block_id = get_stored_block_id(request.block.block_id);
+        let mut file_store = self.file_store.write().unwrap();
+        get_open_file_path(&file_store, block_id, &request.client_name)?;
+        self.check_lease_user(&request.client_name, user)?;
+
+        // record the issued stamp which updatePipeline must commit
+        let generation_stamp = file_store.allocate_generation_stamp();
+        let mut block_store = self.block_store.write().unwrap();
+        self.log(Operation::SetGenerationStamp {
+            generation_stamp: generation_stamp }, || {
+                block_store.set_pipeline_stamp(block_id, generation_stamp);
+                Ok(())
+            })?;
+
+        let lb_proto = &mut response.block;
+        lb_proto.b = request.block.clone();
+        lb_proto.b.generation_stamp = generation_stamp;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn update_pipeline(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = UpdatePipelineRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = UpdatePipelineResponseProto::default();
+
+        // validate recovered block
+        debug!("updatePipeline({:?})", request);
+        let (old_block, new_block) = (&request.old_block, &request.new_block);
+        let block_id = get_stored_block_id(old_block.block_id);
+        let mut file_store = self.file_store.write().unwrap();
+        let path = get_open_file_path(&file_store,
+            block_id, &request.client_name)?;
+        self.check_lease_user(&request.client_name, user)?;
+
+        if new_block.block_id != old_block.block_id {
+            return Err(NahFSError::from(format!("Update block {} (new) \
+                is not the same block as {} (old)", new_block.block_id,
+                old_block.block_id)));
+        } else if new_block.generation_stamp <= old_block.generation_stamp {
+            return Err(NahFSError::from(format!("Update block {} is not \
+                valid, the new generation stamp {} must be greater than \
+                {}", new_block.block_id, new_block.generation_stamp,
+                old_block.generation_stamp)));
+        }
+
+        let length = new_block.num_bytes.ok_or_else(||
+            NahFSError::from(format!("Update block {} is not valid, \
+                the new length is not set", new_block.block_id)))?;
+
+        let mut block_store = self.block_store.write().unwrap();
+        if block_store.get_pipeline_stamp(&block_id)
+                != Some(new_block.generation_stamp) {
+            return Err(NahFSError::from(format!("Update block {} is not \
+                valid, the new generation stamp {} was not issued by \
+                updateBlockForPipeline", new_block.block_id,
+                new_block.generation_stamp)));
+        }
+
+        // commit generation stamp and length to both stores and drop
+        //  datanodes which left the pipeline - invalidating their
+        //  stale replicas
+        let datanode_ids: Vec<String> = request.new_nodes.iter()
+            .map(|di_proto| di_proto.datanode_uuid.clone()).collect();
+        let removed_ids = self.log(Operation::UpdatePipeline {
+            path: path.clone(), block_id: block_id,
+            generation_stamp: new_block.generation_stamp,
+            length: length }, || {
+                file_store.commit_block(&path, block_id, length)?;
+                Ok(block_store.update_pipeline(block_id,
+                    new_block.generation_stamp, length, &datanode_ids))
+            })?;
+
+        let mut datanode_store = self.datanode_store.write().unwrap();
+        for datanode_id in removed_ids {
+            datanode_store.add_invalid_block(&datanode_id, block_id);
+        }
 
         response.encode_length_delimited(resp_buf)?;
         Ok(())
@@ -417,31 +1592,95 @@ impl ClientNamenodeProtocol {
 impl Protocol for ClientNamenodeProtocol {
     fn process(&self, user: &Option<String>, method: &str,
             req_buf: &[u8], resp_buf: &mut Vec<u8>) -> std::io::Result<()> {
-        let user = match user {
+        let user = self.user_store.get_user(match user {
             Some(user) => user,
             None => "default",
-        };
+        });
 
         match method {
-            "addBlock" => self.add_block(req_buf, resp_buf)?,
-            "complete" => self.complete(req_buf, resp_buf)?,
-            "create" => self.create(user, req_buf, resp_buf)?,
-            "getBlockLocations" => self.get_block_locations(req_buf, resp_buf)?,
-            "getFileInfo" => self.get_file_info(req_buf, resp_buf)?,
-            "getListing" => self.get_listing(req_buf, resp_buf)?,
+            "abandonBlock" => self.abandon_block(&user, req_buf, resp_buf)?,
+            "addBlock" => self.add_block(&user, req_buf, resp_buf)?,
+            "allowSnapshot" => self.allow_snapshot(&user, req_buf, resp_buf)?,
+            "append" => self.append(&user, req_buf, resp_buf)?,
+            "complete" => self.complete(&user, req_buf, resp_buf)?,
+            "create" => self.create(&user, req_buf, resp_buf)?,
+            "createSnapshot" => self.create_snapshot(&user, req_buf, resp_buf)?,
+            "delete" => self.delete(&user, req_buf, resp_buf)?,
+            "deleteSnapshot" => self.delete_snapshot(&user, req_buf, resp_buf)?,
+            "getAclStatus" => self.get_acl_status(&user, req_buf, resp_buf)?,
+            "getAdditionalDatanode" => self.get_additional_datanode(&user, req_buf, resp_buf)?,
+            "getBlockLocations" => self.get_block_locations(&user, req_buf, resp_buf)?,
+            "getContentSummary" => self.get_content_summary(&user, req_buf, resp_buf)?,
+            "getFileInfo" => self.get_file_info(&user, req_buf, resp_buf)?,
+            "getListing" => self.get_listing(&user, req_buf, resp_buf)?,
             "getServerDefaults" => self.get_server_defaults(req_buf, resp_buf)?,
-            "getStoragePolicy" => self.get_storage_policy(req_buf, resp_buf)?,
-            "mkdirs" => self.mkdirs(user, req_buf, resp_buf)?,
-            "rename" => self.rename(req_buf, resp_buf)?,
-            "renewLease" => self.renew_lease(req_buf, resp_buf)?,
-            "setStoragePolicy" => self.set_storage_policy(req_buf, resp_buf)?,
-            _ => error!("unimplemented method '{}'", method),
+            "getStoragePolicies" => self.get_storage_policies(req_buf, resp_buf)?,
+            "getStoragePolicy" => self.get_storage_policy(&user, req_buf, resp_buf)?,
+            "getXAttrs" => self.get_xattrs(&user, req_buf, resp_buf)?,
+            "listXAttrs" => self.list_xattrs(&user, req_buf, resp_buf)?,
+            "mkdirs" => self.mkdirs(&user, req_buf, resp_buf)?,
+            "modifyAclEntries" => self.modify_acl_entries(&user, req_buf, resp_buf)?,
+            "recoverLease" => self.recover_lease(&user, req_buf, resp_buf)?,
+            "removeAclEntries" => self.remove_acl_entries(&user, req_buf, resp_buf)?,
+            "removeXAttr" => self.remove_xattr(&user, req_buf, resp_buf)?,
+            "rename" => self.rename(&user, req_buf, resp_buf)?,
+            "rename2" => self.rename2(&user, req_buf, resp_buf)?,
+            "renewLease" => self.renew_lease(&user, req_buf, resp_buf)?,
+            "setAcl" => self.set_acl(&user, req_buf, resp_buf)?,
+            "setOwner" => self.set_owner(&user, req_buf, resp_buf)?,
+            "setPermission" => self.set_permission(&user, req_buf, resp_buf)?,
+            "setQuota" => self.set_quota(&user, req_buf, resp_buf)?,
+            "setReplication" => self.set_replication(&user, req_buf, resp_buf)?,
+            "setStoragePolicy" => self.set_storage_policy(&user, req_buf, resp_buf)?,
+            "setTimes" => self.set_times(&user, req_buf, resp_buf)?,
+            "setXAttr" => self.set_xattr(&user, req_buf, resp_buf)?,
+            "unsetStoragePolicy" => self.unset_storage_policy(&user, req_buf, resp_buf)?,
+            "updateBlockForPipeline" => self.update_block_for_pipeline(&user, req_buf, resp_buf)?,
+            "updatePipeline" => self.update_pipeline(&user, req_buf, resp_buf)?,
+            _ => return Err(NahFSError::RpcNoSuchMethodException(format!(
+                "Unknown method {} called on \
+                org.apache.hadoop.hdfs.protocol.ClientProtocol protocol.",
+                method)).into()),
         }
 
         Ok(())
     }
 }
 
+fn check_lease(file_store: &FileStore, path: &str,
+        client_name: &str) -> Result<(), NahFSError> {
+    match file_store.get_file(path).map(|file| file.get_client_name()) {
+        Some(Some(holder)) if holder == client_name => Ok(()),
+        Some(Some(holder)) => Err(NahFSError::LeaseExpiredException(format!(
+            "Lease mismatch on {} owned by {} but is accessed by {}",
+            path, holder, client_name))),
+        Some(None) => Err(NahFSError::LeaseExpiredException(format!(
+            "No lease on {}: File is not open for writing. Holder {} \
+            does not have any open files.", path, client_name))),
+        None => Err(NahFSError::LeaseExpiredException(format!(
+            "No lease on {}: File does not exist. Holder {} \
+            does not have any open files.", path, client_name))),
+    }
+}
+
+fn get_open_file_path(file_store: &FileStore, block_id: u64,
+        client_name: &str) -> Result<String, NahFSError> {
+    // find the file under construction containing the block
+    for file in file_store.get_open_files() {
+        if let FileType::Regular {blocks, replication: _, block_size: _} =
+                file.get_file_type() {
+            let path = file_store.compute_path(file.get_inode());
+            if blocks.contains(&block_id) && !is_snapshot_path(&path) {
+                check_lease(file_store, &path, client_name)?;
+                return Ok(path);
+            }
+        }
+    }
+
+    Err(NahFSError::from(format!("Block {} does not exist or is not \
+        under construction", block_id)))
+}
+
 fn parse_embedded_query_path(path: &str) -> Result<(&str,
         Option<(&str, (Option<SpatialQuery>, Option<TemporalQuery>))>), NahFSError> {
     let fields: Vec<&str> = path.split("+").collect();
@@ -453,3 +1692,86 @@ fn parse_embedded_query_path(path: &str) -> Result<(&str,
 
     Ok((fields[0], query))
 }
+
+fn to_acl_entries(ae_protos: &Vec<AclEntryProto>)
+        -> Result<Vec<AclEntry>, NahFSError> {
+    let mut entries = Vec::new();
+    for ae_proto in ae_protos.iter() {
+        let scope = match ae_proto.scope {
+            0 => AclScope::Access,
+            1 => AclScope::Default,
+            scope => return Err(NahFSError::from(format!(
+                "Invalid ACL: unknown entry scope {}", scope))),
+        };
+
+        let acl_type = match ae_proto.type_ {
+            0 => AclType::User,
+            1 => AclType::Group,
+            2 => AclType::Mask,
+            3 => AclType::Other,
+            acl_type => return Err(NahFSError::from(format!(
+                "Invalid ACL: unknown entry type {}", acl_type))),
+        };
+
+        // unnamed entries may be sent with an empty name
+        let name = ae_proto.name.clone().filter(|x| !x.is_empty());
+        entries.push(AclEntry::new(scope, acl_type,
+            name, ae_proto.permissions as u32 & permission::ALL));
+    }
+
+    Ok(entries)
+}
+
+fn to_acl_entry_proto(entry: &AclEntry) -> AclEntryProto {
+    let mut ae_proto = AclEntryProto::default();
+    ae_proto.scope = match entry.scope {
+        AclScope::Access => 0,
+        AclScope::Default => 1,
+    };
+
+    ae_proto.type_ = match entry.acl_type {
+        AclType::User => 0,
+        AclType::Group => 1,
+        AclType::Mask => 2,
+        AclType::Other => 3,
+    };
+
+    ae_proto.permissions = entry.permissions as i32;
+    ae_proto.name = entry.name.clone();
+    ae_proto
+}
+
+fn is_xattr_visible(name: &str, user: &User) -> bool {
+    name.starts_with("user.")
+        || (name.starts_with("trusted.") && user.is_superuser())
+}
+
+fn to_xattr_name(xa_proto: &XAttrProto,
+        user: &User) -> Result<String, NahFSError> {
+    // only the user and superuser restricted trusted namespaces
+    //  are supported
+    let namespace = match xa_proto.namespace {
+        0 => "user",
+        1 if user.is_superuser() => "trusted",
+        1 => return Err(NahFSError::AccessControlException(format!(
+            "User doesn't have permission for xattr: trusted.{}",
+            xa_proto.name))),
+        namespace => return Err(NahFSError::from(format!(
+            "XAttr namespace {} is not supported", namespace))),
+    };
+
+    Ok(format!("{}.{}", namespace, xa_proto.name))
+}
+
+fn to_xattr_proto(name: &str, value: Option<&Vec<u8>>) -> XAttrProto {
+    let mut xa_proto = XAttrProto::default();
+    let fields: Vec<&str> = name.splitn(2, ".").collect();
+    xa_proto.namespace = match fields[0] {
+        "trusted" => 1,
+        _ => 0, // user
+    };
+
+    xa_proto.name = fields[1].to_string();
+    xa_proto.value = value.map(|x| x.clone());
+    xa_proto
+}
@@ -1,28 +1,38 @@
 use hdfs_comm::rpc::Protocol;
-use hdfs_comm::protos::hdfs::datanode::{BlockReportResponseProto, BlockReportRequestProto, HeartbeatResponseProto, HeartbeatRequestProto, RegisterDatanodeResponseProto, RegisterDatanodeRequestProto};
+use hdfs_comm::protos::hdfs::{BlockProto, DatanodeInfosProto};
+use hdfs_comm::protos::hdfs::datanode::{block_command_proto, datanode_command_proto, BlockCommandProto, BlockReportResponseProto, BlockReportRequestProto, DatanodeCommandProto, HeartbeatResponseProto, HeartbeatRequestProto, RegisterCommandProto, RegisterDatanodeResponseProto, RegisterDatanodeRequestProto};
 use prost::Message;
 use shared::NahFSError;
 
 use crate::block::BlockStore;
 use crate::datanode::DatanodeStore;
+use crate::file::FileStore;
+use crate::replication::{ReplicationQueue, NEW_BLOCK_GRACE_MS};
 use crate::storage::StorageStore;
 
-use std::sync::{Arc, RwLock};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct DatanodeProtocol {
     block_store: Arc<RwLock<BlockStore>>,
     datanode_store: Arc<RwLock<DatanodeStore>>,
+    file_store: Arc<RwLock<FileStore>>,
+    replication_queue: Arc<Mutex<ReplicationQueue>>,
     storage_store: Arc<RwLock<StorageStore>>,
 }
 
 impl DatanodeProtocol {
     pub fn new(block_store: Arc<RwLock<BlockStore>>,
             datanode_store: Arc<RwLock<DatanodeStore>>,
+            file_store: Arc<RwLock<FileStore>>,
+            replication_queue: Arc<Mutex<ReplicationQueue>>,
             storage_store: Arc<RwLock<StorageStore>>) -> DatanodeProtocol {
         DatanodeProtocol {
             block_store: block_store,
             datanode_store: datanode_store,
+            file_store: file_store,
+            replication_queue: replication_queue,
             storage_store: storage_store,
         }
     }
@@ -35,9 +45,12 @@ impl DatanodeProtocol {
 
         // process block report
         trace!("blockReport({:?})", request);
+        let file_store = self.file_store.read().unwrap();
         let mut block_store = self.block_store.write().unwrap();
 
         let datanode_id = request.registration.datanode_id.datanode_uuid;
+        let mut block_ids = HashSet::new();
+        let (mut added_ids, mut orphaned_ids) = (Vec::new(), Vec::new());
         for sbr_proto in request.reports {
             let storage_id = sbr_proto.storage.storage_uuid;
             let mut index = 0;
@@ -45,13 +58,54 @@ impl DatanodeProtocol {
                 let block_id = sbr_proto.blocks[index];
                 let length = sbr_proto.blocks[index+1];
                 let generation_stamp = sbr_proto.blocks[index+2];
-
-                block_store.update(block_id, generation_stamp,
-                    length, &datanode_id, &storage_id);
                 index += 4;
+
+                // replicas of deleted or abandoned blocks are never
+                //  restored - invalidating them instead
+                if !file_store.is_block_referenced(&block_id) {
+                    orphaned_ids.push(block_id);
+                    continue;
+                }
+
+                if block_store.update(block_id, generation_stamp,
+                        length, &datanode_id, &storage_id) {
+                    added_ids.push(block_id);
+                }
+
+                block_ids.insert(block_id);
             }
         }
 
+        // first report after registration is complete -> remove
+        // restored locations which the datanode no longer stores
+        let mut datanode_store = self.datanode_store.write().unwrap();
+        let removed_ids = match datanode_store
+                .take_full_report_pending(&datanode_id) {
+            true => block_store.remove_unreported(&datanode_id, &block_ids),
+            false => Vec::new(),
+        };
+
+        if orphaned_ids.len() != 0 {
+            debug!("invalidating {} orphaned block(s) on datanode '{}'",
+                orphaned_ids.len(), datanode_id);
+        }
+
+        for block_id in orphaned_ids {
+            datanode_store.add_invalid_block(&datanode_id, block_id);
+        }
+
+        // check replication of blocks with changed locations - new
+        //  replicas may belong to pipelines which are still reporting
+        let time = super::get_current_time();
+        let mut replication_queue = self.replication_queue.lock().unwrap();
+        for block_id in added_ids {
+            replication_queue.add(block_id, time + NEW_BLOCK_GRACE_MS);
+        }
+
+        for block_id in removed_ids {
+            replication_queue.add(block_id, time);
+        }
+
         response.encode_length_delimited(resp_buf)?;
         Ok(())
     }
@@ -60,29 +114,112 @@ impl DatanodeProtocol {
             resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
         let request = HeartbeatRequestProto
             ::decode_length_delimited(req_buf)?;
-        let response = HeartbeatResponseProto::default();
+        let mut response = HeartbeatResponseProto::default();
 
         // process heartbeat
         trace!("heartbeat({:?})", request);
         let time = SystemTime::now().duration_since(UNIX_EPOCH)
             .unwrap().as_secs() * 1000;
 
-        // process datanode report
-        let mut datanode_store = self.datanode_store.write().unwrap();
-        let datanode_id = &request.registration.datanode_id.datanode_uuid;
-        datanode_store.update(datanode_id, request.cache_capacity,
-            request.cache_used, time, request.xmits_in_progress,
-            request.xceiver_count);
- 
-        // process storage reports
-        let mut storage_store = self.storage_store.write().unwrap();
-        for sr_proto in request.reports {
-            let storage_id = &sr_proto.storage_uuid;
-            storage_store.update(storage_id, sr_proto.capacity,
-                sr_proto.dfs_used, sr_proto.remaining,
-                sr_proto.block_pool_used, sr_proto.non_dfs_used, time);
-
-            datanode_store.add_storage(datanode_id, storage_id);
+        // process datanode report and issue pending commands
+        {
+            let mut datanode_store = self.datanode_store.write().unwrap();
+            let datanode_id =
+                &request.registration.datanode_id.datanode_uuid;
+            if datanode_store.get_datanode(datanode_id).is_none() {
+                // unknown datanode (ex. namenode restart) -> reregister
+                debug!("requesting registration of datanode '{}'",
+                    datanode_id);
+
+                let mut dc_proto = DatanodeCommandProto::default();
+                dc_proto.cmd_type =
+                    datanode_command_proto::Type::RegisterCommand as i32;
+                dc_proto.register_cmd =
+                    Some(RegisterCommandProto::default());
+                response.cmds.push(dc_proto);
+
+                response.encode_length_delimited(resp_buf)?;
+                return Ok(());
+            }
+
+            datanode_store.update(datanode_id, request.cache_capacity,
+                request.cache_used, time, request.xmits_in_progress,
+                request.xceiver_count);
+
+            for sr_proto in request.reports.iter() {
+                datanode_store.add_storage(datanode_id,
+                    &sr_proto.storage_uuid);
+            }
+
+            // issue invalidate command for deleted blocks
+            let block_ids = datanode_store.take_invalid_blocks(datanode_id);
+            if block_ids.len() != 0 {
+                debug!("invalidating {} block(s) on datanode '{}'",
+                    block_ids.len(), datanode_id);
+
+                let mut bc_proto = BlockCommandProto::default();
+                bc_proto.action =
+                    block_command_proto::Action::Invalidate as i32;
+                for block_id in block_ids {
+                    let mut b_proto = BlockProto::default();
+                    b_proto.block_id = block_id;
+                    bc_proto.blocks.push(b_proto);
+                }
+
+                let mut dc_proto = DatanodeCommandProto::default();
+                dc_proto.cmd_type =
+                    datanode_command_proto::Type::BlockCommand as i32;
+                dc_proto.blk_cmd = Some(bc_proto);
+                response.cmds.push(dc_proto);
+            }
+
+            // issue transfer command for under-replicated blocks
+            let transfer_blocks =
+                datanode_store.take_transfer_blocks(datanode_id);
+            if transfer_blocks.len() != 0 {
+                debug!("transferring {} block(s) from datanode '{}'",
+                    transfer_blocks.len(), datanode_id);
+
+                let mut bc_proto = BlockCommandProto::default();
+                bc_proto.action =
+                    block_command_proto::Action::Transfer as i32;
+                for (block_id, target_ids) in transfer_blocks {
+                    let mut b_proto = BlockProto::default();
+                    b_proto.block_id = block_id;
+                    bc_proto.blocks.push(b_proto);
+
+                    // targets which have since unregistered are skipped
+                    let mut dis_proto = DatanodeInfosProto::default();
+                    for target_id in target_ids.iter() {
+                        if let Some(datanode) =
+                                datanode_store.get_datanode(target_id) {
+                            dis_proto.datanodes.push(
+                                super::to_datanode_info_proto(datanode, None));
+                        }
+                    }
+
+                    bc_proto.targets.push(dis_proto);
+                }
+
+                let mut dc_proto = DatanodeCommandProto::default();
+                dc_proto.cmd_type =
+                    datanode_command_proto::Type::BlockCommand as i32;
+                dc_proto.blk_cmd = Some(bc_proto);
+                response.cmds.push(dc_proto);
+            }
+
+            // process storage reports
+            let mut storage_store = self.storage_store.write().unwrap();
+            for sr_proto in request.reports.iter() {
+                storage_store.update(&sr_proto.storage_uuid,
+                    sr_proto.capacity, sr_proto.dfs_used, sr_proto.remaining,
+                    sr_proto.block_pool_used, sr_proto.non_dfs_used, time);
+            }
+
+            // rebuild datanode info snapshot for read-only rpcs
+            let din_proto = super::to_datanode_info_proto(datanode_store
+                .get_datanode(datanode_id).unwrap(), Some(&storage_store));
+            datanode_store.set_info(datanode_id, din_proto);
         }
 
         response.encode_length_delimited(resp_buf)?;
@@ -99,8 +236,14 @@ impl DatanodeProtocol {
         trace!("registerDatanode({:?})", request);
         let mut datanode_store = self.datanode_store.write().unwrap();
         let di_proto = request.registration.datanode_id;
-        datanode_store.register(di_proto.datanode_uuid,
-            di_proto.ip_addr, di_proto.xfer_port);
+        let datanode_id = di_proto.datanode_uuid.clone();
+        datanode_store.register(di_proto.datanode_uuid, di_proto.ip_addr,
+            di_proto.xfer_port, super::get_current_time());
+
+        let storage_store = self.storage_store.read().unwrap();
+        let din_proto = super::to_datanode_info_proto(datanode_store
+            .get_datanode(&datanode_id).unwrap(), Some(&storage_store));
+        datanode_store.set_info(&datanode_id, din_proto);
 
         response.encode_length_delimited(resp_buf)?;
         Ok(())
@@ -114,7 +257,9 @@ impl Protocol for DatanodeProtocol {
             "blockReport" => self.block_report(req_buf, resp_buf)?,
             "heartbeat" => self.heartbeat(req_buf, resp_buf)?,
             "registerDatanode" => self.register_datanode(req_buf, resp_buf)?,
-            _ => error!("unimplemented method '{}'", method),
+            _ => return Err(NahFSError::RpcNoSuchMethodException(format!(
+                "Unknown method {} called on \
+                org.apache.hadoop.hdfs.server.protocol.DatanodeProtocol protocol.", method)).into()),
         }
 
         Ok(())
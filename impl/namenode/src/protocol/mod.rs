@@ -1,8 +1,8 @@
-use hdfs_comm::protos::hdfs::{DatanodeIdProto, DatanodeInfoProto, HdfsFileStatusProto, LocatedBlockProto, LocatedBlocksProto};
+use hdfs_comm::protos::hdfs::{ContentSummaryProto, DatanodeIdProto, DatanodeInfoProto, HdfsFileStatusProto, LocatedBlockProto, LocatedBlocksProto};
 
-use crate::block::BlockStore;
+use crate::block::{Block, BlockStore};
 use crate::datanode::{Datanode, DatanodeStore};
-use crate::file::{File, FileStore, FileType};
+use crate::file::{is_snapshot_path, File, FileStore, FileType};
 use crate::index::{Index, SpatialQuery, TemporalQuery};
 use crate::storage::StorageStore;
 
@@ -14,10 +14,69 @@ pub use client_namenode::ClientNamenodeProtocol;
 pub use datanode::DatanodeProtocol;
 pub use nahfs::NahFSProtocol;
 
+// shared metadata locks must be acquired in the following order to
+//  avoid deadlocks, any lock may be skipped but never reacquired:
+//  - file_store
+//  - block_store
+//  - index
+//  - lease_store
+//  - datanode_store
+//  - storage_store
+//  - journal
+//  - replication_queue
+// background monitors follow the same order:
+//  - lease.rs start_monitor: file_store, block_store, lease_store,
+//    journal, replication_queue (in recover_lease)
+//  - replication.rs start_monitor: datanode_store, released before
+//    block_store, datanode_store, replication_queue (in
+//    remove_expired_datanodes), released before file_store, released
+//    before block_store, datanode_store, storage_store,
+//    replication_queue (in schedule)
+//  - trash.rs start_monitor: file_store, held while journal and then
+//    block_store, index and datanode_store (in remove_blocks) are each
+//    acquired and released in turn
+// read-only rpcs retrieve a datanode info snapshot - rebuilt by
+//  heartbeats - before acquiring metadata locks
+
 use std::collections::HashMap;
 use std::cmp::Ordering;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn get_current_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .unwrap().as_millis() as u64
+}
+
+fn compute_length(block_ids: &[u64], block_store: &BlockStore,
+        index: &Index, query: &Option<(&str, (Option<SpatialQuery>,
+            Option<TemporalQuery>))>) -> u64 {
+    let mut length = 0;
+    for (block_id, query_result) in query_blocks(block_ids, index, query) {
+        if let Some(block) = block_store.get_block(&block_id) {
+            match query_result {
+                Some((_, query_length)) => length += query_length as u64,
+                None => length += block.length,
+            }
+        }
+    }
 
-fn get_datanode_usage(datanode_store: &DatanodeStore,
+    length
+}
+
+fn get_blocks<'a>(file: &'a File, snapshot: bool,
+        file_store: &FileStore) -> &'a [u64] {
+    // snapshots exclude blocks written after they were taken
+    match (file.get_file_type(), snapshot) {
+        (FileType::Regular {blocks: _, replication: _, block_size: _},
+            true) => file_store.get_snapshot_blocks(file),
+        (FileType::Regular {blocks, replication: _, block_size: _},
+            false) => blocks,
+        (FileType::Directory, _) => &[],
+    }
+}
+
+pub fn get_datanode_usage(datanode_store: &DatanodeStore,
         storage_store: &StorageStore) -> Vec<(String, u64)> {
     let mut datanodes = Vec::new();
 
@@ -42,6 +101,12 @@ fn get_datanode_usage(datanode_store: &DatanodeStore,
     datanodes
 }
 
+fn get_datanode_infos(datanode_store: &RwLock<DatanodeStore>)
+        -> Arc<HashMap<String, DatanodeInfoProto>> {
+    let datanode_store = datanode_store.read().unwrap();
+    datanode_store.get_infos()
+}
+
 fn get_spatiotemporal_datanode_usage(block_store: &BlockStore,
         datanode_store: &DatanodeStore, index: &Index,
         geohashes: &Vec<String>) -> Vec<(String, u64)> {
@@ -72,17 +137,95 @@ fn get_spatiotemporal_datanode_usage(block_store: &BlockStore,
     datanodes
 }
 
-fn select_block_replica(vec: &Vec<(String, u64)>) -> usize {
+pub fn remove_blocks(block_ids: &Vec<u64>, block_store: &RwLock<BlockStore>,
+        datanode_store: &RwLock<DatanodeStore>, index: &RwLock<Index>) {
+    // remove blocks from BlockStore
+    let mut invalid_blocks = Vec::new();
+    {
+        let mut block_store = block_store.write().unwrap();
+        for block_id in block_ids.iter() {
+            if let Some(block) = block_store.remove(block_id) {
+                for datanode_id in block.locations {
+                    invalid_blocks.push((datanode_id, *block_id));
+                }
+            }
+        }
+    }
+
+    // remove blocks from Index
+    {
+        let mut index = index.write().unwrap();
+        for block_id in block_ids.iter() {
+            index.remove(block_id);
+        }
+    }
+
+    // schedule block replica invalidations on datanodes
+    let mut datanode_store = datanode_store.write().unwrap();
+    for (datanode_id, block_id) in invalid_blocks {
+        datanode_store.add_invalid_block(&datanode_id, block_id);
+    }
+}
+
+pub fn select_block_replica(vec: &Vec<(String, u64)>,
+        excluded_ids: &Vec<String>) -> Option<usize> {
+    // skip excluded nodes (ex. failed pipeline datanodes)
+    let indices: Vec<usize> = (0..vec.len())
+        .filter(|i| !excluded_ids.contains(&vec[*i].0)).collect();
+    if indices.len() == 0 {
+        return None;
+    }
+
     // use a logarithmic function to favor nodes with lower utilization
-    let log_base = (vec.len() + 1) as f64;
+    let log_base = (indices.len() + 1) as f64;
     let replica_token = rand::random::<f64>();
-    for i in 0..vec.len() {
+    for i in 0..indices.len() {
         if replica_token <= ((i + 2) as f64).log(log_base) {
-            return i;
+            return Some(indices[i]);
         }
     }
 
-    return vec.len() - 1;
+    return Some(indices[indices.len() - 1]);
+}
+
+fn to_content_summary_proto(path: &str, file: &File,
+        query: &Option<(&str, (Option<SpatialQuery>, Option<TemporalQuery>))>,
+        block_store: &BlockStore, file_store: &FileStore,
+        index: &Index) -> ContentSummaryProto {
+    let mut cs_proto = ContentSummaryProto::default();
+    let snapshot = is_snapshot_path(path);
+
+    // quotas of -1 indicate the directory has no quota
+    let quota = file_store.get_quota(file.get_inode());
+    cs_proto.quota = quota.and_then(|quota| quota.namespace)
+        .unwrap_or(std::u64::MAX);
+    cs_proto.space_quota = quota.and_then(|quota| quota.space)
+        .unwrap_or(std::u64::MAX);
+
+    // iterate over subtree - filtering lengths by query
+    let mut files = vec!(file);
+    while let Some(file) = files.pop() {
+        match file.get_file_type() {
+            FileType::Directory => {
+                cs_proto.directory_count += 1;
+                if let Some(children) =
+                        file_store.get_children(file.get_inode()) {
+                    files.extend(children);
+                }
+            },
+            FileType::Regular {blocks: _, replication, block_size: _} => {
+                let blocks = get_blocks(file, snapshot, file_store);
+                let length =
+                    compute_length(blocks, block_store, index, query);
+
+                cs_proto.file_count += 1;
+                cs_proto.length += length;
+                cs_proto.space_consumed += length * *replication as u64;
+            },
+        }
+    }
+
+    cs_proto
 }
 
 fn to_datanode_id_proto(datanode: &Datanode) -> DatanodeIdProto {
@@ -153,14 +296,20 @@ fn to_datanode_info_proto(datanode: &Datanode,
     din_proto
 }
 
-fn to_hdfs_file_status_proto(file: &File,
+fn to_hdfs_file_status_proto(path: &str, file: &File,
         query: &Option<(&str, (Option<SpatialQuery>, Option<TemporalQuery>))>, 
         block_store: &BlockStore, file_store: &FileStore,
         index: &Index) -> HdfsFileStatusProto {
     let mut hfs_proto = HdfsFileStatusProto::default();
     hfs_proto.file_type = file.get_file_type_code();
-    hfs_proto.path =
-        file_store.compute_path(file.get_inode()).into_bytes();
+
+    // snapshots share inodes with live files - so their paths are
+    //  taken from the request rather than computed from the inode
+    let snapshot = is_snapshot_path(path);
+    hfs_proto.path = match snapshot {
+        true => path.trim_end_matches("/").to_string(),
+        false => file_store.compute_path(file.get_inode()),
+    }.into_bytes();
     if let Some((query_string, _)) = query {
         hfs_proto.path.push('+' as u8);
 
@@ -170,30 +319,21 @@ fn to_hdfs_file_status_proto(file: &File,
     }
 
     // iterate over blocks to compute file length
-    hfs_proto.length = 0;
-    if let FileType::Regular{blocks, replication: _, block_size: _} =
-            file.get_file_type() {
-        for (block_id, query_result) in query_blocks(blocks, index, query) {
-            if let Some(block) = block_store.get_block(&block_id) {
-                match query_result {
-                    Some((_, length)) => hfs_proto.length += length as u64,
-                    None => hfs_proto.length += block.length,
-                }
-            }
-        }
-    }
+    let blocks = get_blocks(file, snapshot, file_store);
+    hfs_proto.length = compute_length(blocks, block_store, index, query);
 
     let fp_proto = &mut hfs_proto.permission;
     fp_proto.perm = file.get_permissions();
 
     hfs_proto.owner = file.get_owner().to_string();
     hfs_proto.group = file.get_group().to_string();
+    hfs_proto.modification_time = file.get_modification_time();
+    hfs_proto.access_time = file.get_access_time();
 
     match file.get_file_type() {
         FileType::Directory =>  {
-            if let Some(children) = file_store.get_children(file.get_inode()) {
-                hfs_proto.children_num = Some(children.len() as i32);
-            }
+            hfs_proto.children_num = Some(file_store
+                .get_children_count(file.get_inode()) as i32);
         },
         FileType::Regular{blocks: _, replication, block_size} => {
             hfs_proto.block_replication = Some(*replication);
@@ -209,71 +349,78 @@ fn to_hdfs_file_status_proto(file: &File,
     hfs_proto
 }
 
-fn to_located_blocks_proto(file: &File,
+fn to_located_blocks_proto(path: &str, file: &File,
         query: &Option<(&str, (Option<SpatialQuery>, Option<TemporalQuery>))>, 
-        block_store: &BlockStore, datanode_store: &DatanodeStore,
-        index: &Index, storage_store: &StorageStore) -> LocatedBlocksProto {
+        block_store: &BlockStore, file_store: &FileStore, index: &Index,
+        datanode_infos: &HashMap<String, DatanodeInfoProto>)
+        -> LocatedBlocksProto {
     let mut lbs_proto = LocatedBlocksProto::default();
     let lb_proto_blocks = &mut lbs_proto.blocks;
 
-    let (mut length, mut complete) = (0, true);
-    if let FileType::Regular{blocks, replication: _, block_size: _} =
-            file.get_file_type() {
-        for (block_id, query_result) in query_blocks(blocks, index, query) {
-            if let Some(block) = block_store.get_block(&block_id) {
-                // populate LocatedBlockProto
-                let mut lb_proto = LocatedBlockProto::default();
-                let eb_proto = &mut lb_proto.b;
-
-                // populate ExtendedBlockProto
-                match query_result {
-                    Some((query_block_id, length)) => {
-                        eb_proto.block_id = query_block_id;
-                        eb_proto.num_bytes = Some(length as u64);
-                    },
-                    None => {
-                        eb_proto.block_id = block.id;
-                        eb_proto.num_bytes = Some(block.length);
-                    },
-                }
+    // snapshots of open files are read as complete files
+    let snapshot = is_snapshot_path(path);
+    let blocks = get_blocks(file, snapshot, file_store);
 
-                // populate LocatedBlockProto
-                lb_proto.offset = length;
-                lb_proto.corrupt = false;
-
-                // populate locs
-                for datanode_id in &block.locations {
-                    if let Some(datanode) =
-                            datanode_store.get_datanode(datanode_id) {
-                        lb_proto.locs.push(to_datanode_info_proto(
-                            datanode, Some(storage_store)));
-                    }
-                }
-
-                // populate storages
-                for storage_id in &block.storage_ids {
-                    lb_proto.storage_types.push(0);
-                    lb_proto.storage_i_ds.push(storage_id.to_string());
-                    lb_proto.is_cached.push(false);
-                }
+    let (mut length, mut complete) = (0, true);
+    for (block_id, query_result) in query_blocks(blocks, index, query) {
+        if let Some(block) = block_store.get_block(&block_id) {
+            // populate LocatedBlockProto
+            let mut lb_proto =
+                to_located_block_proto(block, length, datanode_infos);
 
-                // increment file length
-                length += eb_proto.num_bytes.unwrap();
-                lb_proto_blocks.push(lb_proto);
-            } else {
-                // block_id not found -> file not complete
-                complete = false;
+            // if queried -> use query block id and length
+            if let Some((query_block_id, query_length)) = query_result {
+                lb_proto.b.block_id = query_block_id;
+                lb_proto.b.num_bytes = Some(query_length as u64);
             }
+
+            // increment file length
+            length += lb_proto.b.num_bytes.unwrap();
+            lb_proto_blocks.push(lb_proto);
+        } else {
+            // block_id not found -> file not complete
+            complete = false;
         }
     }
 
     lbs_proto.file_length = length;
-    lbs_proto.under_construction = !complete;
+    lbs_proto.under_construction =
+        (file.is_under_construction() && !snapshot) || !complete;
     lbs_proto.is_last_block_complete = complete;
     lbs_proto
 }
 
-fn query_blocks(block_ids: &Vec<u64>, index: &Index, 
+fn to_located_block_proto(block: &Block, offset: u64,
+        datanode_infos: &HashMap<String, DatanodeInfoProto>)
+        -> LocatedBlockProto {
+    let mut lb_proto = LocatedBlockProto::default();
+
+    // populate ExtendedBlockProto
+    let eb_proto = &mut lb_proto.b;
+    eb_proto.block_id = block.id;
+    eb_proto.generation_stamp = block.generation_stamp;
+    eb_proto.num_bytes = Some(block.length);
+
+    // populate LocatedBlockProto
+    lb_proto.offset = offset;
+    lb_proto.corrupt = false;
+
+    // populate locs and storages of registered datanodes
+    for (datanode_id, storage_id) in
+            block.locations.iter().zip(block.storage_ids.iter()) {
+        if let Some(din_proto) = datanode_infos.get(datanode_id) {
+            lb_proto.locs.push(din_proto.clone());
+
+            lb_proto.storage_types.push(0);
+            lb_proto.storage_i_ds.push(storage_id.to_string());
+            lb_proto.is_cached.push(false);
+        }
+    }
+
+    lb_proto
+}
+
+fn query_blocks(block_ids: &[u64], index: &Index, 
         query: &Option<(&str, (Option<SpatialQuery>, Option<TemporalQuery>))>)
         -> Vec<(u64, Option<(u64, u32)>)> {
     let mut blocks = Vec::new();
@@ -329,3 +476,829 @@ fn query_blocks(block_ids: &Vec<u64>, index: &Index,
 
     blocks
 }
+
+#[cfg(test)]
+mod tests {
+    use hdfs_comm::protos::hdfs::{AddBlockRequestProto, AddBlockResponseProto, CreateRequestProto, ExtendedBlockProto};
+    use hdfs_comm::protos::hdfs::datanode::{BlockReportRequestProto, DatanodeRegistrationProto, RegisterDatanodeRequestProto, StorageBlockReportProto};
+    use hdfs_comm::rpc::Protocol;
+    use prost::Message;
+
+    use super::{ClientNamenodeProtocol, DatanodeProtocol};
+    use crate::block::BlockStore;
+    use crate::datanode::DatanodeStore;
+    use crate::file::FileStore;
+    use crate::file::journal::Journal;
+    use crate::file::permission::UserStore;
+    use crate::index::Index;
+    use crate::lease::LeaseStore;
+    use crate::replication::ReplicationQueue;
+    use crate::storage::StorageStore;
+
+    use std::sync::{Arc, Mutex, RwLock};
+
+    #[derive(Clone)]
+    pub struct TestNamenode {
+        pub block_store: Arc<RwLock<BlockStore>>,
+        pub client_protocol: Arc<ClientNamenodeProtocol>,
+        pub datanode_protocol: Arc<DatanodeProtocol>,
+        pub datanode_store: Arc<RwLock<DatanodeStore>>,
+        pub file_store: Arc<RwLock<FileStore>>,
+        pub index: Arc<RwLock<Index>>,
+        pub journal: Arc<Mutex<Journal>>,
+        pub lease_store: Arc<RwLock<LeaseStore>>,
+        pub replication_queue: Arc<Mutex<ReplicationQueue>>,
+        pub storage_store: Arc<RwLock<StorageStore>>,
+    }
+
+    impl TestNamenode {
+        pub fn add_block(&self, path: &str,
+                client_name: &str) -> ExtendedBlockProto {
+            let mut request = AddBlockRequestProto::default();
+            request.src = path.to_string();
+            request.client_name = client_name.to_string();
+            let resp_buf = call(&*self.client_protocol, "addBlock", request);
+            AddBlockResponseProto::decode_length_delimited(&resp_buf[..])
+                .unwrap().block.b
+        }
+
+        pub fn create_file(&self, path: &str,
+                client_name: &str, replication: u32) {
+            let mut request = CreateRequestProto::default();
+            request.src = path.to_string();
+            request.masked.perm = 420;
+            request.client_name = client_name.to_string();
+            request.replication = replication;
+            request.block_size = 1024;
+            call(&*self.client_protocol, "create", request);
+        }
+    }
+
+    pub fn call<P: Protocol, T: Message>(protocol: &P,
+            method: &str, request: T) -> Vec<u8> {
+        try_call(protocol, method, request).unwrap()
+    }
+
+    pub fn registration(datanode_id: &str) -> DatanodeRegistrationProto {
+        let mut dr_proto = DatanodeRegistrationProto::default();
+        dr_proto.datanode_id.datanode_uuid = datanode_id.to_string();
+        dr_proto.datanode_id.ip_addr = "127.0.0.1".to_string();
+        dr_proto
+    }
+
+    pub fn report_block(datanode_protocol: &DatanodeProtocol,
+            datanode_id: &str, block_id: u64, length: u64) {
+        let mut sbr_proto = StorageBlockReportProto::default();
+        sbr_proto.storage.storage_uuid = format!("storage-{}", datanode_id);
+        sbr_proto.blocks = vec!(block_id, length, 0, 0);
+
+        let mut request = BlockReportRequestProto::default();
+        request.registration = registration(datanode_id);
+        request.reports.push(sbr_proto);
+        call(datanode_protocol, "blockReport", request);
+    }
+
+    pub fn start_namenode(path: &str, datanode_ids: &[&str]) -> TestNamenode {
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        let journal = Journal::open(path,
+            &mut file_store, &mut block_store, 0).unwrap();
+
+        let block_store = Arc::new(RwLock::new(block_store));
+        let datanode_store = Arc::new(RwLock::new(DatanodeStore::new(10)));
+        let file_store = Arc::new(RwLock::new(file_store));
+        let index = Arc::new(RwLock::new(Index::new()));
+        let journal = Arc::new(Mutex::new(journal));
+        let lease_store =
+            Arc::new(RwLock::new(LeaseStore::new(60000, 3600000)));
+        let replication_queue = Arc::new(Mutex::new(ReplicationQueue::new()));
+        let storage_store = Arc::new(RwLock::new(StorageStore::new(10)));
+        let client_protocol = Arc::new(ClientNamenodeProtocol::new(0, 1, 0,
+            block_store.clone(), datanode_store.clone(), file_store.clone(),
+            index.clone(), journal.clone(), lease_store.clone(),
+            replication_queue.clone(), storage_store.clone(),
+            Arc::new(UserStore::new("root", "supergroup"))));
+        let datanode_protocol = Arc::new(DatanodeProtocol::new(
+            block_store.clone(), datanode_store.clone(), file_store.clone(),
+            replication_queue.clone(), storage_store.clone()));
+
+        // register datanodes and complete their initial full reports
+        for datanode_id in datanode_ids.iter() {
+            let mut request = RegisterDatanodeRequestProto::default();
+            request.registration = registration(datanode_id);
+            call(&*datanode_protocol, "registerDatanode", request);
+
+            let mut request = BlockReportRequestProto::default();
+            request.registration = registration(datanode_id);
+            call(&*datanode_protocol, "blockReport", request);
+        }
+
+        TestNamenode {
+            block_store: block_store,
+            client_protocol: client_protocol,
+            datanode_protocol: datanode_protocol,
+            datanode_store: datanode_store,
+            file_store: file_store,
+            index: index,
+            journal: journal,
+            lease_store: lease_store,
+            replication_queue: replication_queue,
+            storage_store: storage_store,
+        }
+    }
+
+    pub fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "nahfs-{}-{}", name, rand::random::<u64>()));
+        path.to_str().unwrap().to_string()
+    }
+
+    pub fn try_call<P: Protocol, T: Message>(protocol: &P, method: &str,
+            request: T) -> std::io::Result<Vec<u8>> {
+        try_call_as(protocol, "root", method, request)
+    }
+
+    pub fn try_call_as<P: Protocol, T: Message>(protocol: &P, user: &str,
+            method: &str, request: T) -> std::io::Result<Vec<u8>> {
+        let (mut req_buf, mut resp_buf) = (Vec::new(), Vec::new());
+        request.encode_length_delimited(&mut req_buf).unwrap();
+        protocol.process(&Some(user.to_string()), method,
+            &req_buf, &mut resp_buf)?;
+        Ok(resp_buf)
+    }
+
+    #[test]
+    fn abandon_block() {
+        use hdfs_comm::protos::hdfs::AbandonBlockRequestProto;
+        use super::tests::{call, report_block, start_namenode, temp_path, try_call_as};
+        use crate::block::BlockStore;
+        use crate::file::{FileStore, FileType};
+        use crate::file::journal::Journal;
+
+        let path = &temp_path("abandon-block");
+
+        let namenode = start_namenode(path, &["a"]);
+        let (client_protocol, datanode_protocol) =
+            (&*namenode.client_protocol, &*namenode.datanode_protocol);
+
+        namenode.create_file("/f", "client", 1);
+        let (first_proto, last_proto) = (namenode.add_block("/f", "client"),
+            namenode.add_block("/f", "client"));
+        report_block(datanode_protocol, "a", first_proto.block_id, 1024);
+        report_block(datanode_protocol, "a", last_proto.block_id, 100);
+
+        // only the lease user may abandon blocks
+        let mut request = AbandonBlockRequestProto::default();
+        request.b = last_proto.clone();
+        request.src = "/f".to_string();
+        request.holder = "client".to_string();
+        assert!(try_call_as(client_protocol, "alice",
+            "abandonBlock", request).is_err());
+
+        // only the last block may be abandoned
+        for eb_proto in vec!(first_proto.clone(), last_proto.clone()) {
+            let mut request = AbandonBlockRequestProto::default();
+            request.b = eb_proto;
+            request.src = "/f".to_string();
+            request.holder = "client".to_string();
+            call(client_protocol, "abandonBlock", request);
+        }
+
+        // abandoned replicas are removed and the removal is journaled
+        {
+            let block_store = namenode.block_store.read().unwrap();
+            assert!(block_store.get_block(&first_proto.block_id).is_some());
+            assert!(block_store.get_block(&last_proto.block_id).is_none());
+        }
+
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        Journal::open(path, &mut file_store, &mut block_store, 0).unwrap();
+        match file_store.get_file("/f").unwrap().get_file_type() {
+            FileType::Regular {blocks, replication: _, block_size: _} =>
+                assert_eq!(blocks, &vec!(first_proto.block_id)),
+            FileType::Directory => panic!("file is a directory"),
+        }
+
+        // a new block may replace the abandoned block
+        assert!(namenode.add_block("/f", "client").block_id
+            != last_proto.block_id);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn append() {
+        use hdfs_comm::protos::hdfs::{AppendRequestProto, AppendResponseProto, CompleteRequestProto};
+        use super::tests::{call, report_block, start_namenode, temp_path};
+        use crate::block::BlockStore;
+        use crate::file::FileStore;
+        use crate::file::journal::Journal;
+
+        let path = &temp_path("append");
+
+        let namenode = start_namenode(path, &["a"]);
+        let (client_protocol, datanode_protocol) =
+            (&*namenode.client_protocol, &*namenode.datanode_protocol);
+
+        namenode.create_file("/f", "client", 1);
+        let eb_proto = namenode.add_block("/f", "client");
+        let (block_id, generation_stamp) =
+            (eb_proto.block_id, eb_proto.generation_stamp);
+
+        let mut complete = CompleteRequestProto::default();
+        complete.src = "/f".to_string();
+        complete.client_name = "client".to_string();
+        report_block(datanode_protocol, "a", block_id, 100);
+        call(client_protocol, "complete", complete.clone());
+
+        let append = |flag: Option<u32>| {
+            let mut request = AppendRequestProto::default();
+            request.src = "/f".to_string();
+            request.client_name = "client".to_string();
+            request.flag = flag;
+            let resp_buf = call(client_protocol, "append", request);
+            AppendResponseProto::decode_length_delimited(&resp_buf[..])
+                .unwrap().block
+        };
+
+        // partial last blocks are reopened with a new generation stamp
+        let lb_proto = append(None).unwrap();
+        assert_eq!((lb_proto.b.block_id, lb_proto.offset), (block_id, 0));
+        assert!(lb_proto.b.generation_stamp > generation_stamp);
+        assert_eq!(lb_proto.locs.len(), 1);
+        let generation_stamp = lb_proto.b.generation_stamp;
+        assert_eq!(namenode.block_store.read().unwrap()
+            .get_block(&block_id).unwrap().generation_stamp,
+            generation_stamp);
+        call(client_protocol, "complete", complete.clone());
+
+        // full last blocks are never reopened
+        report_block(datanode_protocol, "a", block_id, 1024);
+        assert!(append(None).is_none());
+        call(client_protocol, "complete", complete.clone());
+
+        // the NEW_BLOCK flag never reopens partial blocks
+        report_block(datanode_protocol, "a", block_id, 100);
+        assert!(append(Some(32)).is_none());
+
+        // reopened block generation stamps are journaled
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        Journal::open(path, &mut file_store, &mut block_store, 0).unwrap();
+        assert_eq!(block_store.get_block(&block_id).unwrap()
+            .generation_stamp, generation_stamp);
+        assert!(file_store.get_file("/f").unwrap().is_under_construction());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn block_report() {
+        use super::tests::{report_block, start_namenode, temp_path};
+
+        let path = &temp_path("block-report");
+
+        let namenode = start_namenode(path, &["a"]);
+        {
+            let mut file_store = namenode.file_store.write().unwrap();
+            file_store.create("/f", 420, "root", "root",
+                1, 1024, "client", 0).unwrap();
+            file_store.add_block("/f", 1, 0, 0).unwrap();
+        }
+
+        // referenced blocks are added and orphaned blocks invalidated
+        report_block(&namenode.datanode_protocol, "a", 1, 100);
+        report_block(&namenode.datanode_protocol, "a", 2, 100);
+
+        let block_store = namenode.block_store.read().unwrap();
+        assert!(block_store.get_block(&1).is_some());
+        assert!(block_store.get_block(&2).is_none());
+
+        let datanode_store = namenode.datanode_store.read().unwrap();
+        assert_eq!(datanode_store.get_datanode("a").unwrap()
+            .invalid_blocks, vec!(2));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn concurrency() {
+        use hdfs_comm::protos::hdfs::{CompleteRequestProto, CompleteResponseProto, DeleteRequestProto, DeleteResponseProto, GetBlockLocationsRequestProto, GetFileInfoRequestProto, GetListingRequestProto, GetListingResponseProto, MkdirsRequestProto, RenameRequestProto, RenameResponseProto, StorageReportProto};
+        use hdfs_comm::protos::hdfs::datanode::HeartbeatRequestProto;
+        use super::tests::{call, registration, report_block, start_namenode, temp_path, try_call};
+        use crate::{lease, replication, trash};
+
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let path = &temp_path("concurrency");
+
+        let datanode_ids = ["a", "b", "c"];
+        let namenode = start_namenode(path, &datanode_ids);
+        let (client_protocol, datanode_protocol) =
+            (namenode.client_protocol.clone(),
+                namenode.datanode_protocol.clone());
+
+        // run background monitors alongside rpcs
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let monitors = vec!(
+            lease::start_monitor(namenode.block_store.clone(),
+                namenode.file_store.clone(), namenode.journal.clone(),
+                namenode.lease_store.clone(),
+                namenode.replication_queue.clone(), 5, shutdown.clone()),
+            replication::start_monitor(namenode.block_store.clone(),
+                namenode.datanode_store.clone(), namenode.file_store.clone(),
+                namenode.storage_store.clone(),
+                namenode.replication_queue.clone(), 5, shutdown.clone()),
+            trash::start_monitor(namenode.block_store.clone(),
+                namenode.datanode_store.clone(), namenode.file_store.clone(),
+                namenode.index.clone(), namenode.journal.clone(),
+                5, 60000, shutdown.clone()));
+
+        // concurrently write files, rename and delete directories, read
+        //  metadata and process heartbeats - a thread which deadlocks
+        //  never reports completion
+        let (sender, receiver) = mpsc::channel();
+        let (writer_count, file_count) = (4, 16);
+        let active_writers = Arc::new(AtomicUsize::new(writer_count + 1));
+        let mut writers = Vec::new();
+        for i in 0..writer_count {
+            let (namenode, sender, active_writers) = (namenode.clone(),
+                sender.clone(), active_writers.clone());
+            let datanode_id = datanode_ids[i % datanode_ids.len()];
+            writers.push(std::thread::spawn(move || {
+                let client_name = format!("client-{}", i);
+                let mut request = MkdirsRequestProto::default();
+                request.src = format!("/t/{}", i);
+                request.masked.perm = 493;
+                request.create_parent = true;
+                call(&*namenode.client_protocol, "mkdirs", request);
+
+                let mut completed_count = 0;
+                for j in 0..file_count {
+                    let src = format!("/t/{}/{}", i, j);
+                    namenode.create_file(&src, &client_name, 1);
+                    let block_id =
+                        namenode.add_block(&src, &client_name).block_id;
+
+                    // complete fails until the block has been reported
+                    let mut request = CompleteRequestProto::default();
+                    request.src = src.clone();
+                    request.client_name = client_name.clone();
+                    let resp_buf = call(&*namenode.client_protocol,
+                        "complete", request.clone());
+                    assert!(!CompleteResponseProto
+                        ::decode_length_delimited(&resp_buf[..])
+                        .unwrap().result);
+
+                    report_block(&namenode.datanode_protocol,
+                        datanode_id, block_id, 1024);
+
+                    let resp_buf = call(&*namenode.client_protocol,
+                        "complete", request);
+                    assert!(CompleteResponseProto
+                        ::decode_length_delimited(&resp_buf[..])
+                        .unwrap().result);
+                    completed_count += 1;
+                }
+
+                active_writers.fetch_sub(1, Ordering::SeqCst);
+                sender.send(()).unwrap();
+                completed_count
+            }));
+        }
+
+        let renamer = {
+            let (client_protocol, sender, active_writers) =
+                (client_protocol.clone(), sender.clone(),
+                    active_writers.clone());
+            std::thread::spawn(move || {
+                let (mut rename_count, mut delete_count) = (0, 0);
+                for i in 0..file_count * writer_count {
+                    let mut request = MkdirsRequestProto::default();
+                    request.src = format!("/r/{}/d", i);
+                    request.masked.perm = 493;
+                    request.create_parent = true;
+                    call(&*client_protocol, "mkdirs", request);
+
+                    let mut request = RenameRequestProto::default();
+                    request.src = format!("/r/{}/d", i);
+                    request.dst = format!("/r/{}/e", i);
+                    let resp_buf = call(&*client_protocol, "rename", request);
+                    assert!(RenameResponseProto
+                        ::decode_length_delimited(&resp_buf[..])
+                        .unwrap().result);
+                    rename_count += 1;
+
+                    let mut request = DeleteRequestProto::default();
+                    request.src = format!("/r/{}", i);
+                    request.recursive = true;
+                    let resp_buf = call(&*client_protocol, "delete", request);
+                    assert!(DeleteResponseProto
+                        ::decode_length_delimited(&resp_buf[..])
+                        .unwrap().result);
+                    delete_count += 1;
+                }
+
+                active_writers.fetch_sub(1, Ordering::SeqCst);
+                sender.send(()).unwrap();
+                (rename_count, delete_count)
+            })
+        };
+
+        // readers run until all writers have completed
+        let reader_count = 4;
+        let (read_count, stopped) =
+            (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        for _ in 0..reader_count {
+            let (client_protocol, sender, read_count, stopped) =
+                (client_protocol.clone(), sender.clone(),
+                    read_count.clone(), stopped.clone());
+            std::thread::spawn(move || {
+                let mut i = 0;
+                while !stopped.load(Ordering::SeqCst) {
+                    let src = format!("/t/{}", i % writer_count);
+                    let mut request = GetListingRequestProto::default();
+                    request.src = src.clone();
+                    request.need_location = true;
+                    call(&*client_protocol, "getListing", request);
+
+                    let mut request = GetFileInfoRequestProto::default();
+                    request.src = src.clone();
+                    call(&*client_protocol, "getFileInfo", request);
+
+                    // files may not have been created yet
+                    let mut request =
+                        GetBlockLocationsRequestProto::default();
+                    request.src = format!("{}/0", src);
+                    let _ = try_call(&*client_protocol,
+                        "getBlockLocations", request);
+
+                    read_count.fetch_add(1, Ordering::SeqCst);
+                    i += 1;
+                }
+
+                sender.send(()).unwrap();
+            });
+        }
+
+        {
+            let (datanode_protocol, sender) =
+                (datanode_protocol.clone(), sender.clone());
+            std::thread::spawn(move || {
+                for i in 0..file_count * writer_count {
+                    let datanode_id = datanode_ids[i % datanode_ids.len()];
+                    let mut sr_proto = StorageReportProto::default();
+                    sr_proto.storage_uuid =
+                        format!("storage-{}", datanode_id);
+                    sr_proto.capacity = Some(1048576);
+                    sr_proto.dfs_used = Some(i as u64);
+
+                    let mut request = HeartbeatRequestProto::default();
+                    request.registration = registration(datanode_id);
+                    request.reports.push(sr_proto);
+                    call(&*datanode_protocol, "heartbeat", request);
+                }
+
+                sender.send(()).unwrap();
+            });
+        }
+
+        // reads progress while writes are in flight
+        let start_read_count = read_count.load(Ordering::SeqCst);
+        let mut progressed = false;
+        while active_writers.load(Ordering::SeqCst) != 0 {
+            if read_count.load(Ordering::SeqCst)
+                    >= start_read_count + reader_count * 2 {
+                progressed = true;
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(progressed, "reads stalled behind writes");
+
+        for _ in 0..writer_count + 2 {
+            receiver.recv_timeout(Duration::from_secs(60))
+                .expect("rpc threads deadlocked");
+        }
+
+        stopped.store(true, Ordering::SeqCst);
+        for _ in 0..reader_count {
+            receiver.recv_timeout(Duration::from_secs(60))
+                .expect("rpc threads deadlocked");
+        }
+
+        // every writer completed all of its operations
+        for writer in writers {
+            assert_eq!(writer.join().unwrap(), file_count);
+        }
+
+        assert_eq!(renamer.join().unwrap(),
+            (file_count * writer_count, file_count * writer_count));
+
+        // monitors exit after their current interval
+        shutdown.store(true, Ordering::SeqCst);
+        for monitor in monitors {
+            monitor.join().expect("monitor panicked");
+        }
+
+        // all files are listed with their reported block locations
+        for i in 0..writer_count {
+            let mut request = GetListingRequestProto::default();
+            request.src = format!("/t/{}", i);
+            request.need_location = true;
+            let resp_buf = call(&*client_protocol, "getListing", request);
+            let dl_proto = GetListingResponseProto
+                ::decode_length_delimited(&resp_buf[..])
+                .unwrap().dir_list.unwrap();
+
+            assert_eq!(dl_proto.partial_listing.len(), file_count);
+            for hfs_proto in dl_proto.partial_listing {
+                let lbs_proto = hfs_proto.locations.unwrap();
+                assert!(!lbs_proto.under_construction);
+                assert_eq!(lbs_proto.blocks.len(), 1);
+                assert_eq!(lbs_proto.blocks[0].locs.len(), 1);
+            }
+        }
+
+        // renamed and deleted directories are removed
+        let mut request = GetListingRequestProto::default();
+        request.src = "/r".to_string();
+        let resp_buf = call(&*client_protocol, "getListing", request);
+        assert_eq!(GetListingResponseProto
+            ::decode_length_delimited(&resp_buf[..]).unwrap()
+            .dir_list.unwrap().partial_listing.len(), 0);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn content_summary() {
+        use super::to_content_summary_proto;
+        use crate::block::BlockStore;
+        use crate::file::FileStore;
+        use crate::index::Index;
+
+        let mut block_store = BlockStore::new();
+        let mut file_store = FileStore::new();
+        let index = Index::new();
+
+        file_store.mkdirs("/a/b/c", 493, "root", "root", true, 0).unwrap();
+        file_store.create("/a/f", 420, "root", "root",
+            3, 65536, "client", 0).unwrap();
+        file_store.add_block("/a/f", 1, 0, 0).unwrap();
+        file_store.add_block("/a/f", 2, 0, 0).unwrap();
+        file_store.create("/a/b/g", 420, "root", "root",
+            1, 65536, "client", 0).unwrap();
+        file_store.add_block("/a/b/g", 3, 0, 0).unwrap();
+        file_store.set_quota("/a/b", Some(10), Some(4096)).unwrap();
+
+        block_store.update(1, 0, 65536, "a", "sa");
+        block_store.update(2, 0, 100, "a", "sa");
+        block_store.update(3, 0, 50, "a", "sa");
+
+        // nested directories and files are counted recursively
+        let file = file_store.get_file("/a").unwrap();
+        let cs_proto = to_content_summary_proto("/a", file,
+            &None, &block_store, &file_store, &index);
+        assert_eq!((cs_proto.directory_count, cs_proto.file_count), (3, 2));
+        assert_eq!(cs_proto.length, 65686);
+        assert_eq!(cs_proto.space_consumed, 65636 * 3 + 50);
+        assert_eq!((cs_proto.quota, cs_proto.space_quota),
+            (std::u64::MAX, std::u64::MAX));
+
+        // quotas are reported for the summarized directory only
+        let file = file_store.get_file("/a/b").unwrap();
+        let cs_proto = to_content_summary_proto("/a/b", file,
+            &None, &block_store, &file_store, &index);
+        assert_eq!((cs_proto.directory_count, cs_proto.file_count), (2, 1));
+        assert_eq!((cs_proto.length, cs_proto.space_consumed), (50, 50));
+        assert_eq!((cs_proto.quota, cs_proto.space_quota), (10, 4096));
+
+        // files summarize themselves
+        let file = file_store.get_file("/a/f").unwrap();
+        let cs_proto = to_content_summary_proto("/a/f", file,
+            &None, &block_store, &file_store, &index);
+        assert_eq!((cs_proto.directory_count, cs_proto.file_count), (0, 1));
+        assert_eq!(cs_proto.length, 65636);
+    }
+
+    #[test]
+    fn get_additional_datanode() {
+        use hdfs_comm::protos::hdfs::{DatanodeInfoProto, GetAdditionalDatanodeRequestProto, GetAdditionalDatanodeResponseProto};
+        use super::tests::{start_namenode, temp_path, try_call_as};
+
+        let path = &temp_path("get-additional-datanode");
+
+        let namenode = start_namenode(path, &["a", "b", "c", "d"]);
+        let client_protocol = &*namenode.client_protocol;
+
+        namenode.create_file("/f", "client", 2);
+        let eb_proto = namenode.add_block("/f", "client");
+
+        let datanode_info = |datanode_id: &str| {
+            let mut di_proto = DatanodeInfoProto::default();
+            di_proto.id.datanode_uuid = datanode_id.to_string();
+            di_proto
+        };
+
+        let get_additional_datanode = |user: &str,
+                client_name: &str, num_additional_nodes: u32| {
+            let mut request = GetAdditionalDatanodeRequestProto::default();
+            request.src = "/f".to_string();
+            request.blk = eb_proto.clone();
+            request.existings = vec!(datanode_info("a"));
+            request.excludes = vec!(datanode_info("b"));
+            request.num_additional_nodes = num_additional_nodes;
+            request.client_name = client_name.to_string();
+            try_call_as(client_protocol, user,
+                "getAdditionalDatanode", request)
+                .map(|resp_buf| GetAdditionalDatanodeResponseProto
+                    ::decode_length_delimited(&resp_buf[..]).unwrap().block)
+        };
+
+        // existing nodes are followed by nodes which are not excluded
+        for _ in 0..16 {
+            let lb_proto = get_additional_datanode("root", "client", 1)
+                .unwrap();
+            assert_eq!(lb_proto.b, eb_proto);
+            assert_eq!(lb_proto.locs.len(), 2);
+            assert_eq!(lb_proto.locs[0].id.datanode_uuid, "a");
+            assert!(lb_proto.locs[1].id.datanode_uuid == "c"
+                || lb_proto.locs[1].id.datanode_uuid == "d");
+        }
+
+        // additional nodes are limited to the available datanodes
+        let lb_proto = get_additional_datanode("root", "client", 3).unwrap();
+        let mut datanode_ids: Vec<&str> = lb_proto.locs.iter()
+            .map(|di_proto| di_proto.id.datanode_uuid.as_str()).collect();
+        datanode_ids[1..].sort();
+        assert_eq!(datanode_ids, vec!("a", "c", "d"));
+
+        // only the lease holder may request datanodes
+        assert!(get_additional_datanode("root", "other", 1).is_err());
+        assert!(get_additional_datanode("alice", "client", 1).is_err());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn rename() {
+        use hdfs_comm::protos::hdfs::RenameRequestProto;
+        use super::tests::{start_namenode, temp_path, try_call_as};
+
+        let path = &temp_path("rename");
+
+        let namenode = start_namenode(path, &[]);
+        {
+            let mut file_store = namenode.file_store.write().unwrap();
+            file_store.mkdirs("/a", 511, "root", "root", false, 0).unwrap();
+            file_store.mkdirs("/a/d", 493, "root", "root", false, 0).unwrap();
+            file_store.create("/a/f", 420, "root", "root",
+                1, 1024, "client", 0).unwrap();
+        }
+
+        let rename = |user: &str, src: &str, dst: &str| {
+            let mut request = RenameRequestProto::default();
+            request.src = src.to_string();
+            request.dst = dst.to_string();
+            try_call_as(&*namenode.client_protocol, user, "rename", request)
+        };
+
+        // moving into an existing directory requires writing to it
+        assert!(rename("alice", "/a/f", "/a/d").is_err());
+        assert!(namenode.file_store.read().unwrap()
+            .get_file("/a/f").is_some());
+
+        assert!(rename("alice", "/a/f", "/a/g").is_ok());
+        assert!(rename("root", "/a/g", "/a/d").is_ok());
+        assert!(namenode.file_store.read().unwrap()
+            .get_file("/a/d/g").is_some());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn select_block_replica() {
+        use super::select_block_replica;
+
+        let datanodes = vec!(("a".to_string(), 0), ("b".to_string(), 10),
+            ("c".to_string(), 20));
+
+        // excluded datanodes are never selected
+        let excluded_ids = vec!("a".to_string(), "c".to_string());
+        for _ in 0..64 {
+            assert_eq!(select_block_replica(&datanodes, &excluded_ids),
+                Some(1));
+        }
+
+        let excluded_ids = vec!("b".to_string());
+        for _ in 0..64 {
+            let index = select_block_replica(&datanodes, &excluded_ids);
+            assert!(index == Some(0) || index == Some(2));
+        }
+
+        // no datanode is selected when all are excluded
+        let excluded_ids = vec!("a".to_string(),
+            "b".to_string(), "c".to_string());
+        assert_eq!(select_block_replica(&datanodes, &excluded_ids), None);
+        assert_eq!(select_block_replica(&Vec::new(), &Vec::new()), None);
+    }
+
+    #[test]
+    fn update_pipeline() {
+        use hdfs_comm::protos::hdfs::{DatanodeIdProto, ExtendedBlockProto, UpdateBlockForPipelineRequestProto, UpdateBlockForPipelineResponseProto, UpdatePipelineRequestProto};
+        use super::tests::{report_block, start_namenode, temp_path, try_call_as};
+        use crate::block::BlockStore;
+        use crate::file::FileStore;
+        use crate::file::journal::Journal;
+
+        let path = &temp_path("update-pipeline");
+
+        let namenode = start_namenode(path, &["a", "b"]);
+        let (client_protocol, datanode_protocol) =
+            (&*namenode.client_protocol, &*namenode.datanode_protocol);
+
+        namenode.create_file("/f", "client", 2);
+        let old_block = namenode.add_block("/f", "client");
+        let block_id = old_block.block_id;
+        report_block(datanode_protocol, "a", block_id, 100);
+        report_block(datanode_protocol, "b", block_id, 100);
+
+        let update_block_for_pipeline = |user: &str, client_name: &str| {
+            let mut request = UpdateBlockForPipelineRequestProto::default();
+            request.block = old_block.clone();
+            request.client_name = client_name.to_string();
+            try_call_as(client_protocol, user,
+                "updateBlockForPipeline", request)
+                .map(|resp_buf| UpdateBlockForPipelineResponseProto
+                    ::decode_length_delimited(&resp_buf[..])
+                    .unwrap().block.b)
+        };
+
+        let update_pipeline = |user: &str, new_block: &ExtendedBlockProto| {
+            let mut di_proto = DatanodeIdProto::default();
+            di_proto.datanode_uuid = "a".to_string();
+
+            let mut request = UpdatePipelineRequestProto::default();
+            request.client_name = "client".to_string();
+            request.old_block = old_block.clone();
+            request.new_block = new_block.clone();
+            request.new_nodes.push(di_proto);
+            try_call_as(client_protocol, user, "updatePipeline", request)
+        };
+
+        // only the lease holder may bump the generation stamp
+        assert!(update_block_for_pipeline("root", "other").is_err());
+        assert!(update_block_for_pipeline("alice", "client").is_err());
+        let mut new_block =
+            update_block_for_pipeline("root", "client").unwrap();
+        assert_eq!(new_block.block_id, block_id);
+        assert!(new_block.generation_stamp > old_block.generation_stamp);
+        new_block.num_bytes = Some(512);
+
+        // only the issued generation stamp may be committed
+        let mut invalid_block = new_block.clone();
+        invalid_block.generation_stamp += 1;
+        assert!(update_pipeline("root", &invalid_block).is_err());
+        assert!(update_pipeline("root", &old_block).is_err());
+
+        // recovered lengths must be set
+        let mut unset_block = new_block.clone();
+        unset_block.num_bytes = None;
+        assert!(update_pipeline("root", &unset_block).is_err());
+
+        // only the lease user may commit the recovered pipeline
+        assert!(update_pipeline("alice", &new_block).is_err());
+
+        // failed datanodes are removed from the recovered block
+        assert!(update_pipeline("root", &new_block).is_ok());
+        {
+            let block_store = namenode.block_store.read().unwrap();
+            let block = block_store.get_block(&block_id).unwrap();
+            assert_eq!(block.generation_stamp, new_block.generation_stamp);
+            assert_eq!(block.length, 512);
+            assert_eq!(block.locations, vec!("a".to_string()));
+            assert_eq!(namenode.file_store.read().unwrap()
+                .get_block_length(&block_id), Some(512));
+        }
+
+        // issued generation stamps are committed once
+        assert!(update_pipeline("root", &new_block).is_err());
+
+        // recovered generation stamps and lengths are journaled
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        Journal::open(path, &mut file_store, &mut block_store, 0).unwrap();
+        let block = block_store.get_block(&block_id).unwrap();
+        assert_eq!(block.generation_stamp, new_block.generation_stamp);
+        assert_eq!(block.length, 512);
+        assert_eq!(file_store.get_block_length(&block_id), Some(512));
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,35 +1,44 @@
 use hdfs_comm::rpc::Protocol;
 use prost::Message;
 use shared::NahFSError;
-use shared::protos::{BlockFilterRequestProto, BlockFilterResponseProto, BlockIndexProto, GetIndexReplicasRequestProto, GetIndexReplicasResponseProto, GetStoragePolicyResponseProto, GetStoragePolicyRequestProto, IndexReportResponseProto, IndexReportRequestProto, IndexViewResponseProto, IndexViewRequestProto, InodePersistResponseProto, InodePersistRequestProto, SpatialIndexProto, TemporalIndexProto};
+use shared::protos::{BlockFilterRequestProto, BlockFilterResponseProto, BlockIndexProto, GetIndexReplicasRequestProto, GetIndexReplicasResponseProto, GetStoragePolicyResponseProto, GetStoragePolicyRequestProto, IndexReportResponseProto, IndexReportRequestProto, IndexViewResponseProto, IndexViewRequestProto, InodePersistResponseProto, InodePersistRequestProto, SpatialIndexProto, StoragePolicyListResponseProto, StoragePolicyListRequestProto, StoragePolicyProto, StoragePolicyUnsetResponseProto, StoragePolicyUnsetRequestProto, TemporalIndexProto, TrashExpungeResponseProto, TrashExpungeRequestProto};
 
-use crate::{BlockStore, DatanodeStore};
+use crate::block::BlockStore;
+use crate::datanode::DatanodeStore;
 use crate::file::FileStore;
+use crate::file::journal::{Journal, Operation};
+use crate::file::permission::{self, User, UserStore};
+use crate::image::Checkpointer;
 use crate::index::Index;
 
-use std::fs::File;
-use std::io::{Write};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 pub struct NahFSProtocol {
     block_store: Arc<RwLock<BlockStore>>,
+    checkpointer: Arc<Checkpointer>,
     datanode_store: Arc<RwLock<DatanodeStore>>,
     file_store: Arc<RwLock<FileStore>>,
     index: Arc<RwLock<Index>>,
-    persist_path: String,
+    journal: Arc<Mutex<Journal>>,
+    user_store: Arc<UserStore>,
 }
 
 impl NahFSProtocol {
     pub fn new(block_store: Arc<RwLock<BlockStore>>,
+            checkpointer: Arc<Checkpointer>,
             datanode_store: Arc<RwLock<DatanodeStore>>,
             file_store: Arc<RwLock<FileStore>>,
-            index: Arc<RwLock<Index>>, persist_path: &str) -> NahFSProtocol {
+            index: Arc<RwLock<Index>>,
+            journal: Arc<Mutex<Journal>>,
+            user_store: Arc<UserStore>) -> NahFSProtocol {
         NahFSProtocol {
             block_store: block_store,
+            checkpointer: checkpointer,
             datanode_store: datanode_store,
             file_store: file_store,
             index: index,
-            persist_path: persist_path.to_string(),
+            journal: journal,
+            user_store: user_store,
         }
     }
 
@@ -80,8 +89,8 @@ impl NahFSProtocol {
 
         // compute spatiotemporal datanode storage usage
         let block_store = self.block_store.read().unwrap();
-        let datanode_store = self.datanode_store.read().unwrap();
         let index = self.index.read().unwrap();
+        let datanode_store = self.datanode_store.read().unwrap();
         let mut datanodes = super::get_spatiotemporal_datanode_usage(
             &block_store, &datanode_store, &index, &geohashes);
 
@@ -104,9 +113,14 @@ impl NahFSProtocol {
         // choose subsequent replicas based on storage usage
         while response.datanode_id_protos.len()
                 < request.replication as usize {
-           let index = super::select_block_replica(&datanodes);    
-           let datanode = datanode_store
-               .get_datanode(&datanodes[index].0).unwrap();
+            let index = match super
+                    ::select_block_replica(&datanodes, &Vec::new()) {
+                Some(index) => index,
+                None => break,
+            };
+
+            let datanode = datanode_store
+                .get_datanode(&datanodes[index].0).unwrap();
 
             // serialize DatanodeIdProto
             let di_proto = super::to_datanode_id_proto(datanode);
@@ -155,6 +169,7 @@ impl NahFSProtocol {
         for i in 0..request.block_ids.len() {
             let block_id = &request.block_ids[i];
             let bi_proto = &request.block_indices[i];
+            index.verify(block_id);
 
             // add geohashes
             if let Some(si_proto) = &bi_proto.spatial_index {
@@ -224,11 +239,92 @@ impl NahFSProtocol {
 
         // process inode persist
         debug!("inodePersist({:?})", request);
-        let file_store: &FileStore = &self.file_store.read().unwrap();
-        let buf: Vec<u8> = bincode::serialize(file_store)?;
+        self.checkpointer.checkpoint()?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn log<T, F>(&self, operation: Operation,
+            mutate: F) -> Result<T, NahFSError>
+            where F: FnOnce() -> Result<T, NahFSError> {
+        let mut journal = self.journal.lock().unwrap();
+        journal.write(&operation, mutate)
+    }
+
+    fn storage_policy_list(&self, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = StoragePolicyListRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let mut response = StoragePolicyListResponseProto::default();
+
+        // list storage policies with referencing paths
+        debug!("storagePolicyList({:?})", request);
+        let file_store = self.file_store.read().unwrap();
+        for (id, storage_policy) in file_store.get_storage_policies() {
+            if request.id.map(|x| x != id).unwrap_or(false) {
+                continue;
+            }
+
+            let mut sp_proto = StoragePolicyProto::default();
+            sp_proto.id = id;
+            sp_proto.storage_policy = storage_policy.to_string();
+            sp_proto.paths = file_store.get_storage_policy_paths(id);
+
+            response.storage_policies.push(sp_proto);
+        }
+
+        if let (Some(id), true) =
+                (request.id, response.storage_policies.is_empty()) {
+            return Err(NahFSError::from(
+                format!("storage policy {} not found", id)));
+        }
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
 
-        let mut file = File::create(&self.persist_path)?;
-        file.write_all(&buf)?;
+    fn storage_policy_unset(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = StoragePolicyUnsetRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = StoragePolicyUnsetResponseProto::default();
+
+        // unset storage policy
+        debug!("storagePolicyUnset({:?})", request);
+        let mut file_store = self.file_store.write().unwrap();
+        file_store.check_permission(&request.path, user,
+            0, permission::WRITE, 0)?;
+        self.log(Operation::UnsetStoragePolicy {
+            path: request.path.clone() },
+            || file_store.unset_storage_policy(&request.path))?;
+
+        response.encode_length_delimited(resp_buf)?;
+        Ok(())
+    }
+
+    fn trash_expunge(&self, user: &User, req_buf: &[u8],
+            resp_buf: &mut Vec<u8>) -> Result<(), NahFSError> {
+        let request = TrashExpungeRequestProto
+            ::decode_length_delimited(req_buf)?;
+        let response = TrashExpungeResponseProto::default();
+
+        // only superusers may expunge the trash of all users
+        debug!("trashExpunge({:?})", request);
+        if !user.is_superuser() {
+            return Err(NahFSError::AccessControlException(format!(
+                "Access denied for user {}. Superuser privilege is \
+                required", user.get_name())));
+        }
+
+        // process trash expunge - removing all trash checkpoints
+        let time = super::get_current_time();
+        let mut file_store = self.file_store.write().unwrap();
+        let block_ids = crate::trash::expunge(&mut file_store,
+            &mut self.journal.lock().unwrap(), 0, time)?;
+
+        super::remove_blocks(&block_ids, &self.block_store,
+            &self.datanode_store, &self.index);
 
         response.encode_length_delimited(resp_buf)?;
         Ok(())
@@ -236,8 +332,13 @@ impl NahFSProtocol {
 }
 
 impl Protocol for NahFSProtocol {
-    fn process(&self, _user: &Option<String>, method: &str,
+    fn process(&self, user: &Option<String>, method: &str,
             req_buf: &[u8], resp_buf: &mut Vec<u8>) -> std::io::Result<()> {
+        let user = self.user_store.get_user(match user {
+            Some(user) => user,
+            None => "default",
+        });
+
         match method {
             "filterBlocks" => self.filter_blocks(req_buf, resp_buf)?,
             "getIndexReplicas" =>
@@ -247,7 +348,15 @@ impl Protocol for NahFSProtocol {
             "indexReport" => self.index_report(req_buf, resp_buf)?,
             "indexView" => self.index_view(req_buf, resp_buf)?,
             "inodePersist" => self.inode_persist(req_buf, resp_buf)?,
-            _ => error!("unimplemented method '{}'", method),
+            "storagePolicyList" =>
+                self.storage_policy_list(req_buf, resp_buf)?,
+            "storagePolicyUnset" =>
+                self.storage_policy_unset(&user, req_buf, resp_buf)?,
+            "trashExpunge" =>
+                self.trash_expunge(&user, req_buf, resp_buf)?,
+            _ => return Err(NahFSError::RpcNoSuchMethodException(format!(
+                "Unknown method {} called on \
+                io.blackpine.nahfs.protocol.NahFSProtocol protocol.", method)).into()),
         }
 
         Ok(())
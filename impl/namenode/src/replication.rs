@@ -0,0 +1,365 @@
+use crate::block::BlockStore;
+use crate::datanode::DatanodeStore;
+use crate::file::FileStore;
+use crate::storage::StorageStore;
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+static DATANODE_TIMEOUT_MS: u64 = 630000;
+static MAX_BLOCKS_PER_INTERVAL: usize = 1000;
+static PENDING_TIMEOUT_MS: u64 = 60000;
+
+// blocks are checked after a grace period when written (ex. replicas of
+//  a pipeline which have not been reported yet)
+pub static NEW_BLOCK_GRACE_MS: u64 = 30000;
+
+pub struct ReplicationQueue {
+    blocks: HashMap<u64, u64>,
+    queue: BTreeSet<(u64, u64)>,
+}
+
+impl ReplicationQueue {
+    pub fn new() -> ReplicationQueue {
+        ReplicationQueue {
+            blocks: HashMap::new(),
+            queue: BTreeSet::new(),
+        }
+    }
+
+    pub fn add(&mut self, block_id: u64, time: u64) {
+        // queued blocks are checked at the earliest requested time
+        if let Some(queued_time) = self.blocks.get(&block_id) {
+            if *queued_time <= time {
+                return;
+            }
+
+            self.queue.remove(&(*queued_time, block_id));
+        }
+
+        self.blocks.insert(block_id, time);
+        self.queue.insert((time, block_id));
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn take(&mut self, time: u64, count: usize) -> Vec<u64> {
+        let mut block_ids = Vec::new();
+        while block_ids.len() < count {
+            let (queued_time, block_id) = match self.queue.iter().next() {
+                Some(entry) if entry.0 <= time => *entry,
+                _ => break,
+            };
+
+            self.queue.remove(&(queued_time, block_id));
+            self.blocks.remove(&block_id);
+            block_ids.push(block_id);
+        }
+
+        block_ids
+    }
+}
+
+pub fn remove_expired_datanodes(block_store: &mut BlockStore,
+        datanode_store: &mut DatanodeStore,
+        replication_queue: &Mutex<ReplicationQueue>, time: u64) -> usize {
+    // datanodes which stopped sending heartbeats are removed
+    //  - they reregister and report their blocks if they return
+    let expired_ids: Vec<String> = datanode_store.get_datanodes().iter()
+        .filter(|datanode| datanode.get_last_seen()
+            + DATANODE_TIMEOUT_MS < time)
+        .map(|datanode| datanode.id.clone()).collect();
+
+    for datanode_id in expired_ids.iter() {
+        datanode_store.remove(datanode_id);
+        let block_ids = block_store
+            .remove_unreported(datanode_id, &Default::default());
+
+        let mut replication_queue = replication_queue.lock().unwrap();
+        for block_id in block_ids {
+            replication_queue.add(block_id, time);
+        }
+    }
+
+    expired_ids.len()
+}
+
+pub fn schedule(block_replications: &HashMap<u64, u32>,
+        block_store: &mut BlockStore, datanode_store: &mut DatanodeStore,
+        storage_store: &StorageStore, pending: &mut HashMap<u64, u64>,
+        replication_queue: &Mutex<ReplicationQueue>,
+        time: u64) -> (usize, usize) {
+    // choose targets based on storage usage
+    let datanode_usage =
+        crate::protocol::get_datanode_usage(datanode_store, storage_store);
+
+    let (mut transfer_count, mut invalid_count) = (0, 0);
+    let mut retry_ids = Vec::new();
+    for (block_id, replication) in block_replications.iter() {
+        let locations = match block_store.get_block(block_id) {
+            Some(block) => block.locations.clone(),
+            None => continue, // block has not been reported
+        };
+
+        let replication = *replication as usize;
+        if locations.len() > replication {
+            // remove excess replicas from the last reported locations
+            for datanode_id in locations[replication..].iter() {
+                block_store.remove_location(block_id, datanode_id);
+                datanode_store.add_invalid_block(datanode_id, *block_id);
+                invalid_count += 1;
+            }
+
+            pending.remove(block_id);
+        } else if locations.len() < replication {
+            // wait for outstanding transfers to be reported
+            if let Some(pending_time) = pending.get(block_id) {
+                if pending_time + PENDING_TIMEOUT_MS > time {
+                    retry_ids.push((*block_id,
+                        pending_time + PENDING_TIMEOUT_MS));
+                    continue;
+                }
+            }
+
+            // find a registered datanode to copy the block from
+            let source_id = match locations.iter().find(|datanode_id|
+                    datanode_store.get_datanode(datanode_id).is_some()) {
+                Some(source_id) => source_id,
+                None => {
+                    retry_ids.push((*block_id, time + PENDING_TIMEOUT_MS));
+                    continue;
+                },
+            };
+
+            let mut datanodes = datanode_usage.clone();
+            let mut target_ids = Vec::new();
+            while target_ids.len() < replication - locations.len() {
+                match crate::protocol
+                        ::select_block_replica(&datanodes, &locations) {
+                    Some(index) => target_ids.push(datanodes.remove(index).0),
+                    None => break,
+                }
+            }
+
+            // retry once the transfer is reported or times out
+            retry_ids.push((*block_id, time + PENDING_TIMEOUT_MS));
+            if target_ids.len() == 0 {
+                continue;
+            }
+
+            debug!("scheduling transfer of block {} from {} to {:?}",
+                block_id, source_id, target_ids);
+            datanode_store.add_transfer_block(source_id,
+                *block_id, target_ids);
+            pending.insert(*block_id, time);
+            transfer_count += 1;
+        } else {
+            pending.remove(block_id);
+        }
+    }
+
+    let mut replication_queue = replication_queue.lock().unwrap();
+    for (block_id, time) in retry_ids {
+        replication_queue.add(block_id, time);
+    }
+
+    (transfer_count, invalid_count)
+}
+
+pub fn start_monitor(block_store: Arc<RwLock<BlockStore>>,
+        datanode_store: Arc<RwLock<DatanodeStore>>,
+        file_store: Arc<RwLock<FileStore>>,
+        storage_store: Arc<RwLock<StorageStore>>,
+        replication_queue: Arc<Mutex<ReplicationQueue>>,
+        interval_ms: u64, shutdown: Arc<AtomicBool>) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut pending = HashMap::new();
+        while !shutdown.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(interval_ms));
+
+            // queue blocks of datanodes which stopped heartbeating
+            let time = crate::protocol::get_current_time();
+            let expired = {
+                let datanode_store = datanode_store.read().unwrap();
+                datanode_store.get_datanodes().iter()
+                    .any(|datanode| datanode.get_last_seen()
+                        + DATANODE_TIMEOUT_MS < time)
+            };
+
+            if expired {
+                let mut block_store = block_store.write().unwrap();
+                let mut datanode_store = datanode_store.write().unwrap();
+                let count = remove_expired_datanodes(&mut block_store,
+                    &mut datanode_store, &replication_queue, time);
+                info!("removed {} expired datanode(s)", count);
+            }
+
+            // compare locations of queued blocks with file replication
+            //  - blocks which are no longer managed are dropped
+            let block_ids = replication_queue.lock().unwrap()
+                .take(time, MAX_BLOCKS_PER_INTERVAL);
+            if block_ids.len() == 0 {
+                continue;
+            }
+
+            let mut block_replications = HashMap::new();
+            {
+                let file_store = file_store.read().unwrap();
+                for block_id in block_ids {
+                    match file_store.get_block_replication(&block_id) {
+                        Some(replication) => {
+                            block_replications.insert(block_id, replication);
+                        },
+                        None => {
+                            pending.remove(&block_id);
+                        },
+                    }
+                }
+            }
+
+            let mut block_store = block_store.write().unwrap();
+            let mut datanode_store = datanode_store.write().unwrap();
+            let storage_store = storage_store.read().unwrap();
+            let (transfer_count, invalid_count) =
+                schedule(&block_replications, &mut block_store,
+                    &mut datanode_store, &storage_store, &mut pending,
+                    &replication_queue, time);
+
+            if transfer_count != 0 || invalid_count != 0 {
+                info!("scheduled {} block transfer(s) and {} excess \
+                    replica deletion(s)", transfer_count, invalid_count);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn remove_expired_datanodes() {
+        use super::{remove_expired_datanodes, ReplicationQueue};
+        use crate::block::BlockStore;
+        use crate::datanode::DatanodeStore;
+
+        use std::sync::Mutex;
+
+        let mut block_store = BlockStore::new();
+        let mut datanode_store = DatanodeStore::new(10);
+        for id in vec!("a", "b", "c") {
+            datanode_store.register(id.to_string(),
+                "127.0.0.1".to_string(), 8020, 0);
+        }
+
+        datanode_store.register("d".to_string(),
+            "127.0.0.1".to_string(), 8020, 700000);
+        datanode_store.update("a", None, None, 0, None, None);
+        datanode_store.update("b", None, None, 700000, None, None);
+        block_store.update(1, 0, 64, "a", "sa");
+        block_store.update(1, 0, 64, "b", "sb");
+
+        // blocks of datanodes without recent heartbeats are queued
+        let replication_queue = Mutex::new(ReplicationQueue::new());
+        assert_eq!(remove_expired_datanodes(&mut block_store,
+            &mut datanode_store, &replication_queue, 700000), 2);
+        assert!(datanode_store.get_datanode("a").is_none());
+
+        // datanodes which never heartbeat expire after registration
+        assert!(datanode_store.get_datanode("c").is_none());
+        assert!(datanode_store.get_datanode("d").is_some());
+        assert_eq!(block_store.get_block(&1).unwrap().locations,
+            vec!("b".to_string()));
+        assert_eq!(replication_queue.lock().unwrap()
+            .take(700000, 10), vec!(1));
+    }
+
+    #[test]
+    fn replication_queue() {
+        use super::ReplicationQueue;
+
+        // blocks are taken in order once their check time has passed
+        let mut replication_queue = ReplicationQueue::new();
+        replication_queue.add(1, 10);
+        replication_queue.add(2, 0);
+        replication_queue.add(3, 5);
+        replication_queue.add(1, 20);
+        assert_eq!(replication_queue.len(), 3);
+        assert_eq!(replication_queue.take(5, 10), vec!(2, 3));
+
+        // earlier check times replace later ones
+        replication_queue.add(4, 20);
+        replication_queue.add(4, 15);
+        assert_eq!(replication_queue.take(15, 1), vec!(1));
+        assert_eq!(replication_queue.take(15, 1), vec!(4));
+        assert_eq!(replication_queue.len(), 0);
+    }
+
+    #[test]
+    fn schedule() {
+        use super::{schedule, ReplicationQueue};
+        use crate::block::BlockStore;
+        use crate::datanode::DatanodeStore;
+        use crate::storage::StorageStore;
+
+        use std::collections::HashMap;
+        use std::sync::Mutex;
+
+        let mut block_store = BlockStore::new();
+        let mut datanode_store = DatanodeStore::new(10);
+        let storage_store = StorageStore::new(10);
+        for id in vec!("a", "b", "c") {
+            datanode_store.register(id.to_string(),
+                "127.0.0.1".to_string(), 8020, 0);
+        }
+
+        block_store.update(1, 0, 64, "a", "sa");
+        block_store.update(2, 0, 64, "a", "sa");
+        block_store.update(2, 0, 64, "b", "sb");
+        block_store.update(2, 0, 64, "c", "sc");
+
+        let mut block_replications = HashMap::new();
+        block_replications.insert(1, 3);
+        block_replications.insert(2, 1);
+        block_replications.insert(3, 2);
+
+        // under-replicated blocks are copied from an existing replica
+        //  and excess replicas are invalidated
+        let mut pending = HashMap::new();
+        let replication_queue = Mutex::new(ReplicationQueue::new());
+        assert_eq!(schedule(&block_replications, &mut block_store,
+            &mut datanode_store, &storage_store, &mut pending,
+            &replication_queue, 0), (1, 2));
+
+        let mut transfer_blocks = datanode_store.take_transfer_blocks("a");
+        assert_eq!(transfer_blocks.len(), 1);
+        let (block_id, mut target_ids) = transfer_blocks.remove(0);
+        target_ids.sort();
+        assert_eq!((block_id, target_ids),
+            (1, vec!("b".to_string(), "c".to_string())));
+
+        assert_eq!(block_store.get_block(&2).unwrap().locations,
+            vec!("a".to_string()));
+        assert_eq!(datanode_store.take_invalid_blocks("b"), vec!(2));
+        assert_eq!(datanode_store.take_invalid_blocks("c"), vec!(2));
+
+        // scheduled transfers are requeued until they time out
+        assert_eq!(replication_queue.lock().unwrap()
+            .take(60000, 10), vec!(1));
+
+        // pending transfers are not rescheduled until reported or expired
+        assert_eq!(schedule(&block_replications, &mut block_store,
+            &mut datanode_store, &storage_store, &mut pending,
+            &replication_queue, 1), (0, 0));
+
+        block_store.update(1, 0, 64, "b", "sb");
+        block_store.update(1, 0, 64, "c", "sc");
+        assert_eq!(schedule(&block_replications, &mut block_store,
+            &mut datanode_store, &storage_store, &mut pending,
+            &replication_queue, 2), (0, 0));
+        assert!(pending.is_empty());
+    }
+}
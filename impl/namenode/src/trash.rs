@@ -0,0 +1,349 @@
+use crate::block::BlockStore;
+use crate::datanode::DatanodeStore;
+use crate::file::{FileStore, FileType};
+use crate::file::journal::{Journal, Operation};
+use crate::file::permission::{User, UserStore};
+use crate::index::Index;
+
+use shared::NahFSError;
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+static CURRENT_DIRECTORY: &str = "Current";
+static HOME_PERMISSIONS: u32 = 493; // 0755
+static SNAPSHOT_DIRECTORY: &str = ".snapshot";
+static TRASH_DIRECTORY: &str = ".Trash";
+static TRASH_PERMISSIONS: u32 = 448; // 0700
+
+pub fn expunge(file_store: &mut FileStore, journal: &mut Journal,
+        interval_ms: u64, time: u64) -> Result<Vec<u64>, NahFSError> {
+    // find trash directories of all users
+    let mut trash_paths = Vec::new();
+    if let Some(file) = file_store.get_file("/user") {
+        for user_file in file_store.get_children(file.get_inode())
+                .unwrap_or(Vec::new()) {
+            let trash_path = format!("{}/{}",
+                file_store.compute_path(user_file.get_inode()),
+                TRASH_DIRECTORY);
+            if file_store.get_file(&trash_path).is_some() {
+                trash_paths.push(trash_path);
+            }
+        }
+    }
+
+    let mut block_ids = Vec::new();
+    for trash_path in trash_paths {
+        // checkpoint current trash
+        let current_path = format!("{}/{}", trash_path, CURRENT_DIRECTORY);
+        if file_store.get_file(&current_path).is_some() {
+            let checkpoint_path = format!("{}/{}", trash_path, time);
+            journal.write(&Operation::Rename {
+                src_path: current_path.clone(),
+                dst_path: checkpoint_path.clone(), time: time },
+                || file_store.rename(&current_path, &checkpoint_path, time))?;
+        }
+
+        // find checkpoints older than the interval
+        let inode = file_store.get_file(&trash_path).unwrap().get_inode();
+        let expired_paths: Vec<String> = file_store.get_children(inode)
+            .unwrap_or(Vec::new()).iter()
+            .filter(|file| match file.get_name().parse::<u64>() {
+                Ok(checkpoint_time) => checkpoint_time + interval_ms <= time,
+                Err(_) => false,
+            })
+            .map(|file| file_store.compute_path(file.get_inode()))
+            .collect();
+
+        // delete expired checkpoints
+        for path in expired_paths {
+            // snapshots would retain the checkpoint indefinitely
+            match delete_snapshots(file_store, journal, &path) {
+                Ok(snapshot_block_ids) => block_ids.extend(snapshot_block_ids),
+                Err(e) => {
+                    warn!("failed to delete snapshots of trash \
+                        checkpoint '{}': {}", path, e);
+                    continue;
+                },
+            }
+
+            match journal.write(&Operation::Delete { path: path.clone(),
+                    recursive: true, time: time },
+                    || file_store.delete(&path, true, time)) {
+                Ok(checkpoint_block_ids) => {
+                    block_ids.extend(checkpoint_block_ids);
+                    info!("expunged trash checkpoint '{}'", path);
+                },
+                Err(e) => warn!("failed to expunge trash checkpoint \
+                    '{}': {}", path, e),
+            }
+        }
+    }
+
+    Ok(block_ids)
+}
+
+fn delete_snapshots(file_store: &mut FileStore,
+        journal: &mut Journal, path: &str) -> Result<Vec<u64>, NahFSError> {
+    // find snapshots of all directories within the subtree
+    let mut snapshots = Vec::new();
+    let mut paths = vec!(path.to_string());
+    while let Some(path) = paths.pop() {
+        let inode = match file_store.get_file(&path) {
+            Some(file) => file.get_inode(),
+            None => continue,
+        };
+
+        for file in file_store.get_children(inode).unwrap_or(Vec::new()) {
+            if let FileType::Directory = file.get_file_type() {
+                paths.push(format!("{}/{}", path, file.get_name()));
+            }
+        }
+
+        let snapshot_path = format!("{}/{}", path, SNAPSHOT_DIRECTORY);
+        if let Some(snapshot_file) = file_store.get_file(&snapshot_path) {
+            for file in file_store.get_children(snapshot_file.get_inode())
+                    .unwrap_or(Vec::new()) {
+                snapshots.push((path.clone(), file.get_name().to_string()));
+            }
+        }
+    }
+
+    // delete snapshots
+    let mut block_ids = Vec::new();
+    for (path, name) in snapshots {
+        block_ids.extend(journal.write(&Operation::DeleteSnapshot {
+            path: path.clone(), name: name.clone() },
+            || file_store.delete_snapshot(&path, &name))?);
+        info!("deleted snapshot '{}' of trash checkpoint '{}'", name, path);
+    }
+
+    Ok(block_ids)
+}
+
+pub fn move_to_trash(file_store: &mut FileStore, journal: &mut Journal,
+        user_store: &UserStore, user: &User, path: &str, recursive: bool,
+        time: u64) -> Result<bool, NahFSError> {
+    // check if file may be deleted
+    let file = file_store.get_file(path).ok_or_else(||
+        NahFSError::FileNotFoundException(
+            format!("File does not exist: {}", path)))?;
+    if !recursive && file_store.get_children_count(file.get_inode()) != 0 {
+        return Err(NahFSError::PathIsNotEmptyDirectoryException(
+            format!("`{} is non empty': Directory is not empty", path)));
+    }
+
+    // files within the trash are deleted permanently
+    let components = parse_path(path);
+    let trash_path = format!("/user/{}/{}", user.get_name(), TRASH_DIRECTORY);
+    let trash_components = parse_path(&trash_path);
+    if components.len() == 0 {
+        return Ok(false);
+    } else if components.starts_with(&trash_components) {
+        return Ok(false);
+    } else if trash_components.starts_with(&components) {
+        // only superusers may permanently delete a trash
+        return match user.is_superuser() {
+            true => Ok(false),
+            false => Err(NahFSError::from(format!(
+                "Cannot move \"{}\" to the trash, as it contains \
+                the trash", path))),
+        };
+    }
+
+    // rename files which conflict with the destination ancestors
+    let mut dst_components: Vec<String> = components.iter()
+        .map(|x| x.to_string()).collect();
+    for i in 0..dst_components.len() - 1 {
+        let ancestor_path = format!("{}/{}/{}", trash_path,
+            CURRENT_DIRECTORY, dst_components[..i + 1].join("/"));
+        match file_store.get_file(&ancestor_path)
+                .map(|file| file.get_file_type()) {
+            Some(FileType::Regular {blocks: _, replication: _,
+                    block_size: _}) => {
+                dst_components[i] = format!("{}{}", dst_components[i], time);
+                break;
+            },
+            Some(FileType::Directory) => continue,
+            None => break,
+        }
+    }
+
+    // create missing home directory ancestors as the superuser
+    let home_path = format!("/user/{}", user.get_name());
+    if file_store.get_file(&home_path).is_none() {
+        journal.write(&Operation::Mkdirs { path: home_path.clone(),
+            permissions: HOME_PERMISSIONS,
+            owner: user_store.get_superuser().to_string(),
+            group: user_store.get_supergroup().to_string(),
+            create_parent: true, time: time },
+            || file_store.mkdirs(&home_path, HOME_PERMISSIONS,
+                user_store.get_superuser(), user_store.get_supergroup(),
+                true, time))?;
+    }
+
+    // create destination parent directory
+    let name = dst_components.pop().unwrap();
+    let parent_path = format!("{}/{}/{}", trash_path,
+        CURRENT_DIRECTORY, dst_components.join("/"));
+    let parent_path = parent_path.trim_end_matches("/");
+    journal.write(&Operation::Mkdirs { path: parent_path.to_string(),
+        permissions: TRASH_PERMISSIONS, owner: user.get_name().to_string(),
+        group: user.get_name().to_string(), create_parent: true,
+        time: time }, || file_store.mkdirs(parent_path, TRASH_PERMISSIONS,
+            user.get_name(), user.get_name(), true, time))?;
+
+    // move file into trash - suffixing conflicting names with the time
+    let mut dst_path = format!("{}/{}", parent_path, name);
+    if file_store.get_file(&dst_path).is_some() {
+        dst_path = format!("{}{}", dst_path, time);
+    }
+
+    journal.write(&Operation::Rename { src_path: path.to_string(),
+        dst_path: dst_path.clone(), time: time },
+        || file_store.rename(path, &dst_path, time))?;
+
+    info!("moved '{}' to trash at '{}'", path, dst_path);
+    Ok(true)
+}
+
+fn parse_path(path: &str) -> Vec<&str> {
+    path.split("/").filter(|x| !x.is_empty()).collect()
+}
+
+pub fn start_monitor(block_store: Arc<RwLock<BlockStore>>,
+        datanode_store: Arc<RwLock<DatanodeStore>>,
+        file_store: Arc<RwLock<FileStore>>, index: Arc<RwLock<Index>>,
+        journal: Arc<Mutex<Journal>>, checkpoint_interval_ms: u64,
+        interval_ms: u64, shutdown: Arc<AtomicBool>) -> JoinHandle<()> {
+    std::thread::spawn(move || while !shutdown.load(Ordering::SeqCst) {
+        std::thread::sleep(Duration::from_millis(checkpoint_interval_ms));
+
+        // checkpoint trash and expunge expired checkpoints
+        let time = crate::protocol::get_current_time();
+        let mut file_store = file_store.write().unwrap();
+        let result = expunge(&mut file_store,
+            &mut journal.lock().unwrap(), interval_ms, time);
+
+        match result {
+            Ok(block_ids) => crate::protocol::remove_blocks(&block_ids,
+                &block_store, &datanode_store, &index),
+            Err(e) => error!("failed to expunge trash: {}", e),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn trash() {
+        use super::{expunge, move_to_trash};
+        use crate::block::BlockStore;
+        use crate::file::FileStore;
+        use crate::file::journal::{Journal, Operation};
+        use crate::file::permission::{User, UserStore};
+
+        let path = std::env::temp_dir().join(format!(
+            "nahfs-trash-{}", rand::random::<u64>()));
+        let path = path.to_str().unwrap();
+
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        let mut journal = Journal::open(path,
+            &mut file_store, &mut block_store, 0).unwrap();
+        let user_store = UserStore::new("root", "supergroup");
+        let bob = User::new("bob".to_string(), Vec::new(), false);
+        file_store.mkdirs("/a/b", 493, "bob", "bob", true, 0).unwrap();
+        journal.append(&Operation::Mkdirs { path: "/a/b".to_string(),
+            permissions: 493, owner: "bob".to_string(),
+            group: "bob".to_string(), create_parent: true,
+            time: 0 }).unwrap();
+
+        // deleted files are moved to the users current trash
+        assert!(move_to_trash(&mut file_store, &mut journal, &user_store,
+            &bob, "/a", false, 1).is_err());
+        assert!(move_to_trash(&mut file_store, &mut journal, &user_store,
+            &bob, "/a/b", true, 1).unwrap());
+        assert!(file_store.get_file("/a/b").is_none());
+        assert!(file_store
+            .get_file("/user/bob/.Trash/Current/a/b").is_some());
+
+        // home directories are owned by the superuser
+        for (path, owner, permissions) in vec!(("/user", "root", 493),
+                ("/user/bob", "root", 493), ("/user/bob/.Trash", "bob", 448),
+                ("/user/bob/.Trash/Current/a", "bob", 448)) {
+            let file = file_store.get_file(path).unwrap();
+            assert_eq!(file.get_owner(), owner);
+            assert_eq!(file.get_permissions(), permissions);
+        }
+
+        // conflicting names are suffixed with the time
+        file_store.mkdirs("/a/b", 493, "bob", "bob", true, 2).unwrap();
+        journal.append(&Operation::Mkdirs { path: "/a/b".to_string(),
+            permissions: 493, owner: "bob".to_string(),
+            group: "bob".to_string(), create_parent: true,
+            time: 2 }).unwrap();
+        assert!(move_to_trash(&mut file_store, &mut journal, &user_store,
+            &bob, "/a/b", true, 2).unwrap());
+        assert!(file_store
+            .get_file("/user/bob/.Trash/Current/a/b2").is_some());
+
+        // files within the trash are deleted permanently
+        assert!(!move_to_trash(&mut file_store, &mut journal, &user_store,
+            &bob, "/user/bob/.Trash/Current/a/b2", true, 3).unwrap());
+        assert!(move_to_trash(&mut file_store, &mut journal, &user_store,
+            &bob, "/user", true, 3).is_err());
+
+        // current trash is checkpointed and expunged after the interval
+        assert_eq!(expunge(&mut file_store, &mut journal,
+            10, 4).unwrap().len(), 0);
+        assert!(file_store.get_file("/user/bob/.Trash/Current").is_none());
+        assert!(file_store.get_file("/user/bob/.Trash/4/a/b").is_some());
+
+        // snapshots within expired checkpoints are deleted
+        let snapshot_path = "/user/bob/.Trash/4/a/b";
+        file_store.create(&format!("{}/c", snapshot_path), 420, "bob",
+            "bob", 3, 65536, "client", 5).unwrap();
+        file_store.add_block(&format!("{}/c", snapshot_path),
+            1, 0, 5).unwrap();
+        file_store.complete(&format!("{}/c", snapshot_path), 5).unwrap();
+        file_store.allow_snapshot(snapshot_path).unwrap();
+        file_store.create_snapshot(snapshot_path, "s1", 5).unwrap();
+        for operation in vec!(
+                Operation::Create { path: format!("{}/c", snapshot_path),
+                    permissions: 420, owner: "bob".to_string(),
+                    group: "bob".to_string(), replication: 3,
+                    block_size: 65536, client_name: "client".to_string(),
+                    time: 5 },
+                Operation::AddBlock { path: format!("{}/c", snapshot_path),
+                    block_id: 1, generation_stamp: 0, time: 5 },
+                Operation::Complete { path: format!("{}/c", snapshot_path),
+                    time: 5 },
+                Operation::AllowSnapshot { path: snapshot_path.to_string() },
+                Operation::CreateSnapshot {
+                    path: snapshot_path.to_string(),
+                    name: "s1".to_string(), time: 5 }) {
+            journal.append(&operation).unwrap();
+        }
+
+        assert_eq!(expunge(&mut file_store, &mut journal,
+            10, 14).unwrap(), vec!(1));
+        assert!(file_store.get_file("/user/bob/.Trash/4").is_none());
+        assert_eq!(file_store.get_block_ids().len(), 0);
+
+        // journaled operations replay to the same namespace
+        let (mut file_store, mut block_store) =
+            (FileStore::new(), BlockStore::new());
+        let journal = Journal::open(path,
+            &mut file_store, &mut block_store, 0).unwrap();
+        assert!(journal.get_count() != 0);
+        assert_eq!(file_store.get_file("/user/bob").unwrap()
+            .get_owner(), "root");
+        assert!(file_store.get_file("/user/bob/.Trash/4").is_none());
+        assert_eq!(file_store.get_block_ids().len(), 0);
+
+        let _ = std::fs::remove_file(path);
+    }
+}
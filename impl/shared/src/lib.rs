@@ -7,6 +7,7 @@ use std::error::Error;
 use std::num::{ParseFloatError, ParseIntError};
 
 pub mod block;
+pub mod policy;
 pub mod protos {
     include!(concat!(env!("OUT_DIR"), "/nahfs.rs"));
 }
@@ -23,41 +24,100 @@ pub fn geohash_char_to_value(c: char) -> Result<u8, NahFSError> {
 
 impl From<NahFSError> for std::io::Error {
     fn from(err: NahFSError) -> std::io::Error {
-        std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+        // remote exceptions are relayed as '<java class>: <message>'
+        let message = match err {
+            NahFSError::AccessControlException(ref err) => format!(
+                "org.apache.hadoop.security.AccessControlException: {}",
+                err),
+            NahFSError::AlreadyBeingCreatedException(ref err) => format!(
+                "org.apache.hadoop.hdfs.protocol.AlreadyBeingCreatedException: {}",
+                err),
+            NahFSError::FileAlreadyExistsException(ref err) => format!(
+                "org.apache.hadoop.fs.FileAlreadyExistsException: {}", err),
+            NahFSError::FileNotFoundException(ref err) => format!(
+                "java.io.FileNotFoundException: {}", err),
+            NahFSError::InvalidPathException(ref err) => format!(
+                "org.apache.hadoop.fs.InvalidPathException: {}", err),
+            NahFSError::LeaseExpiredException(ref err) => format!(
+                "org.apache.hadoop.hdfs.server.namenode.LeaseExpiredException: {}",
+                err),
+            NahFSError::ParentNotDirectoryException(ref err) => format!(
+                "org.apache.hadoop.fs.ParentNotDirectoryException: {}", err),
+            NahFSError::PathIsNotEmptyDirectoryException(ref err) => format!(
+                "org.apache.hadoop.fs.PathIsNotEmptyDirectoryException: {}",
+                err),
+            NahFSError::QuotaExceededException(ref err) => format!(
+                "org.apache.hadoop.hdfs.protocol.QuotaExceededException: {}",
+                err),
+            NahFSError::RpcNoSuchMethodException(ref err) => format!(
+                "org.apache.hadoop.ipc.RpcNoSuchMethodException: {}", err),
+            NahFSError::SnapshotAccessControlException(ref err) => format!(
+                "org.apache.hadoop.hdfs.protocol.SnapshotAccessControlException: {}",
+                err),
+            NahFSError::SnapshotException(ref err) => format!(
+                "org.apache.hadoop.hdfs.protocol.SnapshotException: {}", err),
+            _ => err.to_string(),
+        };
+
+        std::io::Error::new(std::io::ErrorKind::Other, message)
     }
 }
 
 #[derive(Debug)]
 pub enum NahFSError {
+    AccessControlException(String),
+    AlreadyBeingCreatedException(String),
     BincodeError(Box<bincode::ErrorKind>),
     BoxError(Box<dyn Error>),
     DecodeError(DecodeError),
     EncodeError(EncodeError),
+    FileAlreadyExistsException(String),
+    FileNotFoundException(String),
     GlobError(GlobError),
+    InvalidPathException(String),
     IoError(std::io::Error),
+    LeaseExpiredException(String),
     Nah(String),
+    ParentNotDirectoryException(String),
     ParseFloatError(ParseFloatError),
     ParseIntError(ParseIntError),
+    PathIsNotEmptyDirectoryException(String),
     PatternError(PatternError),
+    QuotaExceededException(String),
     RadixError(RadixError),
     RegexError(regex::Error),
+    RpcNoSuchMethodException(String),
+    SnapshotAccessControlException(String),
+    SnapshotException(String),
 }
 
 impl Display for NahFSError {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         match *self {
+            NahFSError::AccessControlException(ref err) => write!(f, "AccessControlException: {}", err),
+            NahFSError::AlreadyBeingCreatedException(ref err) => write!(f, "AlreadyBeingCreatedException: {}", err),
             NahFSError::BincodeError(ref err) => write!(f, "BincodeError: {:?}", err),
             NahFSError::BoxError(ref err) => write!(f, "BoxError: {:?}", err),
             NahFSError::DecodeError(ref err) => write!(f, "DecodeError: {:?}", err),
             NahFSError::EncodeError(ref err) => write!(f, "EncodeError: {:?}", err),
+            NahFSError::FileAlreadyExistsException(ref err) => write!(f, "FileAlreadyExistsException: {}", err),
+            NahFSError::FileNotFoundException(ref err) => write!(f, "FileNotFoundException: {}", err),
             NahFSError::GlobError(ref err) => write!(f, "GlobError: {:?}", err),
+            NahFSError::InvalidPathException(ref err) => write!(f, "InvalidPathException: {}", err),
             NahFSError::IoError(ref err) => write!(f, "IoError: {:?}", err),
+            NahFSError::LeaseExpiredException(ref err) => write!(f, "LeaseExpiredException: {}", err),
             NahFSError::Nah(ref err) => write!(f, "NahFSError: {}", err),
+            NahFSError::ParentNotDirectoryException(ref err) => write!(f, "ParentNotDirectoryException: {}", err),
             NahFSError::ParseFloatError(ref err) => write!(f, "ParseFloatError: {}", err),
             NahFSError::ParseIntError(ref err) => write!(f, "ParseIntError: {}", err),
+            NahFSError::PathIsNotEmptyDirectoryException(ref err) => write!(f, "PathIsNotEmptyDirectoryException: {}", err),
             NahFSError::PatternError(ref err) => write!(f, "PatternError: {}", err),
+            NahFSError::QuotaExceededException(ref err) => write!(f, "QuotaExceededException: {}", err),
             NahFSError::RadixError(ref err) => write!(f, "RaddixError: {}", err),
             NahFSError::RegexError(ref err) => write!(f, "RegexError: {}", err),
+            NahFSError::RpcNoSuchMethodException(ref err) => write!(f, "RpcNoSuchMethodException: {}", err),
+            NahFSError::SnapshotAccessControlException(ref err) => write!(f, "SnapshotAccessControlException: {}", err),
+            NahFSError::SnapshotException(ref err) => write!(f, "SnapshotException: {}", err),
         }
     }
 }
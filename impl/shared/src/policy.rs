@@ -0,0 +1,118 @@
+use regex::Regex;
+
+use crate::NahFSError;
+
+#[derive(Debug, PartialEq)]
+pub enum StoragePolicy {
+    CsvPoint {latitude_index: usize, longitude_index: usize,
+        timestamp_index: usize},
+    Wkt {spatial_index: usize},
+}
+
+impl StoragePolicy {
+    pub fn parse(string: &str) -> Result<StoragePolicy, NahFSError> {
+        // compile regexes
+        let regex = Regex::new(r"^(\w+)\((\w+:\w+(,\s*\w+:\w+)*)?\)$")?;
+        let fields_regex = Regex::new(r"(\w+):(\w+)")?;
+
+        // check for match
+        let caps = match regex.captures(string.trim()) {
+            Some(caps) => caps,
+            None => return Err(NahFSError::from(format!(
+                "unable to parse storage policy '{}'", string))),
+        };
+
+        // parse fields
+        let mut fields: Vec<(String, String)> = Vec::new();
+        if let Some(fields_match) = caps.get(2) {
+            for field in fields_regex.captures_iter(fields_match.as_str()) {
+                if fields.iter().any(|(name, _)| name == &field[1]) {
+                    return Err(NahFSError::from(format!(
+                        "duplicate field '{}' in storage policy '{}'",
+                        &field[1], string)));
+                }
+
+                fields.push((field[1].to_string(), field[2].to_string()));
+            }
+        }
+
+        // validate field names for the indexer type
+        let field_names: &[&str] = match &caps[1] {
+            "CsvPoint" => &["latitude_index",
+                "longitude_index", "timestamp_index"],
+            "Wkt" => &["spatial_index"],
+            indexer_type => return Err(NahFSError::from(format!(
+                "unsupported indexer type '{}' in storage policy '{}'",
+                indexer_type, string))),
+        };
+
+        for (name, _) in fields.iter() {
+            if !field_names.contains(&name.as_str()) {
+                return Err(NahFSError::from(format!(
+                    "unknown field '{}' for {} in storage policy '{}'",
+                    name, &caps[1], string)));
+            }
+        }
+
+        // parse field values
+        let storage_policy = match &caps[1] {
+            "CsvPoint" => StoragePolicy::CsvPoint {
+                latitude_index:
+                    get_index("latitude_index", &fields, string)?,
+                longitude_index:
+                    get_index("longitude_index", &fields, string)?,
+                timestamp_index:
+                    get_index("timestamp_index", &fields, string)?,
+            },
+            _ => StoragePolicy::Wkt {
+                spatial_index: get_index("spatial_index", &fields, string)?,
+            },
+        };
+
+        Ok(storage_policy)
+    }
+}
+
+fn get_index(field: &str, fields: &Vec<(String, String)>,
+        string: &str) -> Result<usize, NahFSError> {
+    let value = match fields.iter().find(|(name, _)| name == field) {
+        Some((_, value)) => value,
+        None => return Err(NahFSError::from(format!(
+            "field '{}' not found in storage policy '{}'", field, string))),
+    };
+
+    value.parse::<usize>().map_err(|_| NahFSError::from(format!(
+        "invalid value '{}' for field '{}' in storage policy '{}'",
+        value, field, string)))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse() {
+        use super::StoragePolicy;
+
+        assert_eq!(StoragePolicy::parse("CsvPoint(latitude_index:1, \
+            longitude_index:2, timestamp_index:0)").unwrap(),
+            StoragePolicy::CsvPoint { latitude_index: 1,
+                longitude_index: 2, timestamp_index: 0 });
+        assert_eq!(StoragePolicy::parse("Wkt(spatial_index:3)").unwrap(),
+            StoragePolicy::Wkt { spatial_index: 3 });
+
+        // malformed policies name the offending field
+        let error = StoragePolicy::parse("CsvPoint(timestamp_idx:3, \
+            latitude_index:1, longitude_index:2)").unwrap_err();
+        assert!(error.to_string().contains("'timestamp_idx'"));
+        let error = StoragePolicy::parse("Wkt(spatial_index:a)")
+            .unwrap_err();
+        assert!(error.to_string().contains("'spatial_index'"));
+        let error = StoragePolicy::parse("CsvPoint(latitude_index:1)")
+            .unwrap_err();
+        assert!(error.to_string().contains("'longitude_index'"));
+
+        assert!(StoragePolicy::parse("Wkt(spatial_index:3").is_err());
+        assert!(StoragePolicy::parse("Geojson()").is_err());
+        assert!(StoragePolicy::parse("Wkt(spatial_index:3, \
+            spatial_index:4)").is_err());
+    }
+}